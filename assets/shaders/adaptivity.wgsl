@@ -1,20 +1,9 @@
 #import "shaders/bindings.wgsl"::{VertexInfo, DispatchIndirectArgs, input_tex, index_lookup, vertex_buffer,
-                                  index_buffer, counts, adaptivity_counts, debug_tex};
-
-@group(1) @binding(0) var normal_tex: texture_3d<f32>;
-@group(1) @binding(1) var normal_samp: sampler;
-
-const VERTICES: array<vec3<u32>, 8> =
-    array<vec3<u32>, 8>(
-        vec3<u32>(0, 0, 0),
-        vec3<u32>(1, 0, 0),
-        vec3<u32>(1, 1, 0),
-        vec3<u32>(0, 1, 0),
-        vec3<u32>(0, 0, 1),
-        vec3<u32>(1, 0, 1),
-        vec3<u32>(1, 1, 1),
-        vec3<u32>(0, 1, 1),
-    );
+                                  index_buffer, counts, adaptivity_counts, debug_tex,
+                                  dispatched_vertex};
+#import "shaders/qef.wgsl"::{qef_new, qef_add, qef_solve, qef_mass_point, qef_error};
+#import "shaders/manifold.wgsl"::{cell_components, edge_component, axis_edge};
+#import "shaders/normals.wgsl"::{normal_tex, sample_normal};
 
 const AXES: array<vec3<u32>, 3> =
     array<vec3<u32>, 3>(
@@ -65,17 +54,16 @@ struct Plane {
 
 var<workgroup> planes: array<Plane, 12>;
 var<workgroup> n_edges: atomic<u32>;
-var<workgroup> forces: array<vec3<f32>, 8>;
-
-var<workgroup> wg_vtx_pos: vec3<f32>;
 
 @compute @workgroup_size(3, 2, 2)
 fn compute_adaptivity(
     @builtin(workgroup_id) wg_id: vec3<u32>,
     @builtin(local_invocation_id) invocation: vec3<u32>,
 ) {
-    let vtx_id = wg_id.x;
-    let vtx = vertex_buffer[vtx_id];
+    let vtx_id = dispatched_vertex(wg_id);
+    // workgroups past the last vertex still have to reach the barrier
+    let in_range = vtx_id < atomicLoad(&adaptivity_counts.vertices);
+    let vtx = vertex_buffer[min(vtx_id, arrayLength(&vertex_buffer) - 1u)];
     let vtx_pos = vec3<u32>(vec3(vtx.x, vtx.y, vtx.z));
 
     let axis_id = invocation.x;
@@ -95,10 +83,20 @@ fn compute_adaptivity(
     let sdf_a = sample_sdf(vtx_pos + start);
     let sdf_b = sample_sdf(vtx_pos + end);
 
-    if is_edge(sdf_a, sdf_b) {
+    var is_crossing = in_range && is_edge(sdf_a, sdf_b);
+
+#ifdef MANIFOLD
+    // cells can have a vertex for each piece of surface in them, only use
+    // the edges of this one's
+    let edge = axis_edge(selection.x, start);
+    is_crossing = is_crossing && edge_component(cell_components(vtx_pos), edge) == vtx.component;
+#endif
+
+    if is_crossing {
         let pos_index = atomicAdd(&n_edges, u32(1));
         let intersection_pos = adapt(sdf_a, sdf_b);
-        let normal = sample_grad(vtx_pos + start, vtx_pos + end, intersection_pos);
+        let grad = sample_grad(vtx_pos + start, vtx_pos + end, intersection_pos);
+        let normal = select(vec3(0.0), normalize(grad), dot(grad, grad) > 0.0);
         let new_vtx_pos = mix(vec3<f32>(start), vec3<f32>(end), intersection_pos);
 
         planes[pos_index] = Plane(new_vtx_pos, normal);
@@ -111,109 +109,48 @@ fn compute_adaptivity(
     let check = n > 0;
     // all subgroups should have n > 0
     let result = subgroupBallot(check);
+    if !in_range {
+        return;
+    }
     let prev = textureLoad(debug_tex, vtx_pos);
     textureStore(debug_tex, vtx_pos, vec4(f32(result.x), 0.0, 0.0, 0.0) + prev);
 
-    let index = calc_index(wg_id);
-
-    // I can't figure out a way to do this in a uniform way, there
-    // doesn't seem to be a way to perform atomic add on a vector
-    //
-    // average all intersection positions and make that the vertex
-    // starting point
-    var pos_calc = vec3<f32>(0.0);
-    for (var i = 0; i < i32(n); i++) {
-        pos_calc += planes[i].pos;
-    }
-    pos_calc = pos_calc / f32(n);
-    let final_pos = pos_calc + vec3<f32>(vtx_pos);
-
-    wg_vtx_pos = final_pos;
-
-    workgroupBarrier();
-
-    // we only have 8 corners unfortunately, some of them are gonna
-    // have to go
-    if index <= 8 {
-        let corner_pos = VERTICES[index];
-
-        const N_ITER: i32 = 3;
-
-        let force = schmitz(n, corner_pos);
-        forces[index] = force;
-        // this is theoretically a non-uniform workgroupBarrier, I
-        // hope it's ok tho
-
-        for (var iter = 0; iter < N_ITER; iter++) {
-            workgroupBarrier();
-            let combined_force = trilinear_add();
-            if index == 0 {
-                wg_vtx_pos += combined_force;
-            }
+    // the planes are all in workgroup memory now, so a single invocation
+    // can solve for the vertex
+    if all(invocation == vec3(0u)) {
+        var qef = qef_new();
+        for (var i = 0; i < i32(n); i++) {
+            qef = qef_add(qef, planes[i].pos, planes[i].normal);
         }
 
-        workgroupBarrier();
-        let final_pos = wg_vtx_pos + vec3<f32>(vtx_pos);
+#ifdef SURFACE_NETS
+        // surface nets only average the crossings, the relaxation happens
+        // in surface_nets.wgsl
+        let pos = qef_mass_point(qef);
+        let error = qef_error(qef, pos);
+#else
+        // settings are passed as the bits of the floats, see
+        // `qef_shader_defs` in src/shader.rs
+        let threshold = bitcast<f32>(#{QEF_SVD_THRESHOLD}u);
+        let bias = bitcast<f32>(#{QEF_MASS_POINT_BIAS}u);
+
+        let solution = qef_solve(qef, threshold, bias, #{QEF_CLAMP}, vec3(0.0), vec3(1.0));
+        let pos = solution.pos;
+        let error = solution.error;
+#endif
+        let final_pos = pos + vec3<f32>(vtx_pos);
 
-        // this code is the same no matter what sooo we should be good
-        // narrator voice: we were not good
         vertex_buffer[vtx_id].x = final_pos.x;
         vertex_buffer[vtx_id].y = final_pos.y;
         vertex_buffer[vtx_id].z = final_pos.z;
+        vertex_buffer[vtx_id].qef_error = error;
+
+        // the surface's own normal rather than the one of the planes, which
+        // are only at the edge crossings. Surface nets resample it once the
+        // vertex has been relaxed
+        let normal = sample_normal(final_pos);
+        vertex_buffer[vtx_id].n_x = normal.x;
+        vertex_buffer[vtx_id].n_y = normal.y;
+        vertex_buffer[vtx_id].n_z = normal.z;
     }
 }
-
-fn calc_index(pos: vec3<u32>) -> u32 {
-    return pos.x * 4 + pos.y * 2 + pos.z;
-}
-
-fn get_force(x: i32, y: i32, z: i32) -> vec3<f32> {
-    return forces[x * 4 + y * 2 + z];
-}
-
-fn trilinear_add() -> vec3<f32> {
-    // code very inspired by https://github.com/Tuntenfisch/Voxels
-
-    let pos = wg_vtx_pos;
-
-    let alpha = pos.x;
-
-    let c00 = mix(get_force(0, 0, 0), get_force(1, 0, 0), alpha);
-    let c01 = mix(get_force(0, 0, 1), get_force(1, 0, 1), alpha);
-    let c10 = mix(get_force(0, 1, 0), get_force(1, 1, 0), alpha);
-    let c11 = mix(get_force(0, 1, 1), get_force(1, 1, 1), alpha);
-
-    let beta = pos.y;
-
-    let c0 = mix(c00, c10, beta);
-    let c1 = mix(c01, c11, beta);
-
-    let gamma = pos.z;
-
-    return mix(c0, c1, gamma);
-}
-
-// compute the Schmitz Particle approximation to figure out the vertex
-// location
-fn schmitz(n: u32, corner_pos: vec3<u32>) -> vec3<f32> {
-    let pos = vec3<f32>(corner_pos);
-
-    var force = vec3<f32>(0.0);
-
-    for (var i = 0; i < i32(n); i++) {
-        let p = project_onto_plane(planes[i], pos);
-        force += p;
-    }
-
-    force *= 0.05;
-
-    return force;
-}
-
-fn project_onto_plane(plane: Plane, point: vec3<f32>) -> vec3<f32> {
-    let ab = point - plane.pos;
-    let ab_proj = dot(ab, plane.normal) * plane.normal;
-    let proj = point - ab_proj;
-
-    return proj;
-}
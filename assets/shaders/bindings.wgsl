@@ -1,3 +1,7 @@
+// every kernel dispatched over the grid runs in cubes of this many
+// invocations per axis, same as WORKGROUP_SIZE in src/shader.rs
+const WORKGROUP_SIZE: u32 = 4u;
+
 @group(0) @binding(0)
 var input_tex: texture_storage_3d<r32float, read_write>;
 
@@ -16,6 +20,19 @@ struct VertexInfo {
     n_x: f32,
     n_y: f32,
     n_z: f32,
+    // with the sign of the bitangent in t_w, see uv.wgsl
+    t_x: f32,
+    t_y: f32,
+    t_z: f32,
+    t_w: f32,
+    // residual of the QEF the position was solved from
+    qef_error: f32,
+    // which piece of the surface in its cell the vertex is for, always 0
+    // unless MANIFOLD is defined
+    component: u32,
+    // the material of the dominant inside corner of the cell, packed like
+    // ATTRIBUTE_MATERIAL in src/mesh.rs
+    material: u32,
 };
 
 
@@ -25,17 +42,52 @@ struct VertexInfo {
 @group(0) @binding(4)
     var<storage, read_write> counts: Counts;
 
+// laid out as the arguments of draw_indexed_indirect, so the mesh can be
+// drawn straight from the buffers without reading the counts back
 struct Counts {
-    vtx: atomic<u32>,
+    // index_count
     idx: atomic<u32>,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    // every vertex asked for, even the ones that didn't fit
+    vtx: atomic<u32>,
+    // slots handed out in index_buffer, idx only counts the ones that fit
+    idx_reserved: atomic<u32>,
+    // OVERFLOW_VERTICES and OVERFLOW_INDICES, for the CPU to grow the buffers
+    overflow: atomic<u32>,
 };
 
+const OVERFLOW_VERTICES: u32 = 1u;
+const OVERFLOW_INDICES: u32 = 2u;
+
+// index_lookup entry of the cells whose vertices didn't fit
+const NO_VERTEX: u32 = 0xffffffffu;
+
 @group(0) @binding(5) var<storage, read_write> adaptivity_counts: DispatchIndirectArgs;
 
+// the per vertex dispatches of the adaptivity and uv passes, one workgroup
+// per vertex laid out in rows of MAX_WORKGROUPS
 struct DispatchIndirectArgs {
     x: atomic<u32>,
     y: atomic<u32>,
     z: atomic<u32>,
+    // vertices written by compute_vertices, the arguments above are derived
+    // from it by vertex_dispatch
+    vertices: atomic<u32>,
 };
 
+// the most workgroups a dispatch can have along one axis
+const MAX_WORKGROUPS: u32 = 65535u;
+
+// the vertex handled by workgroup `wg_id` of a per vertex dispatch, can be
+// past the last one in the last row
+fn dispatched_vertex(wg_id: vec3<u32>) -> u32 {
+    return wg_id.y * MAX_WORKGROUPS + wg_id.x;
+}
+
 @group(0) @binding(6) var debug_tex: texture_storage_3d<rgba32float, read_write>;
+
+// material ID of every sample of input_tex, uploaded from the DensityMap
+@group(0) @binding(7) var material_tex: texture_3d<u32>;
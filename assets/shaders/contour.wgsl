@@ -1,6 +1,9 @@
 
 #import "shaders/bindings.wgsl"::{VertexInfo, DispatchIndirectArgs, input_tex, index_lookup, vertex_buffer,
-                                  index_buffer, counts, adaptivity_counts, debug_tex};
+                                  index_buffer, counts, adaptivity_counts, debug_tex, material_tex,
+                                  OVERFLOW_VERTICES, OVERFLOW_INDICES, NO_VERTEX, WORKGROUP_SIZE,
+                                  MAX_WORKGROUPS};
+#import "shaders/manifold.wgsl"::{cell_components, corners_components, component_count, edge_component, axis_edge};
 
 const VERTICES: array<vec3<u32>, 8> =
     array<vec3<u32>, 8>(
@@ -18,10 +21,6 @@ fn calc_vtx_pos(cell_pos: vec3<u32>) -> vec3<f32> {
     return vec3(0.0);
 }
 
-fn sample(pos: vec3<u32>) -> f32 {
-    return textureLoad(input_tex, pos).x;
-}
-
 fn inside(x: f32) -> bool {
     return x <= 0.0;
 }
@@ -37,42 +36,126 @@ fn is_edge(a: f32, b: f32) -> bool {
 
 
 // returns the vertex index
-fn write_vertex(vtx_index: u32, vtx: vec3<f32>) {
+fn write_vertex(vtx_index: u32, vtx: vec3<f32>, component: u32, material: u32) {
     var v: VertexInfo;
 
     v.x = vtx.x;
     v.y = vtx.y;
     v.z = vtx.z;
+    v.component = component;
+    // blended with itself, only marching cubes blends along the edges
+    v.material = material | (material << 8u);
 
     vertex_buffer[vtx_index] = v;
 }
 
+// the material of the corner furthest inside the surface, like
+// dominant_material in src/mesh.rs. 0 if none of them are inside
+fn dominant_material(cell: vec3<u32>, corners: array<f32, 8>) -> u32 {
+    var material = 0u;
+    var deepest = 0.0;
+    var found = false;
+
+    for (var i: u32 = 0; i < 8; i++) {
+        if inside(corners[i]) && (!found || corners[i] < deepest) {
+            material = textureLoad(material_tex, cell + VERTICES[i], 0).x;
+            deepest = corners[i];
+            found = true;
+        }
+    }
+
+    return material;
+}
+
 struct ComputeInput {
     @builtin(global_invocation_id) global_id: vec3<u32>,
-    // @builtin(workgroup_id) local_index: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
 };
 
+// the densities at the corners of a workgroup's cells, so one more sample
+// along each axis than there are invocations
+const TILE_SIZE: u32 = WORKGROUP_SIZE + 1u;
+const TILE_SAMPLES: u32 = TILE_SIZE * TILE_SIZE * TILE_SIZE;
+const WORKGROUP_INVOCATIONS: u32 = WORKGROUP_SIZE * WORKGROUP_SIZE * WORKGROUP_SIZE;
+
+var<workgroup> tile: array<f32, TILE_SAMPLES>;
+
+// has every invocation load a few of the samples, then waits for the rest.
+// Must be called before any invocation returns
+fn load_tile(input: ComputeInput) {
+    let origin = input.workgroup_id * WORKGROUP_SIZE;
+    // the workgroups on the far border of the grid hang over it
+    let max_pos = textureDimensions(input_tex) - 1u;
+
+    for (var i: u32 = 0; i < (TILE_SAMPLES + WORKGROUP_INVOCATIONS - 1u) / WORKGROUP_INVOCATIONS; i++) {
+        let index = i * WORKGROUP_INVOCATIONS + input.local_index;
+        if index < TILE_SAMPLES {
+            let offset = vec3(index % TILE_SIZE, (index / TILE_SIZE) % TILE_SIZE, index / (TILE_SIZE * TILE_SIZE));
+            tile[index] = textureLoad(input_tex, min(origin + offset, max_pos)).x;
+        }
+    }
+
+    workgroupBarrier();
+}
+
+// the sample at `offset` from the workgroup's first cell
+fn tile_sample(offset: vec3<u32>) -> f32 {
+    return tile[offset.x + TILE_SIZE * (offset.y + TILE_SIZE * offset.z)];
+}
+
 @compute
-@workgroup_size(1, 1, 1)
+@workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
 fn compute_vertices(input: ComputeInput) {
+    load_tile(input);
+
     let global_id = input.global_id;
 
+    // the dispatch is rounded up to whole workgroups
+    if any(global_id >= textureDimensions(index_lookup)) {
+        return;
+    }
+
+    var corners: array<f32, 8>;
     var samples: u32 = u32(0);
     for (var i: u32 = 0; i < 8; i++) {
-        let corner_sample = u32(textureLoad(input_tex, global_id + VERTICES[i]).x < 0.0);
-
-        samples = samples | (corner_sample << i);
+        corners[i] = tile_sample(input.local_id + VERTICES[i]);
+        samples = samples | (u32(inside(corners[i])) << i);
     }
 
     let is_air = samples == 0;
     let is_solid = samples == 255;
     let is_surface = !is_air && !is_solid;
 
-    if (is_surface) {
-        let vtx_index = atomicAdd(&counts.vtx, u32(1));
-        atomicAdd(&adaptivity_counts.x, u32(1));
-        textureStore(index_lookup, global_id, vec4(vtx_index, 0, 0, 0));
-        write_vertex(vtx_index, vec3<f32>(global_id) + calc_vtx_pos(global_id));
+#ifdef MANIFOLD
+    // one vertex for every separate piece of surface in the cell, gen_face
+    // picks the one each edge belongs to
+    let n_vertices = component_count(corners_components(corners));
+#else
+    let n_vertices = u32(is_surface);
+#endif
+
+    if (n_vertices > 0) {
+        let vtx_index = atomicAdd(&counts.vtx, n_vertices);
+
+        // the buffer is full, leave the cell out and let the CPU grow it.
+        // Once one cell doesn't fit no later one does, so the vertices that
+        // were written stay contiguous
+        if vtx_index + n_vertices > arrayLength(&vertex_buffer) {
+            atomicOr(&counts.overflow, OVERFLOW_VERTICES);
+            textureStore(index_lookup, global_id, vec4(NO_VERTEX, 0, 0, 0));
+        } else {
+            atomicAdd(&adaptivity_counts.vertices, n_vertices);
+            textureStore(index_lookup, global_id, vec4(vtx_index, 0, 0, 0));
+
+            // shared by all the vertices of the cell
+            let material = dominant_material(global_id, corners);
+
+            for (var i: u32 = 0; i < n_vertices; i++) {
+                write_vertex(vtx_index + i, vec3<f32>(global_id) + calc_vtx_pos(global_id), i, material);
+            }
+        }
     }
 
     textureStore(debug_tex, global_id, vec4(0.0, f32(samples), 0.0, 0.0));
@@ -96,18 +179,24 @@ const AXIS_TABLE: array<bool, 6> = array<bool, 6>(
     true,
 );
 
-@compute @workgroup_size(1, 1, 1)
-fn compute_edges(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let cell_pos = global_id;
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn compute_edges(input: ComputeInput) {
+    load_tile(input);
 
-    var samples = vec4(sample(global_id), 0.0, 0.0, 0.0);
+    let global_id = input.global_id;
+
+    if any(global_id >= textureDimensions(index_lookup)) {
+        return;
+    }
+
+    var samples = vec4(tile_sample(input.local_id), 0.0, 0.0, 0.0);
 
     for (var i: u32 = 0; i < 3; i++) {
-        let start = global_id;
+        let start = input.local_id;
         let end = start + AXES[i];
 
-        let a = sample(start);
-        let b = sample(end);
+        let a = tile_sample(start);
+        let b = tile_sample(end);
 
         // if a && !b then these polygons should be facing a->b
         // if !a && b then they should be facing b->a
@@ -171,6 +260,18 @@ fn gen_face(pos: vec3<u32>, axis: u32, invert_winding: bool) {
         let cell_pos = vec3<u32>(vec3<i32>(pos) + offsets[i]);
 
         vtx_indices[i] = textureLoad(index_lookup, cell_pos).x;
+
+        // the overflow is already flagged, the quad is left out until the
+        // vertex buffer has grown
+        if vtx_indices[i] == NO_VERTEX {
+            return;
+        }
+
+#ifdef MANIFOLD
+        // the same edge, seen from the cell
+        let edge = axis_edge(axis, vec3<u32>(-offsets[i]));
+        vtx_indices[i] += edge_component(cell_components(cell_pos), edge);
+#endif
     }
 
     write_quad(vtx_indices, invert_winding);
@@ -178,7 +279,13 @@ fn gen_face(pos: vec3<u32>, axis: u32, invert_winding: bool) {
 
 fn write_quad(indices: array<u32, 4>, invert_winding: bool) {
     // vtx orders: 0 1 2, 1 3 2
-    let base = atomicAdd(&counts.idx, u32(6));
+    let base = atomicAdd(&counts.idx_reserved, u32(6));
+
+    // like the vertices, the quads that fit stay contiguous
+    if base + 6 > arrayLength(&index_buffer) {
+        atomicOr(&counts.overflow, OVERFLOW_INDICES);
+        return;
+    }
 
     if invert_winding {
         // tri1
@@ -201,12 +308,26 @@ fn write_quad(indices: array<u32, 4>, invert_winding: bool) {
         index_buffer[base+4] = indices[3];
         index_buffer[base+5] = indices[2];
     }
+
+    atomicAdd(&counts.idx, u32(6));
 }
 
 @compute @workgroup_size(1, 1, 1)
 fn cleanup() {
     atomicStore(&counts.vtx, u32(0));
     atomicStore(&counts.idx, u32(0));
-    atomicStore(&adaptivity_counts.x, u32(0));
+    atomicStore(&counts.idx_reserved, u32(0));
+    atomicStore(&counts.overflow, u32(0));
+    atomicStore(&adaptivity_counts.vertices, u32(0));
+}
+
+// the dispatch arguments covering every written vertex, rows of
+// MAX_WORKGROUPS so large meshes stay within the limit per axis
+@compute @workgroup_size(1, 1, 1)
+fn vertex_dispatch() {
+    let n = atomicLoad(&adaptivity_counts.vertices);
+    atomicStore(&adaptivity_counts.x, min(n, MAX_WORKGROUPS));
+    atomicStore(&adaptivity_counts.y, min((n + MAX_WORKGROUPS - 1u) / MAX_WORKGROUPS, MAX_WORKGROUPS));
+    atomicStore(&adaptivity_counts.z, 1u);
 }
 
@@ -0,0 +1,117 @@
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_view_bindings::view,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    view_transformations::position_world_to_clip,
+}
+#import "shaders/splat_blend.wgsl"::{SplatSettings, splat_weights, splat_color}
+
+// Draws the contoured mesh straight from the buffers the compute passes
+// wrote, see DrawContouring in src/shader.rs. The index buffer is bound as
+// is and the draw arguments are the counts compute_edges accumulates. The
+// view and material bind groups are the ones of the SplatMaterial on the
+// hidden read back mesh, and it's lit like splat.wgsl lights the chunks.
+
+// same layout as VertexInfo in bindings.wgsl, which can't be imported here
+// as its buffers are read_write
+struct VertexInfo {
+    x: f32,
+    y: f32,
+    z: f32,
+    u: f32,
+    v: f32,
+    n_x: f32,
+    n_y: f32,
+    n_z: f32,
+    // with the sign of the bitangent in t_w, see uv.wgsl
+    t_x: f32,
+    t_y: f32,
+    t_z: f32,
+    t_w: f32,
+    qef_error: f32,
+    component: u32,
+    // the material of the dominant inside corner of the cell, packed like
+    // ATTRIBUTE_MATERIAL in src/mesh.rs
+    material: u32,
+};
+
+// next to a single identity mesh at binding 0, which the mesh flags are
+// read from
+@group(1) @binding(1) var<storage, read> vertices: array<VertexInfo>;
+
+@group(2) @binding(100) var<uniform> splat: SplatSettings;
+@group(2) @binding(101) var splat_layers: texture_2d_array<f32>;
+@group(2) @binding(102) var splat_sampler: sampler;
+
+// the standard VertexOutput plus the weights, like SplatVertexOutput in
+// splat.wgsl. The UVs and tangents are always there, see
+// ContouringDrawPipeline
+struct DrawVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(4) world_tangent: vec4<f32>,
+    @location(8) weights: vec4<f32>,
+};
+
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> DrawVertexOutput {
+    let info = vertices[index];
+    let pos = vec3(info.x, info.y, info.z);
+
+    var out: DrawVertexOutput;
+    out.position = position_world_to_clip(pos);
+    out.world_position = vec4(pos, 1.0);
+    out.world_normal = vec3(info.n_x, info.n_y, info.n_z);
+    out.uv = vec2(info.u, info.v);
+    out.world_tangent = vec4(info.t_x, info.t_y, info.t_z, info.t_w);
+    out.weights = splat_weights(info.material);
+    return out;
+}
+
+fn standard_output(in: DrawVertexOutput, normal: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = in.position;
+    out.world_position = in.world_position;
+    out.world_normal = normal;
+#ifdef VERTEX_UVS_A
+    out.uv = in.uv;
+#endif
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = in.world_tangent;
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = 0u;
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = 0;
+#endif
+    return out;
+}
+
+@fragment
+fn fragment(in: DrawVertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    // the normals written by the vertex passes, the same ones the read back
+    // mesh gets. Vertices without a gradient fall back to flat shading
+    let world_position = in.world_position.xyz;
+    var flat_normal = cross(dpdx(world_position), dpdy(world_position));
+    if dot(flat_normal, view.world_position - world_position) < 0.0 {
+        flat_normal = -flat_normal;
+    }
+
+    let has_normal = dot(in.world_normal, in.world_normal) > 0.0;
+    let normal = normalize(select(flat_normal, in.world_normal, has_normal));
+
+    var pbr_input = pbr_input_from_standard_material(standard_output(in, normal), is_front);
+
+    let color = splat_color(splat_layers, splat_sampler, splat, world_position, normal, in.weights);
+    pbr_input.material.base_color *= vec4(color, 1.0);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,96 @@
+#import "shaders/bindings.wgsl"::input_tex;
+#import contouring::edge_components::{MAX_COMPONENTS, COMPONENT_BITS, SUBCASE_OFFSETS, SUBCASE_COMPONENTS,
+                                      AXIS_EDGES};
+
+// Lookups into the separate pieces of surface in each marching cubes case
+// once its ambiguous faces are decided, mirrors `Subcases::components` in
+// src/cases.rs which generates the tables.
+
+const VERTICES: array<vec3<u32>, 8> =
+    array<vec3<u32>, 8>(
+        vec3<u32>(0, 0, 0),
+        vec3<u32>(1, 0, 0),
+        vec3<u32>(1, 1, 0),
+        vec3<u32>(0, 1, 0),
+        vec3<u32>(0, 0, 1),
+        vec3<u32>(1, 0, 1),
+        vec3<u32>(1, 1, 1),
+        vec3<u32>(0, 1, 1),
+    );
+
+// the corners of each face, same as FACES in src/cases.rs
+const FACES: array<vec4<u32>, 6> =
+    array<vec4<u32>, 6>(
+        vec4<u32>(0, 4, 7, 3),
+        vec4<u32>(1, 2, 6, 5),
+        vec4<u32>(0, 1, 5, 4),
+        vec4<u32>(2, 3, 7, 6),
+        vec4<u32>(0, 3, 2, 1),
+        vec4<u32>(4, 5, 6, 7),
+    );
+
+// the pieces of surface of a cell, packed like SUBCASE_COMPONENTS
+fn cell_components(cell: vec3<u32>) -> u32 {
+    var corners: array<f32, 8>;
+    for (var i: u32 = 0; i < 8; i++) {
+        corners[i] = textureLoad(input_tex, cell + VERTICES[i]).x;
+    }
+
+    return corners_components(corners);
+}
+
+// cell_components from densities that were already loaded, in VERTICES
+// order. The ambiguous faces are decided like `resolve_faces`
+fn corners_components(corners: array<f32, 8>) -> u32 {
+    let entry = SUBCASE_OFFSETS[corners_case(corners)];
+    let ambiguous = entry >> 16u;
+
+    // the decided faces with the unambiguous ones squeezed out
+    var rank = 0u;
+    var bit = 0u;
+    for (var face: u32 = 0; face < 6; face++) {
+        if (ambiguous & (1u << face)) != 0u {
+            if joins_set_corners(corners, FACES[face]) {
+                rank |= 1u << bit;
+            }
+            bit++;
+        }
+    }
+
+    return SUBCASE_COMPONENTS[(entry & 0xffffu) + rank];
+}
+
+// the case of a cell, with the corners outside the surface set like
+// `sample_density_map`
+fn corners_case(corners: array<f32, 8>) -> u32 {
+    var mask: u32 = u32(0);
+    for (var i: u32 = 0; i < 8; i++) {
+        mask = mask | (u32(corners[i] > 0.0) << i);
+    }
+
+    return mask;
+}
+
+// the asymptotic decider, same as `joins_set_corners` in src/cases.rs
+fn joins_set_corners(corners: array<f32, 8>, face: vec4<u32>) -> bool {
+    let a = corners[face.x] * corners[face.z];
+    let b = corners[face.y] * corners[face.w];
+
+    return select(b > a, a > b, corners[face.x] > 0.0);
+}
+
+fn component_count(components: u32) -> u32 {
+    return components >> 24u;
+}
+
+// only meaningful for edges the surface crosses
+fn edge_component(components: u32, edge: u32) -> u32 {
+    return (components >> (edge * COMPONENT_BITS)) & (MAX_COMPONENTS - 1u);
+}
+
+// the edge of a cell starting at its corner `start` and running along `axis`
+fn axis_edge(axis: u32, start: vec3<u32>) -> u32 {
+    let u = start[(axis + 1u) % 3u];
+    let v = start[(axis + 2u) % 3u];
+    return AXIS_EDGES[axis * 4u + u + v * 2u];
+}
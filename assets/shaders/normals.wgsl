@@ -0,0 +1,14 @@
+// The gradients compute_sdf or compute_gradient left in normal_tex, shared
+// by the passes that place the final vertices.
+
+@group(1) @binding(0) var normal_tex: texture_3d<f32>;
+@group(1) @binding(1) var normal_samp: sampler;
+
+// the gradient trilinearly interpolated at `pos`, in grid space
+fn sample_normal(pos: vec3<f32>) -> vec3<f32> {
+    // texel centers are half a texel in
+    let uvw = (pos + 0.5) / vec3<f32>(textureDimensions(normal_tex));
+    let grad = textureSampleLevel(normal_tex, normal_samp, uvw, 0.0).xyz;
+
+    return select(vec3(0.0), normalize(grad), dot(grad, grad) > 0.0);
+}
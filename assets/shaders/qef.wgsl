@@ -0,0 +1,135 @@
+// Quadratic error function minimization, mirrors src/qef.rs so keep the
+// two in sync.
+
+// number of Jacobi sweeps, 3x3 matrices converge well before this
+const SWEEPS: i32 = 6;
+
+struct Qef {
+    ata: mat3x3<f32>,
+    atb: vec3<f32>,
+    btb: f32,
+    mass_point_sum: vec3<f32>,
+    count: f32,
+};
+
+struct QefSolution {
+    pos: vec3<f32>,
+    // sum of the squared distances from pos to the planes
+    error: f32,
+};
+
+struct Eigen {
+    values: vec3<f32>,
+    // as columns
+    vectors: mat3x3<f32>,
+};
+
+fn qef_new() -> Qef {
+    return Qef(mat3x3<f32>(), vec3(0.0), 0.0, vec3(0.0), 0.0);
+}
+
+fn outer(a: vec3<f32>, b: vec3<f32>) -> mat3x3<f32> {
+    return mat3x3<f32>(a * b.x, a * b.y, a * b.z);
+}
+
+fn qef_add(qef: Qef, pos: vec3<f32>, normal: vec3<f32>) -> Qef {
+    var q = qef;
+    let b = dot(normal, pos);
+
+    q.ata += outer(normal, normal);
+    q.atb += normal * b;
+    q.btb += b * b;
+    q.mass_point_sum += pos;
+    q.count += 1.0;
+
+    return q;
+}
+
+fn qef_mass_point(qef: Qef) -> vec3<f32> {
+    return qef.mass_point_sum / max(qef.count, 1.0);
+}
+
+fn qef_error(qef: Qef, pos: vec3<f32>) -> f32 {
+    return max(dot(pos, qef.ata * pos) - 2.0 * dot(pos, qef.atb) + qef.btb, 0.0);
+}
+
+// threshold: singular values smaller than this fraction of the largest
+// one are treated as zero
+// bias: weight of an extra pull towards the mass point
+fn qef_solve(
+    qef: Qef,
+    threshold: f32,
+    bias: f32,
+    clamp_to_bounds: bool,
+    min_pos: vec3<f32>,
+    max_pos: vec3<f32>,
+) -> QefSolution {
+    let mass_point = qef_mass_point(qef);
+
+    let bias_mat = mat3x3<f32>(vec3(bias, 0.0, 0.0), vec3(0.0, bias, 0.0), vec3(0.0, 0.0, bias));
+    let ata = qef.ata + bias_mat;
+    let atb = qef.atb - qef.ata * mass_point;
+
+    var pos = mass_point + pseudo_inverse(ata, threshold) * atb;
+
+    if clamp_to_bounds {
+        pos = clamp(pos, min_pos, max_pos);
+    }
+
+    return QefSolution(pos, qef_error(qef, pos));
+}
+
+fn symmetric_eigen(m: mat3x3<f32>) -> Eigen {
+    var a = m;
+    var vectors = mat3x3<f32>(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+
+    for (var sweep = 0; sweep < SWEEPS; sweep++) {
+        for (var pair = 0; pair < 3; pair++) {
+            // (0, 1), (0, 2), (1, 2)
+            let p = select(0, 1, pair == 2);
+            let q = select(pair + 1, 2, pair == 2);
+
+            let apq = a[q][p];
+            if abs(apq) < 1e-12 {
+                continue;
+            }
+
+            let app = a[p][p];
+            let aqq = a[q][q];
+
+            let theta = (aqq - app) / (2.0 * apq);
+            let t = select(-1.0, 1.0, theta >= 0.0) / (abs(theta) + sqrt(theta * theta + 1.0));
+            let c = 1.0 / sqrt(t * t + 1.0);
+            let s = t * c;
+
+            var rotation = mat3x3<f32>(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+            rotation[p][p] = c;
+            rotation[q][q] = c;
+            rotation[q][p] = s;
+            rotation[p][q] = -s;
+
+            a = transpose(rotation) * a * rotation;
+            vectors = vectors * rotation;
+        }
+    }
+
+    return Eigen(vec3(a[0][0], a[1][1], a[2][2]), vectors);
+}
+
+fn pseudo_inverse(a: mat3x3<f32>, threshold: f32) -> mat3x3<f32> {
+    let eigen = symmetric_eigen(a);
+    let values = eigen.values;
+
+    // the eigenvalues are squared singular values
+    let cutoff = threshold * threshold * max(values.x, max(values.y, values.z));
+
+    var inverse = mat3x3<f32>();
+    for (var i = 0; i < 3; i++) {
+        if values[i] > cutoff && values[i] > 1e-12 {
+            let v = eigen.vectors[i];
+            inverse += outer(v, v) * (1.0 / values[i]);
+        }
+    }
+
+    return inverse;
+}
@@ -1,38 +1,43 @@
 
-#import "shaders/bindings.wgsl"::{input_tex, index_lookup, vertex_buffer, index_buffer, counts, debug_tex};
+#import "shaders/bindings.wgsl"::{input_tex, index_lookup, vertex_buffer, index_buffer, counts, debug_tex,
+                                  WORKGROUP_SIZE};
+// generated from the `SdfScene` resource, see src/sdf.rs
+#import contouring::sdf_scene::sdf
 
 @group(1) @binding(0) var grad_tex: texture_storage_3d<rgba8snorm, write>;
 
-struct Sphere {
-    pos: vec3<f32>,
-    radius: f32,
-};
-
-fn sdf(pos: vec3<f32>) -> f32 {
-    let sphere = Sphere(vec3(2.5, 2.5, 2.5), 1.0);
-
-    let dist = length(sphere.pos - pos) - sphere.radius;
-
-    return dist;
-}
-
 struct ComputeInput {
     @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
 }
 
-@compute @workgroup_size(1, 1, 1)
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
 fn compute_sdf(input: ComputeInput) {
+    // the dispatch is rounded up to whole workgroups
+    if any(input.global_id >= textureDimensions(input_tex)) {
+        return;
+    }
+
     let pos = vec3<f32>(input.global_id);
 
     let dist = sdf(pos);
-    let grad = sdf_grad(pos);
+    var grad = sdf_grad(pos);
+
+    // an exact SDF has a unit gradient already, but the CSG operators
+    // don't keep it one and normal_tex clamps to [-1, 1]
+    if length(grad) > 0.0 {
+        grad = normalize(grad);
+    }
 
     textureStore(input_tex, input.global_id, vec4(dist).xxxx);
     textureStore(grad_tex, input.global_id, grad.xyzz);
 }
 
+// the central difference of the sdf along `dv`
 fn estimate_normal(v: vec3<f32>, dv: vec3<f32>) -> f32 {
-    return (sdf(v + dv) + sdf(v - dv)) / 2 / length(dv);
+    return (sdf(v + dv) - sdf(v - dv)) / (2.0 * length(dv));
 }
 
 fn sdf_grad(pos: vec3<f32>) -> vec3<f32> {
@@ -48,3 +53,66 @@ fn sdf_grad(pos: vec3<f32>) -> vec3<f32> {
 
     return vec3(x, y, z);
 }
+
+// the densities around a workgroup's samples, one more on either side
+// along each axis for the central differences
+const TILE_SIZE: u32 = WORKGROUP_SIZE + 2u;
+const TILE_SAMPLES: u32 = TILE_SIZE * TILE_SIZE * TILE_SIZE;
+const WORKGROUP_INVOCATIONS: u32 = WORKGROUP_SIZE * WORKGROUP_SIZE * WORKGROUP_SIZE;
+
+var<workgroup> tile: array<f32, TILE_SAMPLES>;
+
+// has every invocation load a few of the samples, then waits for the rest.
+// Must be called before any invocation returns
+fn load_tile(input: ComputeInput) {
+    let origin = vec3<i32>(input.workgroup_id * WORKGROUP_SIZE) - 1;
+    let max_pos = vec3<i32>(textureDimensions(input_tex)) - 1;
+
+    for (var i: u32 = 0; i < (TILE_SAMPLES + WORKGROUP_INVOCATIONS - 1u) / WORKGROUP_INVOCATIONS; i++) {
+        let index = i * WORKGROUP_INVOCATIONS + input.local_index;
+        if index < TILE_SAMPLES {
+            let offset = vec3(index % TILE_SIZE, (index / TILE_SIZE) % TILE_SIZE, index / (TILE_SIZE * TILE_SIZE));
+            tile[index] = load_density(origin + vec3<i32>(offset), max_pos);
+        }
+    }
+
+    workgroupBarrier();
+}
+
+// the sample at `offset` from the sample handled by the invocation
+fn tile_sample(input: ComputeInput, offset: vec3<i32>) -> f32 {
+    let pos = vec3<u32>(vec3<i32>(input.local_id + 1u) + offset);
+    return tile[pos.x + TILE_SIZE * (pos.y + TILE_SIZE * pos.z)];
+}
+
+// used instead of compute_sdf when the densities are uploaded from the
+// CPU, we don't have an sdf to differentiate so use central differences
+// on the grid itself
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn compute_gradient(input: ComputeInput) {
+    load_tile(input);
+
+    if any(input.global_id >= textureDimensions(input_tex)) {
+        return;
+    }
+
+    let dx = vec3(1, 0, 0);
+    let dy = dx.yxy;
+    let dz = dx.yyx;
+
+    var grad = vec3(
+        tile_sample(input, dx) - tile_sample(input, -dx),
+        tile_sample(input, dy) - tile_sample(input, -dy),
+        tile_sample(input, dz) - tile_sample(input, -dz),
+    );
+
+    if length(grad) > 0.0 {
+        grad = normalize(grad);
+    }
+
+    textureStore(grad_tex, input.global_id, grad.xyzz);
+}
+
+fn load_density(pos: vec3<i32>, max_pos: vec3<i32>) -> f32 {
+    return textureLoad(input_tex, clamp(pos, vec3(0), max_pos)).x;
+}
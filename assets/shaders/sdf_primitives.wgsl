@@ -0,0 +1,93 @@
+// Primitives and operators used by the generated `contouring::sdf_scene`
+// module, see `Sdf::to_wgsl` in src/sdf.rs. The formulas have to stay in
+// sync with `Sdf::eval` so both backends produce the same field.
+//
+// All primitives are centered on the origin, the generated code
+// translates `pos` before calling them.
+
+fn sd_sphere(p: vec3<f32>, radius: f32) -> f32 {
+    return length(p) - radius;
+}
+
+fn sd_box(p: vec3<f32>, half_extents: vec3<f32>) -> f32 {
+    let q = abs(p) - half_extents;
+    return length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+fn sd_rounded_box(p: vec3<f32>, half_extents: vec3<f32>, radius: f32) -> f32 {
+    return sd_box(p, half_extents - vec3(radius)) - radius;
+}
+
+// lies in the xz plane
+fn sd_torus(p: vec3<f32>, major_radius: f32, minor_radius: f32) -> f32 {
+    let q = vec2(length(p.xz) - major_radius, p.y);
+    return length(q) - minor_radius;
+}
+
+fn sd_capsule(p: vec3<f32>, a: vec3<f32>, b: vec3<f32>, radius: f32) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / dot(ba, ba), 0.0, 1.0);
+    return length(pa - ba * h) - radius;
+}
+
+// along the y axis
+fn sd_cylinder(p: vec3<f32>, radius: f32, half_height: f32) -> f32 {
+    let d = abs(vec2(length(p.xz), p.y)) - vec2(radius, half_height);
+    return min(max(d.x, d.y), 0.0) + length(max(d, vec2(0.0)));
+}
+
+// along the y axis, with the base at -half_height and the tip at
+// +half_height
+fn sd_cone(p: vec3<f32>, radius: f32, half_height: f32) -> f32 {
+    let q = vec2(length(p.xz), p.y);
+    let k1 = vec2(0.0, half_height);
+    let k2 = vec2(-radius, 2.0 * half_height);
+
+    var r = 0.0;
+    if q.y < 0.0 {
+        r = radius;
+    }
+
+    let ca = vec2(q.x - min(q.x, r), abs(q.y) - half_height);
+    let cb = q - k1 + k2 * clamp(dot(k1 - q, k2) / dot(k2, k2), 0.0, 1.0);
+
+    var s = 1.0;
+    if cb.x < 0.0 && ca.y < 0.0 {
+        s = -1.0;
+    }
+
+    return s * sqrt(min(dot(ca, ca), dot(cb, cb)));
+}
+
+fn sd_plane(p: vec3<f32>, normal: vec3<f32>, distance: f32) -> f32 {
+    return dot(p, normal) - distance;
+}
+
+fn op_union(a: f32, b: f32) -> f32 {
+    return min(a, b);
+}
+
+fn op_intersection(a: f32, b: f32) -> f32 {
+    return max(a, b);
+}
+
+// a with b cut out of it
+fn op_subtraction(a: f32, b: f32) -> f32 {
+    return max(a, -b);
+}
+
+fn op_smooth_union(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+fn op_smooth_intersection(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 - 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) + k * h * (1.0 - h);
+}
+
+fn op_smooth_subtraction(a: f32, b: f32, k: f32) -> f32 {
+    let h = clamp(0.5 - 0.5 * (a + b) / k, 0.0, 1.0);
+    return mix(a, -b, h) + k * h * (1.0 - h);
+}
@@ -0,0 +1,133 @@
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_functions,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    view_transformations::position_world_to_clip,
+}
+#import "shaders/splat_blend.wgsl"::{SplatSettings, splat_color}
+
+// SplatExtension in src/splat.rs. The vertex shader is the standard one
+// minus skinning and morph targets, passing the splat weights along.
+
+@group(2) @binding(100) var<uniform> splat: SplatSettings;
+@group(2) @binding(101) var splat_layers: texture_2d_array<f32>;
+@group(2) @binding(102) var splat_sampler: sampler;
+
+// the locations are the ones SplatExtension::specialize lays out
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+#ifdef VERTEX_UVS_A
+    @location(2) uv: vec2<f32>,
+#endif
+#ifdef VERTEX_TANGENTS
+    @location(4) tangent: vec4<f32>,
+#endif
+#ifdef SPLAT_WEIGHTS
+    @location(8) weights: vec4<f32>,
+#endif
+};
+
+// the standard VertexOutput, which can't be extended, plus the weights
+struct SplatVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+#ifdef VERTEX_UVS_A
+    @location(2) uv: vec2<f32>,
+#endif
+#ifdef VERTEX_TANGENTS
+    @location(4) world_tangent: vec4<f32>,
+#endif
+    @location(6) @interpolate(flat) instance_index: u32,
+#ifdef VISIBILITY_RANGE_DITHER
+    @location(7) @interpolate(flat) visibility_range_dither: i32,
+#endif
+    @location(8) weights: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> SplatVertexOutput {
+    var out: SplatVertexOutput;
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    out.world_position = mesh_functions::mesh_position_local_to_world(
+        world_from_local,
+        vec4(vertex.position, 1.0),
+    );
+    out.position = position_world_to_clip(out.world_position.xyz);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(
+        vertex.normal,
+        vertex.instance_index,
+    );
+
+#ifdef VERTEX_UVS_A
+    out.uv = vertex.uv;
+#endif
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = mesh_functions::mesh_tangent_local_to_world(
+        world_from_local,
+        vertex.tangent,
+        vertex.instance_index,
+    );
+#endif
+
+    out.instance_index = vertex.instance_index;
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = mesh_functions::get_visibility_range_dither_level(
+        vertex.instance_index,
+        world_from_local[3],
+    );
+#endif
+
+#ifdef SPLAT_WEIGHTS
+    out.weights = vertex.weights;
+#else
+    out.weights = vec4(1.0, 0.0, 0.0, 0.0);
+#endif
+
+    return out;
+}
+
+fn standard_output(in: SplatVertexOutput) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = in.position;
+    out.world_position = in.world_position;
+    out.world_normal = in.world_normal;
+#ifdef VERTEX_UVS_A
+    out.uv = in.uv;
+#endif
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = in.world_tangent;
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = in.instance_index;
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = in.visibility_range_dither;
+#endif
+    return out;
+}
+
+@fragment
+fn fragment(in: SplatVertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(standard_output(in), is_front);
+
+    let color = splat_color(
+        splat_layers,
+        splat_sampler,
+        splat,
+        in.world_position.xyz,
+        normalize(in.world_normal),
+        in.weights,
+    );
+    pbr_input.material.base_color *= vec4(color, 1.0);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,78 @@
+// The splat blending shared by the SplatMaterial in splat.wgsl and the
+// direct draw in draw.wgsl, which bind the layers differently.
+
+// same as SplatSettings in src/splat.rs
+struct SplatSettings {
+    scale: f32,
+    sharpness: f32,
+    blend_depth: f32,
+};
+
+// same as SPLAT_LAYERS in src/splat.rs
+const SPLAT_LAYERS: u32 = 4u;
+
+// the layer weights of a vertex with a packed material, like splat_weights
+// in src/splat.rs
+fn splat_weights(packed: u32) -> vec4<f32> {
+    let a = min(packed & 0xffu, SPLAT_LAYERS - 1u);
+    let b = min((packed >> 8u) & 0xffu, SPLAT_LAYERS - 1u);
+    let blend = f32((packed >> 16u) & 0xffu) / 255.0;
+
+    var weights = vec4(0.0);
+    weights[a] += 1.0 - blend;
+    weights[b] += blend;
+    return weights;
+}
+
+// a layer projected along the three axes, blended by `axes`
+fn triplanar(
+    layers: texture_2d_array<f32>,
+    layers_sampler: sampler,
+    layer: u32,
+    pos: vec3<f32>,
+    axes: vec3<f32>,
+) -> vec4<f32> {
+    let x = textureSample(layers, layers_sampler, pos.zy, layer);
+    let y = textureSample(layers, layers_sampler, pos.xz, layer);
+    let z = textureSample(layers, layers_sampler, pos.xy, layer);
+
+    return x * axes.x + y * axes.y + z * axes.z;
+}
+
+// the albedo of the layers blended by `weights`, the layers sticking out
+// furthest covering the others
+fn splat_color(
+    layers: texture_2d_array<f32>,
+    layers_sampler: sampler,
+    settings: SplatSettings,
+    world_position: vec3<f32>,
+    world_normal: vec3<f32>,
+    weights: vec4<f32>,
+) -> vec3<f32> {
+    let pos = world_position * settings.scale;
+    var axes = pow(abs(world_normal), vec3(settings.sharpness));
+    axes /= max(axes.x + axes.y + axes.z, 1e-5);
+
+    // sampled unconditionally, as the implicit derivatives need uniform
+    // control flow
+    let samples = array(
+        triplanar(layers, layers_sampler, 0u, pos, axes),
+        triplanar(layers, layers_sampler, 1u, pos, axes),
+        triplanar(layers, layers_sampler, 2u, pos, axes),
+        triplanar(layers, layers_sampler, 3u, pos, axes),
+    );
+
+    // layers that aren't there at all stay out, however high they are
+    let heights = vec4(samples[0].a, samples[1].a, samples[2].a, samples[3].a) + weights;
+    let present = select(vec4(-2.0), heights, weights > vec4(0.0));
+    let top = max(max(present.x, present.y), max(present.z, present.w));
+
+    let depth = max(settings.blend_depth, 1e-3);
+    var blend = max(present - (top - depth), vec4(0.0));
+    blend /= max(dot(blend, vec4(1.0)), 1e-5);
+
+    return samples[0].rgb * blend.x
+        + samples[1].rgb * blend.y
+        + samples[2].rgb * blend.z
+        + samples[3].rgb * blend.w;
+}
@@ -0,0 +1,121 @@
+#import "shaders/bindings.wgsl"::{input_tex, index_lookup, vertex_buffer, NO_VERTEX, WORKGROUP_SIZE};
+#import "shaders/normals.wgsl"::sample_normal;
+
+// Smooth Surface Nets relaxation, mirrors `relax` in src/surface_nets.rs.
+// Every iteration runs relax_vertices and then apply_relaxation, so all the
+// neighbours are read before any of them move.
+
+@group(1) @binding(2) var<storage, read_write> relaxed: array<vec4<f32>>;
+
+const AXES: array<vec3<u32>, 3> =
+    array<vec3<u32>, 3>(
+        vec3(1, 0, 0),
+        vec3(0, 1, 0),
+        vec3(0, 0, 1),
+    );
+
+const VERTICES: array<vec3<u32>, 8> =
+    array<vec3<u32>, 8>(
+        vec3<u32>(0, 0, 0),
+        vec3<u32>(1, 0, 0),
+        vec3<u32>(1, 1, 0),
+        vec3<u32>(0, 1, 0),
+        vec3<u32>(0, 0, 1),
+        vec3<u32>(1, 0, 1),
+        vec3<u32>(1, 1, 1),
+        vec3<u32>(0, 1, 1),
+    );
+
+fn sample(pos: vec3<u32>) -> f32 {
+    return textureLoad(input_tex, pos).x;
+}
+
+fn inside(x: f32) -> bool {
+    return x <= 0.0;
+}
+
+// same test as compute_vertices, only these cells have an up to date entry
+// in index_lookup. Cells whose vertex didn't fit into the vertex buffer are
+// left out
+fn is_surface(cell: vec3<u32>) -> bool {
+    var samples: u32 = u32(0);
+    for (var i: u32 = 0; i < 8; i++) {
+        samples = samples | (u32(inside(sample(cell + VERTICES[i]))) << i);
+    }
+
+    return samples != 0 && samples != 255 && textureLoad(index_lookup, cell).x != NO_VERTEX;
+}
+
+// whether the surface passes through the face perpendicular to `axis` with
+// its lowest corner at `origin`
+fn face_is_crossed(origin: vec3<u32>, axis: u32) -> bool {
+    let u = AXES[(axis + 1) % 3];
+    let v = AXES[(axis + 2) % 3];
+    let first = inside(sample(origin));
+
+    return inside(sample(origin + u)) != first ||
+        inside(sample(origin + v)) != first ||
+        inside(sample(origin + u + v)) != first;
+}
+
+fn vertex_pos(vtx_index: u32) -> vec3<f32> {
+    let v = vertex_buffer[vtx_index];
+    return vec3(v.x, v.y, v.z);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn relax_vertices(@builtin(global_invocation_id) cell: vec3<u32>) {
+    // the dispatch is rounded up to whole workgroups
+    if any(cell >= textureDimensions(index_lookup)) || !is_surface(cell) {
+        return;
+    }
+
+    let size = textureDimensions(index_lookup);
+    let vtx_index = textureLoad(index_lookup, cell).x;
+
+    var sum = vec3(0.0);
+    var links = 0u;
+
+    for (var axis: u32 = 0; axis < 3; axis++) {
+        let dir = AXES[axis];
+
+        // the face towards the neighbour on the negative side
+        if cell[axis] > 0 && face_is_crossed(cell, axis) && is_surface(cell - dir) {
+            sum += vertex_pos(textureLoad(index_lookup, cell - dir).x);
+            links++;
+        }
+
+        // and the positive side
+        if cell[axis] + 1 < size[axis] && face_is_crossed(cell + dir, axis) && is_surface(cell + dir) {
+            sum += vertex_pos(textureLoad(index_lookup, cell + dir).x);
+            links++;
+        }
+    }
+
+    var pos = vertex_pos(vtx_index);
+    if links > 0 {
+        let min_pos = vec3<f32>(cell);
+        pos = clamp(sum / f32(links), min_pos, min_pos + vec3(1.0));
+    }
+
+    relaxed[vtx_index] = vec4(pos, 0.0);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn apply_relaxation(@builtin(global_invocation_id) cell: vec3<u32>) {
+    if any(cell >= textureDimensions(index_lookup)) || !is_surface(cell) {
+        return;
+    }
+
+    let vtx_index = textureLoad(index_lookup, cell).x;
+    let pos = relaxed[vtx_index];
+
+    vertex_buffer[vtx_index].x = pos.x;
+    vertex_buffer[vtx_index].y = pos.y;
+    vertex_buffer[vtx_index].z = pos.z;
+
+    let normal = sample_normal(pos.xyz);
+    vertex_buffer[vtx_index].n_x = normal.x;
+    vertex_buffer[vtx_index].n_y = normal.y;
+    vertex_buffer[vtx_index].n_z = normal.z;
+}
@@ -0,0 +1,122 @@
+#import "shaders/bindings.wgsl"::{vertex_buffer, adaptivity_counts, dispatched_vertex};
+
+// UVs and tangents for the final vertices, mirrors src/uv.rs so keep the
+// two in sync. Runs once the vertices have stopped moving, one workgroup
+// per vertex like compute_adaptivity.
+
+const PI: f32 = 3.14159265358979323846264338327950288;
+const TAU: f32 = 6.28318530717958647692528676655900577;
+
+// same as UvMode in src/uv.rs
+const UV_BOX: u32 = 0u;
+const UV_CYLINDRICAL: u32 = 1u;
+const UV_SPHERICAL: u32 = 2u;
+
+struct UvSettings {
+    center: vec3<f32>,
+    mode: u32,
+    scale: f32,
+};
+
+@group(1) @binding(0) var<uniform> settings: UvSettings;
+
+fn sign_of(x: f32) -> f32 {
+    return select(1.0, -1.0, x < 0.0);
+}
+
+// the directions of u and v in the plane the box projection picks for
+// `normal`, as a matrix of the two columns
+fn box_axes(normal: vec3<f32>) -> mat2x3<f32> {
+    let a = abs(normal);
+
+    if a.x >= a.y && a.x >= a.z {
+        return mat2x3(vec3(0.0, 0.0, -sign_of(normal.x)), vec3(0.0, -1.0, 0.0));
+    } else if a.y >= a.z {
+        return mat2x3(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, sign_of(normal.y)));
+    } else {
+        return mat2x3(vec3(sign_of(normal.z), 0.0, 0.0), vec3(0.0, -1.0, 0.0));
+    }
+}
+
+// how far around the vertical axis `offset` is, from 0 to 1
+fn turn(offset: vec3<f32>) -> f32 {
+    return atan2(offset.z, offset.x) / TAU + 0.5;
+}
+
+// glam's Vec3::any_orthonormal_vector
+fn any_orthonormal(v: vec3<f32>) -> vec3<f32> {
+    let s = select(1.0, -1.0, v.z < 0.0);
+    let a = -1.0 / (s + v.z);
+    let b = v.x * v.y * a;
+    return vec3(b, s + v.y * v.y * a, -v.y);
+}
+
+// `du` made perpendicular to `normal`, with the sign in w that makes the
+// bitangent `cross(normal, tangent) * w` follow `dv`
+fn tangent(normal: vec3<f32>, du: vec3<f32>, dv: vec3<f32>) -> vec4<f32> {
+    var t = du - normal * dot(normal, du);
+
+    // du is along the normal at the poles of the cylinder and sphere, any
+    // tangent will do there
+    if dot(t, t) > 0.0 {
+        t = normalize(t);
+    } else if dot(normal, normal) > 0.0 {
+        t = any_orthonormal(normalize(normal));
+    } else {
+        t = vec3(1.0, 0.0, 0.0);
+    }
+
+    let w = select(1.0, -1.0, dot(cross(normal, t), dv) < 0.0);
+    return vec4(t, w);
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn compute_uvs(@builtin(workgroup_id) wg_id: vec3<u32>) {
+    let vtx_id = dispatched_vertex(wg_id);
+    if vtx_id >= atomicLoad(&adaptivity_counts.vertices) {
+        return;
+    }
+    let vtx = vertex_buffer[vtx_id];
+
+    let normal = vec3(vtx.n_x, vtx.n_y, vtx.n_z);
+    let offset = vec3(vtx.x, vtx.y, vtx.z) - settings.center;
+    let around = vec3(-offset.z, 0.0, offset.x);
+
+    // the UV and the directions its u and v grow in
+    var uv: vec2<f32>;
+    var du: vec3<f32>;
+    var dv: vec3<f32>;
+
+    switch settings.mode {
+        case UV_CYLINDRICAL: {
+            uv = vec2(turn(offset), -offset.y * settings.scale);
+            du = around;
+            dv = vec3(0.0, -1.0, 0.0);
+        }
+        case UV_SPHERICAL: {
+            let dir = select(vec3(0.0), normalize(offset), dot(offset, offset) > 0.0);
+            uv = vec2(turn(offset), acos(clamp(dir.y, -1.0, 1.0)) / PI);
+            du = around;
+            dv = vec3(
+                offset.x * offset.y,
+                -(offset.x * offset.x + offset.z * offset.z),
+                offset.z * offset.y,
+            );
+        }
+        case UV_BOX, default: {
+            let axes = box_axes(normal);
+            uv = vec2(dot(offset, axes[0]), dot(offset, axes[1])) * settings.scale;
+            du = axes[0];
+            dv = axes[1];
+        }
+    }
+
+    let t = tangent(normal, du, dv);
+
+    vertex_buffer[vtx_id].u = uv.x;
+    vertex_buffer[vtx_id].v = uv.y;
+    vertex_buffer[vtx_id].t_x = t.x;
+    vertex_buffer[vtx_id].t_y = t.y;
+    vertex_buffer[vtx_id].t_z = t.z;
+    vertex_buffer[vtx_id].t_w = t.w;
+}
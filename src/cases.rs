@@ -1,275 +1,1151 @@
-use std::sync::LazyLock;
+//! The marching cubes triangle table, expanded at compile time from one
+//! triangulation per configuration that isn't a rotation or reflection of
+//! another, and the subcase tables resolving its ambiguities.
+
+use std::{collections::HashMap, sync::LazyLock};
 
 use arrayvec::ArrayVec;
 
-use crate::Case;
+use crate::{Case, SubcaseIndex, EDGES, VERTICES};
+
+/// Most triangles any case or subcase needs, two tunnels between loops of
+/// six.
+pub const MAX_TRIS: usize = 12;
+
+pub static CASES: LazyLock<[Case; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|i| {
+        let (tris, len) = TABLE[i];
+
+        Case {
+            tris: ArrayVec::try_from(&tris[..len]).unwrap(),
+        }
+    })
+});
+
+// The surface is traced around the faces of the cell, and on faces with
+// two diagonally opposite set corners the set corners are kept apart. Every
+// face is decided by its own corners only, so neighbouring cells always
+// agree on the polygon they share.
+//
+// Like the rest of the table, triangles face away from the set corners.
+const BASE_CASES: [(u8, &[[u8; 3]]); 22] = [
+    (0b00000000, &[]),
+    (0b00000001, &[[0, 3, 8]]),
+    (0b00000011, &[[1, 8, 9], [1, 3, 8]]),
+    (0b00000101, &[[0, 3, 8], [1, 10, 2]]),
+    (0b00000111, &[[2, 9, 10], [2, 8, 9], [2, 3, 8]]),
+    (0b00001111, &[[8, 10, 11], [8, 9, 10]]),
+    (0b00010100, &[[1, 10, 2], [4, 8, 7]]),
+    (0b00010101, &[[0, 7, 4], [0, 3, 7], [1, 10, 2]]),
+    (0b00010111, &[[2, 9, 10], [2, 4, 9], [2, 7, 4], [2, 3, 7]]),
+    (0b00011010, &[[0, 9, 1], [2, 11, 3], [4, 8, 7]]),
+    (0b00011011, &[[1, 4, 9], [1, 7, 4], [1, 11, 7], [1, 2, 11]]),
+    (
+        0b00011110,
+        &[[0, 11, 3], [0, 10, 11], [0, 9, 10], [4, 8, 7]],
+    ),
+    (0b00011111, &[[4, 11, 7], [4, 10, 11], [4, 9, 10]]),
+    (0b00111100, &[[1, 11, 3], [1, 10, 11], [5, 8, 7], [5, 9, 8]]),
+    (
+        0b00111101,
+        &[[0, 5, 9], [0, 7, 5], [0, 11, 7], [0, 10, 11], [0, 1, 10]],
+    ),
+    (0b00111111, &[[5, 11, 7], [5, 10, 11]]),
+    (0b01011010, &[[0, 9, 1], [2, 11, 3], [4, 8, 7], [5, 6, 10]]),
+    (
+        0b01011011,
+        &[[1, 4, 9], [1, 7, 4], [1, 11, 7], [1, 2, 11], [5, 6, 10]],
+    ),
+    (0b01011111, &[[4, 11, 7], [4, 6, 11], [4, 5, 6], [4, 9, 5]]),
+    (0b01111101, &[[0, 1, 9], [6, 11, 7]]),
+    (0b01111111, &[[6, 11, 7]]),
+    (0b11111111, &[]),
+];
+
+/// The orderings of the axes, and whether they're an odd permutation.
+const PERMUTATIONS: [([usize; 3], bool); 6] = [
+    ([0, 1, 2], false),
+    ([1, 2, 0], false),
+    ([2, 0, 1], false),
+    ([0, 2, 1], true),
+    ([2, 1, 0], true),
+    ([1, 0, 2], true),
+];
+
+/// Number of rotations and reflections of a cube.
+const SYMMETRIES: usize = PERMUTATIONS.len() * 8;
+
+const TABLE: [([[u8; 3]; MAX_TRIS], usize); 256] = expand_base_cases();
+
+const fn expand_base_cases() -> [([[u8; 3]; MAX_TRIS], usize); 256] {
+    let mut table = [([[0; 3]; MAX_TRIS], 0); 256];
+    let mut filled = [false; 256];
+
+    let mut base = 0;
+    while base < BASE_CASES.len() {
+        let (case, tris) = BASE_CASES[base];
+
+        let mut symmetry = 0;
+        while symmetry < SYMMETRIES {
+            let (corners, mirrored) = map_corners(symmetry);
+            let edges = map_edges(&corners);
+            let mapped = map_case(case, &corners) as usize;
+
+            if !filled[mapped] {
+                let mut i = 0;
+                while i < tris.len() {
+                    let [a, b, c] = tris[i];
+                    let (a, b, c) = (edges[a as usize], edges[b as usize], edges[c as usize]);
+
+                    // mirroring turns the triangles inside out
+                    table[mapped].0[i] = if mirrored { [a, c, b] } else { [a, b, c] };
+                    i += 1;
+                }
+
+                table[mapped].1 = tris.len();
+                filled[mapped] = true;
+            }
+
+            symmetry += 1;
+        }
+
+        base += 1;
+    }
+
+    let mut case = 0;
+    while case < 256 {
+        assert!(filled[case], "BASE_CASES doesn't cover every case");
+        case += 1;
+    }
+
+    table
+}
+
+/// Where each corner ends up, and whether the symmetry is a reflection.
+const fn map_corners(symmetry: usize) -> ([usize; 8], bool) {
+    let (axes, odd) = PERMUTATIONS[symmetry / 8];
+    let flips = symmetry % 8;
+
+    let mut corners = [0; 8];
+
+    let mut i = 0;
+    while i < 8 {
+        let (x, y, z) = VERTICES[i];
+        let pos = [x, y, z];
+
+        let mut mapped = [pos[axes[0]], pos[axes[1]], pos[axes[2]]];
+
+        let mut axis = 0;
+        while axis < 3 {
+            if flips & (1 << axis) != 0 {
+                mapped[axis] = 1 - mapped[axis];
+            }
+            axis += 1;
+        }
+
+        corners[i] = corner_index(mapped);
+        i += 1;
+    }
+
+    (corners, odd != (flips.count_ones() % 2 == 1))
+}
+
+const fn map_edges(corners: &[usize; 8]) -> [u8; 12] {
+    let mut edges = [0; 12];
+
+    let mut i = 0;
+    while i < 12 {
+        let (a, b) = EDGES[i];
+        edges[i] = edge_index(corners[a], corners[b]);
+        i += 1;
+    }
+
+    edges
+}
+
+const fn map_case(case: u8, corners: &[usize; 8]) -> u8 {
+    let mut mapped = 0;
+
+    let mut i = 0;
+    while i < 8 {
+        if case & (1 << i) != 0 {
+            mapped |= 1 << corners[i];
+        }
+        i += 1;
+    }
+
+    mapped
+}
+
+const fn corner_index(pos: [u32; 3]) -> usize {
+    let mut i = 0;
+    while i < 8 {
+        let (x, y, z) = VERTICES[i];
+        if x == pos[0] && y == pos[1] && z == pos[2] {
+            return i;
+        }
+        i += 1;
+    }
+
+    panic!("not a corner of the cube")
+}
+
+const fn edge_index(a: usize, b: usize) -> u8 {
+    let mut i = 0;
+    while i < 12 {
+        let (v0, v1) = EDGES[i];
+        if (v0 == a && v1 == b) || (v0 == b && v1 == a) {
+            return i as u8;
+        }
+        i += 1;
+    }
+
+    panic!("not an edge of the cube")
+}
+
+/// The corners of each face, counterclockwise seen from outside the cell.
+/// In the order -x, +x, -y, +y, -z, +z.
+pub const FACES: [[usize; 4]; 6] = [
+    [0, 4, 7, 3],
+    [1, 2, 6, 5],
+    [0, 1, 5, 4],
+    [2, 3, 7, 6],
+    [0, 3, 2, 1],
+    [4, 5, 6, 7],
+];
+
+/// Triangulations of every case for every way its ambiguous faces can be
+/// decided, keyed by [`SubcaseIndex::case`] and [`SubcaseIndex::faces`].
+pub static SUBCASES: LazyLock<HashMap<(u8, u8), Subcases>> = LazyLock::new(make_subcases);
+
+pub struct Subcases {
+    /// Pairs of corners with the same sign that the faces leave in
+    /// different regions, but that the interior of the cell can connect
+    /// with a tunnel. Bit `i` of [`SubcaseIndex::tunnels`] is set if the
+    /// `i`th pair is connected.
+    pub tunnels: Vec<(usize, usize)>,
+    /// Indexed by [`SubcaseIndex::tunnels`].
+    pub cases: Vec<Case>,
+    /// The separate pieces of surface without any tunnels, for manifold
+    /// dual contouring. Only depends on the faces, so neighbouring cells
+    /// split the surface on the face between them the same way.
+    pub components: EdgeComponents,
+}
+
+impl SubcaseIndex {
+    pub fn case(&self) -> &'static Case {
+        &SUBCASES[&(self.case, self.faces)].cases[self.tunnels as usize]
+    }
+}
+
+/// Import path of the module generated by [`edge_components_wgsl`].
+const EDGE_COMPONENTS_IMPORT_PATH: &str = "contouring::edge_components";
+
+/// Most separate pieces of surface any case in [`CASES`] or [`SUBCASES`]
+/// has, which is what fits in the bits [`edge_components_wgsl`] gives each edge.
+pub const MAX_COMPONENTS: usize = 4;
+
+const COMPONENT_BITS: u32 = MAX_COMPONENTS.ilog2();
+
+/// The connected pieces of surface in each case of [`CASES`]. Manifold dual
+/// contouring decides the ambiguous faces first and uses
+/// [`Subcases::components`] instead.
+pub static EDGE_COMPONENTS: LazyLock<[EdgeComponents; 256]> =
+    LazyLock::new(|| std::array::from_fn(|case| EdgeComponents::new(&CASES[case])));
+
+#[derive(Clone, Copy, Debug)]
+pub struct EdgeComponents {
+    pub count: u8,
+    /// The piece each edge belongs to, numbered in the order of their
+    /// first triangle. `u8::MAX` for edges the surface doesn't cross.
+    pub edges: [u8; 12],
+}
+
+impl EdgeComponents {
+    fn new(case: &Case) -> EdgeComponents {
+        let mut parent: [usize; 12] = std::array::from_fn(|i| i);
+        for tri in &case.tris {
+            union(&mut parent, tri[0] as usize, tri[1] as usize);
+            union(&mut parent, tri[0] as usize, tri[2] as usize);
+        }
 
-pub static CASES: LazyLock<[Case; 256]> = LazyLock::new(|| make_tris());
+        let mut components = EdgeComponents {
+            count: 0,
+            edges: [u8::MAX; 12],
+        };
 
-fn make_case(a: &[[u8; 3]]) -> Case {
-    Case {
-        tris: ArrayVec::try_from(a).unwrap(),
+        for &edge in case.tris.iter().flatten() {
+            let root = find(&mut parent, edge as usize);
+
+            if components.edges[root] == u8::MAX {
+                components.edges[root] = components.count;
+                components.count += 1;
+            }
+
+            components.edges[edge as usize] = components.edges[root];
+        }
+
+        components
+    }
+}
+
+impl EdgeComponents {
+    /// [`COMPONENT_BITS`] for each edge, the count above them.
+    fn pack(&self) -> u32 {
+        let edges = self
+            .edges
+            .iter()
+            .enumerate()
+            .filter(|&(_, &component)| component != u8::MAX)
+            .fold(0, |bits, (edge, &component)| {
+                bits | ((component as u32) << (edge as u32 * COMPONENT_BITS))
+            });
+
+        edges | ((self.count as u32) << 24)
+    }
+}
+
+/// WGSL module exporting [`Subcases::components`] packed into a `u32` per
+/// subcase, and `AXIS_EDGES` to find the edges of a cell by their start and
+/// axis. Unpacked by `manifold.wgsl`.
+pub fn edge_components_wgsl() -> String {
+    let mut offsets = Vec::new();
+    let mut packed = Vec::new();
+
+    for case in 0..=255 {
+        let ambiguous = ambiguous_faces(case);
+
+        // the offset of the case's subcases, and which of its faces pick
+        // one of them
+        offsets.push(format!(
+            "{}u",
+            packed.len() as u32 | ((ambiguous as u32) << 16)
+        ));
+
+        // ordered by the decided faces with the others squeezed out, see
+        // cell_components in manifold.wgsl
+        for rank in 0..1u8 << ambiguous.count_ones() {
+            let faces = deposit(rank, ambiguous);
+            let components = &SUBCASES[&(case, faces)].components;
+            packed.push(format!("{}u", components.pack()));
+        }
+    }
+
+    // indexed by axis * 4 + the offsets of the start along the next two
+    // axes
+    let axis_edges = (0..12usize)
+        .map(|i| {
+            let (axis, u, v) = (i / 4, (i % 2) as u32, (i / 2 % 2) as u32);
+            let mut start = [0; 3];
+            start[(axis + 1) % 3] = u;
+            start[(axis + 2) % 3] = v;
+            let mut end = start;
+            end[axis] = 1;
+
+            let (a, b) = (corner_index(start), corner_index(end));
+            format!("{}u", edge_index(a, b))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "#define_import_path {EDGE_COMPONENTS_IMPORT_PATH}\n\n\
+         const MAX_COMPONENTS: u32 = {MAX_COMPONENTS}u;\n\n\
+         const COMPONENT_BITS: u32 = {COMPONENT_BITS}u;\n\n\
+         const SUBCASE_OFFSETS: array<u32, 256> = array<u32, 256>({});\n\n\
+         const SUBCASE_COMPONENTS: array<u32, {}> = array<u32, {}>({});\n\n\
+         const AXIS_EDGES: array<u32, 12> = array<u32, 12>({axis_edges});\n",
+        offsets.join(", "),
+        packed.len(),
+        packed.len(),
+        packed.join(", "),
+    )
+}
+
+/// Spreads the low bits of `bits` over the set bits of `mask`, lowest
+/// first.
+fn deposit(bits: u8, mask: u8) -> u8 {
+    let mut deposited = 0;
+    let mut bit = 0;
+
+    for i in 0..8 {
+        if mask & (1 << i) != 0 {
+            if bits & (1 << bit) != 0 {
+                deposited |= 1 << i;
+            }
+            bit += 1;
+        }
+    }
+
+    deposited
+}
+
+/// Faces with two diagonally opposite set corners and two unset ones.
+pub fn ambiguous_faces(case: u8) -> u8 {
+    let mut faces = 0;
+
+    for (i, face) in FACES.iter().enumerate() {
+        let set = face.map(|corner| case & (1 << corner) != 0);
+        if set[0] == set[2] && set[1] == set[3] && set[0] != set[1] {
+            faces |= 1 << i;
+        }
+    }
+
+    faces
+}
+
+/// The asymptotic decider, whether the set corners of an ambiguous face
+/// are connected across it. `values` are in the order of [`FACES`].
+///
+/// Only depends on the products of the diagonals, so both cells sharing
+/// the face always come to the same decision.
+pub fn joins_set_corners(values: [f32; 4]) -> bool {
+    let (a, b) = (values[0] * values[2], values[1] * values[3]);
+
+    // the value at the bilinear saddle is the difference of the products
+    // over the set corners minus the unset ones, a positive divisor
+    if values[0] > 0.0 {
+        a > b
+    } else {
+        b > a
+    }
+}
+
+/// Whether set corners `a` and `b` are connected through the trilinear
+/// interpolation of `values`, in the cell or on its faces.
+///
+/// Every slice of the cell at a constant z has its set region connected
+/// to the vertical edges, so two corners are connected iff some slice
+/// connects their vertical edges while both are set.
+pub fn trilinear_connected(values: [f32; 8], a: usize, b: usize) -> bool {
+    // linear along the vertical edges
+    let edge = |k: usize, t: f32| values[k] + (values[k + 4] - values[k]) * t;
+
+    let mut parent = [0, 1, 2, 3];
+
+    for k in 0..4 {
+        // adjacent edges are connected along the side of the slice
+        let l = (k + 1) % 4;
+
+        let (dk, dl) = (values[k + 4] - values[k], values[l + 4] - values[l]);
+        let mut ts = vec![0.0, 1.0];
+        if dk != dl {
+            ts.push((values[l] - values[k]) / (dk - dl));
+        }
+
+        let overlap = ts
+            .into_iter()
+            .filter(|t| (0.0..=1.0).contains(t))
+            .any(|t| edge(k, t) > 0.0 && edge(l, t) > 0.0);
+
+        if overlap {
+            union(&mut parent, k, l);
+        }
+    }
+
+    for (k, l) in [(0, 2), (1, 3)] {
+        let (m, n) = ((k + 1) % 4, (k + 3) % 4);
+
+        // where both are set
+        let (mut lo, mut hi) = (0.0f32, 1.0f32);
+        for e in [k, l] {
+            let (v0, d) = (values[e], values[e + 4] - values[e]);
+            if d == 0.0 {
+                if v0 <= 0.0 {
+                    hi = -1.0;
+                }
+            } else if d > 0.0 {
+                lo = lo.max(-v0 / d);
+            } else {
+                hi = hi.min(-v0 / d);
+            }
+        }
+
+        if lo > hi {
+            continue;
+        }
+
+        // the diagonals are connected where the saddle of the slice is set,
+        // i.e. where this quadratic is positive
+        let saddle = |t: f32| edge(k, t) * edge(l, t) - edge(m, t) * edge(n, t);
+
+        let (c0, c1, c2) = (saddle(0.0), saddle(1.0), saddle(0.5));
+        let quadratic = 2.0 * (c0 + c1) - 4.0 * c2;
+        let linear = c1 - c0 - quadratic;
+
+        let mut ts = vec![lo, hi];
+        if quadratic != 0.0 {
+            ts.push((-linear / (2.0 * quadratic)).clamp(lo, hi));
+        }
+
+        if ts.into_iter().any(|t| saddle(t) > 0.0) {
+            union(&mut parent, k, l);
+        }
     }
+
+    find(&mut parent, a % 4) == find(&mut parent, b % 4)
+}
+
+fn find<const N: usize>(parent: &mut [usize; N], i: usize) -> usize {
+    let mut i = i;
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union<const N: usize>(parent: &mut [usize; N], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    parent[a.max(b)] = a.min(b);
+}
+
+fn make_subcases() -> HashMap<(u8, u8), Subcases> {
+    let mut subcases = HashMap::new();
+
+    for case in 0..=255 {
+        let ambiguous = ambiguous_faces(case);
+
+        // every subset of the ambiguous faces
+        let mut faces = 0u8;
+        loop {
+            let surface = Surface::trace(case, faces);
+            let tunnels = surface.tunnels();
+
+            let cases: Vec<_> = (0..1 << tunnels.len())
+                .map(|mask| surface.triangulate(&tunnels, mask))
+                .collect();
+            let components = EdgeComponents::new(&cases[0]);
+
+            let tunnels = tunnels
+                .iter()
+                .map(|&(a, b)| (surface.outer(a, b), surface.outer(b, a)))
+                .collect();
+
+            subcases.insert(
+                (case, faces),
+                Subcases {
+                    tunnels,
+                    cases,
+                    components,
+                },
+            );
+
+            faces = faces.wrapping_sub(ambiguous) & ambiguous;
+            if faces == 0 {
+                break;
+            }
+        }
+    }
+
+    subcases
 }
 
-// auto-generated using gen.py
-fn make_tris() -> [Case; 256] {
-    [
-        make_case(&[]),
-        make_case(&[[8, 0, 3]]),
-        make_case(&[[1, 0, 9]]),
-        make_case(&[[8, 1, 3], [8, 9, 1]]),
-        make_case(&[[10, 2, 1]]),
-        make_case(&[[8, 0, 3], [1, 10, 2]]),
-        make_case(&[[9, 2, 0], [9, 10, 2]]),
-        make_case(&[[3, 8, 2], [2, 8, 10], [10, 8, 9]]),
-        make_case(&[[3, 2, 11]]),
-        make_case(&[[0, 2, 8], [2, 11, 8]]),
-        make_case(&[[1, 0, 9], [2, 11, 3]]),
-        make_case(&[[2, 9, 1], [11, 9, 2], [8, 9, 11]]),
-        make_case(&[[3, 10, 11], [3, 1, 10]]),
-        make_case(&[[1, 10, 0], [0, 10, 8], [8, 10, 11]]),
-        make_case(&[[0, 11, 3], [9, 11, 0], [10, 11, 9]]),
-        make_case(&[[8, 9, 11], [11, 9, 10]]),
-        make_case(&[[7, 4, 8]]),
-        make_case(&[[3, 7, 0], [7, 4, 0]]),
-        make_case(&[[7, 4, 8], [9, 1, 0]]),
-        make_case(&[[9, 1, 4], [4, 1, 7], [7, 1, 3]]),
-        make_case(&[[7, 4, 8], [2, 1, 10]]),
-        make_case(&[[4, 3, 7], [4, 0, 3], [2, 1, 10]]),
-        make_case(&[[2, 0, 10], [0, 9, 10], [7, 4, 8]]),
-        make_case(&[[9, 10, 4], [4, 10, 3], [3, 10, 2], [4, 3, 7]]),
-        make_case(&[[4, 8, 7], [3, 2, 11]]),
-        make_case(&[[7, 4, 11], [11, 4, 2], [2, 4, 0]]),
-        make_case(&[[1, 0, 9], [2, 11, 3], [8, 7, 4]]),
-        make_case(&[[2, 11, 1], [1, 11, 9], [9, 11, 7], [9, 7, 4]]),
-        make_case(&[[10, 11, 1], [11, 3, 1], [4, 8, 7]]),
-        make_case(&[[4, 0, 7], [7, 0, 10], [0, 1, 10], [7, 10, 11]]),
-        make_case(&[[7, 4, 8], [0, 11, 3], [9, 11, 0], [10, 11, 9]]),
-        make_case(&[[4, 11, 7], [9, 11, 4], [10, 11, 9]]),
-        make_case(&[[9, 4, 5]]),
-        make_case(&[[9, 4, 5], [0, 3, 8]]),
-        make_case(&[[0, 5, 1], [0, 4, 5]]),
-        make_case(&[[4, 3, 8], [5, 3, 4], [1, 3, 5]]),
-        make_case(&[[5, 9, 4], [10, 2, 1]]),
-        make_case(&[[8, 0, 3], [1, 10, 2], [4, 5, 9]]),
-        make_case(&[[10, 4, 5], [2, 4, 10], [0, 4, 2]]),
-        make_case(&[[3, 10, 2], [8, 10, 3], [5, 10, 8], [4, 5, 8]]),
-        make_case(&[[9, 4, 5], [11, 3, 2]]),
-        make_case(&[[11, 0, 2], [11, 8, 0], [9, 4, 5]]),
-        make_case(&[[5, 1, 4], [1, 0, 4], [11, 3, 2]]),
-        make_case(&[[5, 1, 4], [4, 1, 11], [1, 2, 11], [4, 11, 8]]),
-        make_case(&[[3, 10, 11], [3, 1, 10], [5, 9, 4]]),
-        make_case(&[[9, 4, 5], [1, 10, 0], [0, 10, 8], [8, 10, 11]]),
-        make_case(&[[5, 0, 4], [11, 0, 5], [11, 3, 0], [10, 11, 5]]),
-        make_case(&[[5, 10, 4], [4, 10, 8], [8, 10, 11]]),
-        make_case(&[[9, 7, 5], [9, 8, 7]]),
-        make_case(&[[0, 5, 9], [3, 5, 0], [7, 5, 3]]),
-        make_case(&[[8, 7, 0], [0, 7, 1], [1, 7, 5]]),
-        make_case(&[[7, 5, 3], [3, 5, 1]]),
-        make_case(&[[7, 5, 8], [5, 9, 8], [2, 1, 10]]),
-        make_case(&[[10, 2, 1], [0, 5, 9], [3, 5, 0], [7, 5, 3]]),
-        make_case(&[[8, 2, 0], [5, 2, 8], [10, 2, 5], [7, 5, 8]]),
-        make_case(&[[2, 3, 10], [10, 3, 5], [5, 3, 7]]),
-        make_case(&[[9, 7, 5], [9, 8, 7], [11, 3, 2]]),
-        make_case(&[[0, 2, 9], [9, 2, 7], [7, 2, 11], [9, 7, 5]]),
-        make_case(&[[3, 2, 11], [8, 7, 0], [0, 7, 1], [1, 7, 5]]),
-        make_case(&[[11, 1, 2], [7, 1, 11], [5, 1, 7]]),
-        make_case(&[[3, 1, 11], [11, 1, 10], [8, 7, 9], [9, 7, 5]]),
-        make_case(&[[11, 7, 0], [7, 5, 0], [5, 9, 0], [10, 11, 0], [1, 10, 0]]),
-        make_case(&[[0, 5, 10], [0, 7, 5], [0, 8, 7], [0, 10, 11], [0, 11, 3]]),
-        make_case(&[[10, 11, 5], [11, 7, 5]]),
-        make_case(&[[5, 6, 10]]),
-        make_case(&[[8, 0, 3], [10, 5, 6]]),
-        make_case(&[[0, 9, 1], [5, 6, 10]]),
-        make_case(&[[8, 1, 3], [8, 9, 1], [10, 5, 6]]),
-        make_case(&[[1, 6, 2], [1, 5, 6]]),
-        make_case(&[[6, 2, 5], [2, 1, 5], [8, 0, 3]]),
-        make_case(&[[5, 6, 9], [9, 6, 0], [0, 6, 2]]),
-        make_case(&[[5, 8, 9], [2, 8, 5], [3, 8, 2], [6, 2, 5]]),
-        make_case(&[[3, 2, 11], [10, 5, 6]]),
-        make_case(&[[0, 2, 8], [2, 11, 8], [5, 6, 10]]),
-        make_case(&[[3, 2, 11], [0, 9, 1], [10, 5, 6]]),
-        make_case(&[[5, 6, 10], [2, 9, 1], [11, 9, 2], [8, 9, 11]]),
-        make_case(&[[11, 3, 6], [6, 3, 5], [5, 3, 1]]),
-        make_case(&[[11, 8, 6], [6, 8, 1], [1, 8, 0], [6, 1, 5]]),
-        make_case(&[[5, 0, 9], [6, 0, 5], [3, 0, 6], [11, 3, 6]]),
-        make_case(&[[6, 9, 5], [11, 9, 6], [8, 9, 11]]),
-        make_case(&[[7, 4, 8], [6, 10, 5]]),
-        make_case(&[[3, 7, 0], [7, 4, 0], [10, 5, 6]]),
-        make_case(&[[7, 4, 8], [6, 10, 5], [9, 1, 0]]),
-        make_case(&[[5, 6, 10], [9, 1, 4], [4, 1, 7], [7, 1, 3]]),
-        make_case(&[[1, 6, 2], [1, 5, 6], [7, 4, 8]]),
-        make_case(&[[6, 1, 5], [2, 1, 6], [0, 7, 4], [3, 7, 0]]),
-        make_case(&[[4, 8, 7], [5, 6, 9], [9, 6, 0], [0, 6, 2]]),
-        make_case(&[[2, 3, 9], [3, 7, 9], [7, 4, 9], [6, 2, 9], [5, 6, 9]]),
-        make_case(&[[2, 11, 3], [7, 4, 8], [10, 5, 6]]),
-        make_case(&[[6, 10, 5], [7, 4, 11], [11, 4, 2], [2, 4, 0]]),
-        make_case(&[[1, 0, 9], [8, 7, 4], [3, 2, 11], [5, 6, 10]]),
-        make_case(&[[1, 2, 9], [9, 2, 11], [9, 11, 4], [4, 11, 7], [5, 6, 10]]),
-        make_case(&[[7, 4, 8], [11, 3, 6], [6, 3, 5], [5, 3, 1]]),
-        make_case(&[[11, 0, 1], [11, 4, 0], [11, 7, 4], [11, 1, 5], [11, 5, 6]]),
-        make_case(&[[6, 9, 5], [0, 9, 6], [11, 0, 6], [3, 0, 11], [4, 8, 7]]),
-        make_case(&[[5, 6, 9], [9, 6, 11], [9, 11, 7], [9, 7, 4]]),
-        make_case(&[[4, 10, 9], [4, 6, 10]]),
-        make_case(&[[10, 4, 6], [10, 9, 4], [8, 0, 3]]),
-        make_case(&[[1, 0, 10], [10, 0, 6], [6, 0, 4]]),
-        make_case(&[[8, 1, 3], [6, 1, 8], [6, 10, 1], [4, 6, 8]]),
-        make_case(&[[9, 2, 1], [4, 2, 9], [6, 2, 4]]),
-        make_case(&[[3, 8, 0], [9, 2, 1], [4, 2, 9], [6, 2, 4]]),
-        make_case(&[[0, 4, 2], [2, 4, 6]]),
-        make_case(&[[8, 2, 3], [4, 2, 8], [6, 2, 4]]),
-        make_case(&[[4, 10, 9], [4, 6, 10], [2, 11, 3]]),
-        make_case(&[[11, 8, 2], [2, 8, 0], [6, 10, 4], [4, 10, 9]]),
-        make_case(&[[2, 11, 3], [1, 0, 10], [10, 0, 6], [6, 0, 4]]),
-        make_case(&[[8, 4, 1], [4, 6, 1], [6, 10, 1], [11, 8, 1], [2, 11, 1]]),
-        make_case(&[[3, 1, 11], [11, 1, 4], [1, 9, 4], [11, 4, 6]]),
-        make_case(&[[6, 11, 1], [11, 8, 1], [8, 0, 1], [4, 6, 1], [9, 4, 1]]),
-        make_case(&[[3, 0, 11], [11, 0, 6], [6, 0, 4]]),
-        make_case(&[[4, 11, 8], [4, 6, 11]]),
-        make_case(&[[6, 8, 7], [10, 8, 6], [9, 8, 10]]),
-        make_case(&[[3, 7, 0], [0, 7, 10], [7, 6, 10], [0, 10, 9]]),
-        make_case(&[[1, 6, 10], [0, 6, 1], [7, 6, 0], [8, 7, 0]]),
-        make_case(&[[10, 1, 6], [6, 1, 7], [7, 1, 3]]),
-        make_case(&[[9, 8, 1], [1, 8, 6], [6, 8, 7], [1, 6, 2]]),
-        make_case(&[[9, 7, 6], [9, 3, 7], [9, 0, 3], [9, 6, 2], [9, 2, 1]]),
-        make_case(&[[7, 6, 8], [8, 6, 0], [0, 6, 2]]),
-        make_case(&[[3, 6, 2], [3, 7, 6]]),
-        make_case(&[[3, 2, 11], [6, 8, 7], [10, 8, 6], [9, 8, 10]]),
-        make_case(&[[7, 9, 0], [7, 10, 9], [7, 6, 10], [7, 0, 2], [7, 2, 11]]),
-        make_case(&[[0, 10, 1], [6, 10, 0], [8, 6, 0], [7, 6, 8], [2, 11, 3]]),
-        make_case(&[[1, 6, 10], [7, 6, 1], [11, 7, 1], [2, 11, 1]]),
-        make_case(&[[1, 9, 6], [9, 8, 6], [8, 7, 6], [3, 1, 6], [11, 3, 6]]),
-        make_case(&[[9, 0, 1], [11, 7, 6]]),
-        make_case(&[[0, 11, 3], [6, 11, 0], [7, 6, 0], [8, 7, 0]]),
-        make_case(&[[7, 6, 11]]),
-        make_case(&[[11, 6, 7]]),
-        make_case(&[[3, 8, 0], [11, 6, 7]]),
-        make_case(&[[1, 0, 9], [6, 7, 11]]),
-        make_case(&[[1, 3, 9], [3, 8, 9], [6, 7, 11]]),
-        make_case(&[[10, 2, 1], [6, 7, 11]]),
-        make_case(&[[10, 2, 1], [3, 8, 0], [6, 7, 11]]),
-        make_case(&[[9, 2, 0], [9, 10, 2], [11, 6, 7]]),
-        make_case(&[[11, 6, 7], [3, 8, 2], [2, 8, 10], [10, 8, 9]]),
-        make_case(&[[2, 6, 3], [6, 7, 3]]),
-        make_case(&[[8, 6, 7], [0, 6, 8], [2, 6, 0]]),
-        make_case(&[[7, 2, 6], [7, 3, 2], [1, 0, 9]]),
-        make_case(&[[8, 9, 7], [7, 9, 2], [2, 9, 1], [7, 2, 6]]),
-        make_case(&[[6, 1, 10], [7, 1, 6], [3, 1, 7]]),
-        make_case(&[[8, 0, 7], [7, 0, 6], [6, 0, 1], [6, 1, 10]]),
-        make_case(&[[7, 3, 6], [6, 3, 9], [3, 0, 9], [6, 9, 10]]),
-        make_case(&[[7, 8, 6], [6, 8, 10], [10, 8, 9]]),
-        make_case(&[[8, 11, 4], [11, 6, 4]]),
-        make_case(&[[11, 0, 3], [6, 0, 11], [4, 0, 6]]),
-        make_case(&[[6, 4, 11], [4, 8, 11], [1, 0, 9]]),
-        make_case(&[[1, 3, 9], [9, 3, 6], [3, 11, 6], [9, 6, 4]]),
-        make_case(&[[8, 11, 4], [11, 6, 4], [1, 10, 2]]),
-        make_case(&[[1, 10, 2], [11, 0, 3], [6, 0, 11], [4, 0, 6]]),
-        make_case(&[[2, 9, 10], [0, 9, 2], [4, 11, 6], [8, 11, 4]]),
-        make_case(&[[3, 4, 9], [3, 6, 4], [3, 11, 6], [3, 9, 10], [3, 10, 2]]),
-        make_case(&[[3, 2, 8], [8, 2, 4], [4, 2, 6]]),
-        make_case(&[[2, 4, 0], [6, 4, 2]]),
-        make_case(&[[0, 9, 1], [3, 2, 8], [8, 2, 4], [4, 2, 6]]),
-        make_case(&[[1, 2, 9], [9, 2, 4], [4, 2, 6]]),
-        make_case(&[[10, 3, 1], [4, 3, 10], [4, 8, 3], [6, 4, 10]]),
-        make_case(&[[10, 0, 1], [6, 0, 10], [4, 0, 6]]),
-        make_case(&[[3, 10, 6], [3, 9, 10], [3, 0, 9], [3, 6, 4], [3, 4, 8]]),
-        make_case(&[[9, 10, 4], [10, 6, 4]]),
-        make_case(&[[9, 4, 5], [7, 11, 6]]),
-        make_case(&[[9, 4, 5], [7, 11, 6], [0, 3, 8]]),
-        make_case(&[[0, 5, 1], [0, 4, 5], [6, 7, 11]]),
-        make_case(&[[11, 6, 7], [4, 3, 8], [5, 3, 4], [1, 3, 5]]),
-        make_case(&[[1, 10, 2], [9, 4, 5], [6, 7, 11]]),
-        make_case(&[[8, 0, 3], [4, 5, 9], [10, 2, 1], [11, 6, 7]]),
-        make_case(&[[7, 11, 6], [10, 4, 5], [2, 4, 10], [0, 4, 2]]),
-        make_case(&[[8, 2, 3], [10, 2, 8], [4, 10, 8], [5, 10, 4], [11, 6, 7]]),
-        make_case(&[[2, 6, 3], [6, 7, 3], [9, 4, 5]]),
-        make_case(&[[5, 9, 4], [8, 6, 7], [0, 6, 8], [2, 6, 0]]),
-        make_case(&[[7, 3, 6], [6, 3, 2], [4, 5, 0], [0, 5, 1]]),
-        make_case(&[[8, 1, 2], [8, 5, 1], [8, 4, 5], [8, 2, 6], [8, 6, 7]]),
-        make_case(&[[9, 4, 5], [6, 1, 10], [7, 1, 6], [3, 1, 7]]),
-        make_case(&[[7, 8, 6], [6, 8, 0], [6, 0, 10], [10, 0, 1], [5, 9, 4]]),
-        make_case(&[[3, 0, 10], [0, 4, 10], [4, 5, 10], [7, 3, 10], [6, 7, 10]]),
-        make_case(&[[8, 6, 7], [10, 6, 8], [5, 10, 8], [4, 5, 8]]),
-        make_case(&[[5, 9, 6], [6, 9, 11], [11, 9, 8]]),
-        make_case(&[[11, 6, 3], [3, 6, 0], [0, 6, 5], [0, 5, 9]]),
-        make_case(&[[8, 11, 0], [0, 11, 5], [5, 11, 6], [0, 5, 1]]),
-        make_case(&[[6, 3, 11], [5, 3, 6], [1, 3, 5]]),
-        make_case(&[[10, 2, 1], [5, 9, 6], [6, 9, 11], [11, 9, 8]]),
-        make_case(&[[3, 11, 0], [0, 11, 6], [0, 6, 9], [9, 6, 5], [1, 10, 2]]),
-        make_case(&[[0, 8, 5], [8, 11, 5], [11, 6, 5], [2, 0, 5], [10, 2, 5]]),
-        make_case(&[[11, 6, 3], [3, 6, 5], [3, 5, 10], [3, 10, 2]]),
-        make_case(&[[3, 9, 8], [6, 9, 3], [5, 9, 6], [2, 6, 3]]),
-        make_case(&[[9, 6, 5], [0, 6, 9], [2, 6, 0]]),
-        make_case(&[[6, 5, 8], [5, 1, 8], [1, 0, 8], [2, 6, 8], [3, 2, 8]]),
-        make_case(&[[2, 6, 1], [6, 5, 1]]),
-        make_case(&[[6, 8, 3], [6, 9, 8], [6, 5, 9], [6, 3, 1], [6, 1, 10]]),
-        make_case(&[[1, 10, 0], [0, 10, 6], [0, 6, 5], [0, 5, 9]]),
-        make_case(&[[3, 0, 8], [6, 5, 10]]),
-        make_case(&[[10, 6, 5]]),
-        make_case(&[[5, 11, 10], [5, 7, 11]]),
-        make_case(&[[5, 11, 10], [5, 7, 11], [3, 8, 0]]),
-        make_case(&[[11, 10, 7], [10, 5, 7], [0, 9, 1]]),
-        make_case(&[[5, 7, 10], [10, 7, 11], [9, 1, 8], [8, 1, 3]]),
-        make_case(&[[2, 1, 11], [11, 1, 7], [7, 1, 5]]),
-        make_case(&[[3, 8, 0], [2, 1, 11], [11, 1, 7], [7, 1, 5]]),
-        make_case(&[[2, 0, 11], [11, 0, 5], [5, 0, 9], [11, 5, 7]]),
-        make_case(&[[2, 9, 5], [2, 8, 9], [2, 3, 8], [2, 5, 7], [2, 7, 11]]),
-        make_case(&[[10, 3, 2], [5, 3, 10], [7, 3, 5]]),
-        make_case(&[[10, 0, 2], [7, 0, 10], [8, 0, 7], [5, 7, 10]]),
-        make_case(&[[0, 9, 1], [10, 3, 2], [5, 3, 10], [7, 3, 5]]),
-        make_case(&[[7, 8, 2], [8, 9, 2], [9, 1, 2], [5, 7, 2], [10, 5, 2]]),
-        make_case(&[[3, 1, 7], [7, 1, 5]]),
-        make_case(&[[0, 7, 8], [1, 7, 0], [5, 7, 1]]),
-        make_case(&[[9, 5, 0], [0, 5, 3], [3, 5, 7]]),
-        make_case(&[[5, 7, 9], [7, 8, 9]]),
-        make_case(&[[4, 10, 5], [8, 10, 4], [11, 10, 8]]),
-        make_case(&[[3, 4, 0], [10, 4, 3], [10, 5, 4], [11, 10, 3]]),
-        make_case(&[[1, 0, 9], [4, 10, 5], [8, 10, 4], [11, 10, 8]]),
-        make_case(&[[4, 3, 11], [4, 1, 3], [4, 9, 1], [4, 11, 10], [4, 10, 5]]),
-        make_case(&[[1, 5, 2], [2, 5, 8], [5, 4, 8], [2, 8, 11]]),
-        make_case(&[[5, 4, 11], [4, 0, 11], [0, 3, 11], [1, 5, 11], [2, 1, 11]]),
-        make_case(&[[5, 11, 2], [5, 8, 11], [5, 4, 8], [5, 2, 0], [5, 0, 9]]),
-        make_case(&[[5, 4, 9], [2, 3, 11]]),
-        make_case(&[[3, 4, 8], [2, 4, 3], [5, 4, 2], [10, 5, 2]]),
-        make_case(&[[5, 4, 10], [10, 4, 2], [2, 4, 0]]),
-        make_case(&[[2, 8, 3], [4, 8, 2], [10, 4, 2], [5, 4, 10], [0, 9, 1]]),
-        make_case(&[[4, 10, 5], [2, 10, 4], [1, 2, 4], [9, 1, 4]]),
-        make_case(&[[8, 3, 4], [4, 3, 5], [5, 3, 1]]),
-        make_case(&[[1, 5, 0], [5, 4, 0]]),
-        make_case(&[[5, 0, 9], [3, 0, 5], [8, 3, 5], [4, 8, 5]]),
-        make_case(&[[5, 4, 9]]),
-        make_case(&[[7, 11, 4], [4, 11, 9], [9, 11, 10]]),
-        make_case(&[[8, 0, 3], [7, 11, 4], [4, 11, 9], [9, 11, 10]]),
-        make_case(&[[0, 4, 1], [1, 4, 11], [4, 7, 11], [1, 11, 10]]),
-        make_case(&[[10, 1, 4], [1, 3, 4], [3, 8, 4], [11, 10, 4], [7, 11, 4]]),
-        make_case(&[[9, 4, 1], [1, 4, 2], [2, 4, 7], [2, 7, 11]]),
-        make_case(&[[1, 9, 2], [2, 9, 4], [2, 4, 11], [11, 4, 7], [3, 8, 0]]),
-        make_case(&[[11, 4, 7], [2, 4, 11], [0, 4, 2]]),
-        make_case(&[[7, 11, 4], [4, 11, 2], [4, 2, 3], [4, 3, 8]]),
-        make_case(&[[10, 9, 2], [2, 9, 7], [7, 9, 4], [2, 7, 3]]),
-        make_case(&[[2, 10, 7], [10, 9, 7], [9, 4, 7], [0, 2, 7], [8, 0, 7]]),
-        make_case(&[[10, 4, 7], [10, 0, 4], [10, 1, 0], [10, 7, 3], [10, 3, 2]]),
-        make_case(&[[8, 4, 7], [10, 1, 2]]),
-        make_case(&[[4, 1, 9], [7, 1, 4], [3, 1, 7]]),
-        make_case(&[[8, 0, 7], [7, 0, 1], [7, 1, 9], [7, 9, 4]]),
-        make_case(&[[0, 7, 3], [0, 4, 7]]),
-        make_case(&[[8, 4, 7]]),
-        make_case(&[[9, 8, 10], [10, 8, 11]]),
-        make_case(&[[3, 11, 0], [0, 11, 9], [9, 11, 10]]),
-        make_case(&[[0, 10, 1], [8, 10, 0], [11, 10, 8]]),
-        make_case(&[[11, 10, 3], [10, 1, 3]]),
-        make_case(&[[1, 9, 2], [2, 9, 11], [11, 9, 8]]),
-        make_case(&[[9, 2, 1], [11, 2, 9], [3, 11, 9], [0, 3, 9]]),
-        make_case(&[[8, 2, 0], [8, 11, 2]]),
-        make_case(&[[11, 2, 3]]),
-        make_case(&[[2, 8, 3], [10, 8, 2], [9, 8, 10]]),
-        make_case(&[[0, 2, 9], [2, 10, 9]]),
-        make_case(&[[3, 2, 8], [8, 2, 10], [8, 10, 1], [8, 1, 0]]),
-        make_case(&[[1, 2, 10]]),
-        make_case(&[[3, 1, 8], [1, 9, 8]]),
-        make_case(&[[9, 0, 1]]),
-        make_case(&[[3, 0, 8]]),
-        make_case(&[]),
-    ]
+/// Where the surface of a subcase crosses the faces of the cell.
+struct Surface {
+    /// Crossed edges in the order the triangles wind around them.
+    loops: Vec<Vec<u8>>,
+    /// The region of the faces on the set and the unset side of each loop,
+    /// by their lowest corner.
+    sides: Vec<(usize, usize)>,
+}
+
+impl Surface {
+    fn trace(case: u8, faces: u8) -> Surface {
+        let is_set = |corner: usize| case & (1 << corner) != 0;
+
+        let mut next = [None; 12];
+        let mut regions = [0, 1, 2, 3, 4, 5, 6, 7];
+
+        for &(a, b) in &EDGES {
+            if is_set(a) == is_set(b) {
+                union(&mut regions, a, b);
+            }
+        }
+
+        for (i, face) in FACES.iter().enumerate() {
+            let joined = faces & (1 << i) != 0;
+
+            if joined {
+                // only set by ambiguous faces
+                let set = if is_set(face[0]) { 0 } else { 1 };
+                union(&mut regions, face[set], face[set + 2]);
+            } else if ambiguous_faces(case) & (1 << i) != 0 {
+                let unset = if is_set(face[0]) { 1 } else { 0 };
+                union(&mut regions, face[unset], face[unset + 2]);
+            }
+
+            for j in 0..4 {
+                let (c0, c1) = (face[j], face[(j + 1) % 4]);
+                // segments start on the edges going from set to unset
+                if !is_set(c0) || is_set(c1) {
+                    continue;
+                }
+
+                // cut off the unset corners ahead when the set corners are
+                // joined, otherwise the set corners behind
+                let mut k = j;
+                loop {
+                    if joined {
+                        k = (k + 1) % 4;
+                        if is_set(face[(k + 1) % 4]) {
+                            break;
+                        }
+                    } else {
+                        k = (k + 3) % 4;
+                        if !is_set(face[k]) {
+                            break;
+                        }
+                    }
+                }
+
+                let start = edge_index(c0, c1);
+                let end = edge_index(face[k], face[(k + 1) % 4]);
+                next[end as usize] = Some(start);
+            }
+        }
+
+        let mut loops = Vec::new();
+        let mut sides = Vec::new();
+        let mut seen = [false; 12];
+
+        for first in 0..12 {
+            if seen[first] || next[first].is_none() {
+                continue;
+            }
+
+            let mut edges = Vec::new();
+            let mut edge = first as u8;
+            while !seen[edge as usize] {
+                seen[edge as usize] = true;
+                edges.push(edge);
+                edge = next[edge as usize].unwrap();
+            }
+
+            let (a, b) = EDGES[first];
+            let (set, unset) = if is_set(a) { (a, b) } else { (b, a) };
+
+            loops.push(edges);
+            sides.push((find(&mut regions, set), find(&mut regions, unset)));
+        }
+
+        Surface { loops, sides }
+    }
+
+    /// Pairs of loops with the same region on the same side, which a
+    /// tunnel through that region can join.
+    fn tunnels(&self) -> Vec<(usize, usize)> {
+        let mut tunnels = Vec::new();
+
+        for a in 0..self.loops.len() {
+            for b in a + 1..self.loops.len() {
+                let (sa, sb) = (self.sides[a], self.sides[b]);
+                if sa.0 == sb.0 || sa.1 == sb.1 {
+                    tunnels.push((a, b));
+                }
+            }
+        }
+
+        assert!(tunnels.len() <= 8, "too many tunnels for a u8 mask");
+
+        tunnels
+    }
+
+    /// The region on the other side of loop `a` from the one it shares
+    /// with loop `b`.
+    fn outer(&self, a: usize, b: usize) -> usize {
+        let (sa, sb) = (self.sides[a], self.sides[b]);
+        if sa.0 == sb.0 {
+            sa.1
+        } else {
+            sa.0
+        }
+    }
+
+    fn triangulate(&self, tunnels: &[(usize, usize)], mask: u32) -> Case {
+        let mut tris = ArrayVec::new();
+        let mut used = vec![false; self.loops.len()];
+
+        for (i, &(a, b)) in tunnels.iter().enumerate() {
+            if mask & (1 << i) == 0 || used[a] || used[b] {
+                continue;
+            }
+
+            used[a] = true;
+            used[b] = true;
+            zip_tube(&mut tris, &self.loops[a], &self.loops[b]);
+        }
+
+        for (edges, used) in self.loops.iter().zip(used) {
+            if used {
+                continue;
+            }
+
+            for i in 1..edges.len() - 1 {
+                tris.push([edges[0], edges[i], edges[i + 1]]);
+            }
+        }
+
+        Case { tris }
+    }
+}
+
+/// Triangulates the tube between two loops, keeping their direction so the
+/// tube has the same boundary as the two disks it replaces.
+fn zip_tube(tris: &mut ArrayVec<[u8; 3], MAX_TRIS>, a: &[u8], b: &[u8]) {
+    let pos = |edge: u8| {
+        let (v0, v1) = EDGES[edge as usize];
+        let (v0, v1) = (VERTICES[v0], VERTICES[v1]);
+        [v0.0 + v1.0, v0.1 + v1.1, v0.2 + v1.2]
+    };
+    let distance = |x: u8, y: u8| {
+        let (x, y) = (pos(x), pos(y));
+        (0..3).map(|i| x[i].abs_diff(y[i]).pow(2)).sum::<u32>()
+    };
+
+    let (n, m) = (a.len(), b.len());
+
+    // walk forwards along a and backwards along b, starting across from
+    // each other and advancing both evenly so the tube never pinches
+    let start = (0..m).min_by_key(|&j| distance(a[0], b[j])).unwrap();
+    let (mut a_steps, mut b_steps) = (0, 0);
+
+    while a_steps < n || b_steps < m {
+        let i = a_steps;
+        let j = (start + m - b_steps) % m;
+
+        let step_a =
+            b_steps == m || (a_steps < n && (2 * a_steps + 1) * m <= (2 * b_steps + 1) * n);
+
+        if step_a {
+            tris.push([a[i], a[(i + 1) % n], b[j]]);
+            a_steps += 1;
+        } else {
+            tris.push([b[(j + m - 1) % m], b[j], a[i % n]]);
+            b_steps += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::test::{ShaderComposer, TestGpu, Xorshift};
+
+    fn is_set(case: usize, corner: usize) -> bool {
+        case & (1 << corner) != 0
+    }
+
+    fn is_crossed(case: usize, edge: usize) -> bool {
+        let (a, b) = EDGES[edge];
+        is_set(case, a) != is_set(case, b)
+    }
+
+    // twice the position of the middle of the edge, so it stays integer
+    fn edge_pos(edge: u8) -> IVec3 {
+        let (a, b) = EDGES[edge as usize];
+        (UVec3::from(VERTICES[a]) + UVec3::from(VERTICES[b])).as_ivec3()
+    }
+
+    /// Every case and subcase, with a name for the assertions.
+    fn all_cases() -> impl Iterator<Item = (usize, String, &'static Case)> {
+        let cases = CASES
+            .iter()
+            .enumerate()
+            .map(|(case, tris)| (case, format!("case {case:#010b}"), tris));
+
+        let subcases = SUBCASES.iter().flat_map(|(&(case, faces), subcases)| {
+            subcases
+                .cases
+                .iter()
+                .enumerate()
+                .map(move |(tunnels, tris)| {
+                    let name =
+                        format!("subcase {case:#010b}, faces {faces:#08b}, tunnels {tunnels:#b}");
+                    (case as usize, name, tris)
+                })
+        });
+
+        cases.chain(subcases)
+    }
+
+    fn subcase(case: usize, faces: u8, tunnels: usize) -> &'static Case {
+        &SUBCASES[&(case as u8, faces)].cases[tunnels]
+    }
+
+    /// The directed edges of the triangles that no other triangle shares,
+    /// i.e. where the surface leaves the cell.
+    fn boundary(case: &Case) -> HashSet<(IVec3, IVec3)> {
+        let mut segments = HashSet::new();
+
+        for tri in &case.tris {
+            for i in 0..3 {
+                let segment = (edge_pos(tri[i]), edge_pos(tri[(i + 1) % 3]));
+                assert!(segments.insert(segment), "{segment:?} is used twice");
+            }
+        }
+
+        segments
+            .iter()
+            .filter(|(a, b)| !segments.contains(&(*b, *a)))
+            .copied()
+            .collect()
+    }
+
+    fn on_face(segment: (IVec3, IVec3), axis: usize, side: i32) -> bool {
+        segment.0[axis] == side * 2 && segment.1[axis] == side * 2
+    }
+
+    fn face_boundary(case: &Case, axis: usize, side: i32) -> HashSet<(IVec3, IVec3)> {
+        boundary(case)
+            .into_iter()
+            .filter(|&segment| on_face(segment, axis, side))
+            .collect()
+    }
+
+    /// Pairs of cases whose corners agree on the face between them, with
+    /// the `low` cell below the `high` one along `axis`.
+    fn neighbours(axis: usize) -> impl Iterator<Item = (usize, usize)> {
+        (0..256).flat_map(move |low| {
+            (0..256)
+                .filter(move |&high| {
+                    (0..8)
+                        .filter(|&corner| UVec3::from(VERTICES[corner])[axis] == 1)
+                        .all(|corner| {
+                            let other = UVec3::from(VERTICES[corner]) - UVec3::AXES[axis];
+                            let other = corner_index(other.to_array());
+                            is_set(low, corner) == is_set(high, other)
+                        })
+                })
+                .map(move |high| (low, high))
+        })
+    }
+
+    fn assert_closed(low: &Case, high: &Case, axis: usize, name: &str) {
+        let offset = IVec3::AXES[axis] * 2;
+
+        // in the coordinates of the low cell, and reversed
+        let high_face: HashSet<_> = face_boundary(high, axis, 0)
+            .into_iter()
+            .map(|(a, b)| (b + offset, a + offset))
+            .collect();
+
+        assert_eq!(face_boundary(low, axis, 1), high_face, "{name}");
+    }
+
+    #[test]
+    fn triangles_only_use_crossed_edges() {
+        for (case, name, Case { tris }) in all_cases() {
+            for &edge in tris.iter().flatten() {
+                assert!(
+                    is_crossed(case, edge as usize),
+                    "{name} uses edge {edge} without a sign change"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_crossed_edge_is_covered() {
+        for (case, name, Case { tris }) in all_cases() {
+            for edge in 0..EDGES.len() {
+                if is_crossed(case, edge) {
+                    assert!(
+                        tris.iter().flatten().any(|&e| e as usize == edge),
+                        "{name} doesn't cover edge {edge}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_face_away_from_set_corners() {
+        for (i, &corner) in VERTICES.iter().enumerate() {
+            let case = 1 << i;
+            let tri = CASES[case].tris[0].map(|edge| edge_pos(edge).as_vec3());
+            let normal = (tri[1] - tri[0]).cross(tri[2] - tri[0]);
+
+            let corner = UVec3::from(corner).as_vec3() * 2.0;
+            assert!(normal.dot(tri[0] - corner) > 0.0, "case {case:#010b}");
+        }
+    }
+
+    #[test]
+    fn surface_only_leaves_through_faces() {
+        for (_, name, case) in all_cases() {
+            for segment in boundary(case) {
+                let on_any_face =
+                    (0..3).any(|axis| on_face(segment, axis, 0) || on_face(segment, axis, 1));
+                assert!(on_any_face, "{name} has a hole at {segment:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_are_closed() {
+        for axis in 0..3 {
+            for (low, high) in neighbours(axis) {
+                let name = format!("cases {low:#010b} and {high:#010b} along axis {axis}");
+                assert_closed(&CASES[low], &CASES[high], axis, &name);
+            }
+        }
+    }
+
+    #[test]
+    fn subcase_neighbours_are_closed() {
+        for axis in 0..3 {
+            let (low_face, high_face) = (axis * 2 + 1, axis * 2);
+
+            for (low, high) in neighbours(axis) {
+                let ambiguous = ambiguous_faces(low as u8) & (1 << low_face) != 0;
+
+                for joined in [false, true] {
+                    if joined && !ambiguous {
+                        continue;
+                    }
+
+                    let name = format!(
+                        "subcases {low:#010b} and {high:#010b} along axis {axis}, joined: {joined}"
+                    );
+                    let low = subcase(low, (joined as u8) << low_face, 0);
+                    let high = subcase(high, (joined as u8) << high_face, 0);
+
+                    assert_closed(low, high, axis, &name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn faces_only_depend_on_their_own_decision() {
+        for (&(case, faces), subcases) in SUBCASES.iter() {
+            for (tunnels, tris) in subcases.cases.iter().enumerate() {
+                for face in 0..6 {
+                    let (axis, side) = (face / 2, face as i32 % 2);
+                    let reference = subcase(case as usize, faces & (1 << face), 0);
+
+                    assert_eq!(
+                        face_boundary(tris, axis, side),
+                        face_boundary(reference, axis, side),
+                        "subcase {case:#010b}, faces {faces:#08b}, tunnels {tunnels:#b}, face {face}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn components_fit_their_bits() {
+        let most = EDGE_COMPONENTS.iter().map(|c| c.count as usize).max();
+        assert_eq!(most, Some(MAX_COMPONENTS));
+
+        let most = SUBCASES.values().map(|s| s.components.count as usize).max();
+        assert_eq!(most, Some(MAX_COMPONENTS));
+    }
+
+    #[test]
+    fn fixed_cases_are_subcases() {
+        for case in 0..256 {
+            for face in 0..6 {
+                let (axis, side) = (face / 2, face as i32 % 2);
+
+                assert_eq!(
+                    face_boundary(&CASES[case], axis, side),
+                    face_boundary(subcase(case, 0, 0), axis, side),
+                    "case {case:#010b}, face {face}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn diagonal_corners_tunnel() {
+        // two opposite corners, set strongly enough to meet in the middle
+        let mut values = [-0.2; 8];
+        values[0] = 1.0;
+        values[6] = 1.0;
+        assert!(trilinear_connected(values, 0, 6));
+
+        values[0] = 0.3;
+        values[6] = 0.3;
+        assert!(!trilinear_connected(values, 0, 6));
+    }
+
+    /// `case`, `faces` and whether a tunnel is open, the same for every
+    /// rotation and complement of them.
+    fn canonical_subcase(case: u8, faces: u8, tunnel: bool) -> (u8, u8, bool) {
+        (0..SYMMETRIES)
+            .filter(|&symmetry| !map_corners(symmetry).1)
+            .flat_map(|symmetry| {
+                let (corners, _) = map_corners(symmetry);
+                let mask = |face: &[usize; 4]| face.iter().fold(0u8, |m, &c| m | 1 << c);
+
+                let mut mapped = 0;
+                for (i, face) in FACES.iter().enumerate() {
+                    if faces & (1 << i) != 0 {
+                        let moved = face.iter().fold(0u8, |m, &c| m | 1 << corners[c]);
+                        let j = FACES.iter().position(|face| mask(face) == moved).unwrap();
+                        mapped |= 1 << j;
+                    }
+                }
+
+                // the complement joins exactly the faces that didn't
+                let case = map_case(case, &corners);
+                [
+                    (case, mapped, tunnel),
+                    (!case, mapped ^ ambiguous_faces(case), tunnel),
+                ]
+            })
+            .min()
+            .unwrap()
+    }
+
+    /// [`SUBCASES`] is only built on first use, so this also makes sure
+    /// building it doesn't panic.
+    #[test]
+    fn subcases_reach_the_mc33_configurations() {
+        // subcases of the 15 cases up to rotation and complement, as listed
+        // in Chernyaev's "Marching Cubes 33", by their lowest case
+        let mc33 = [
+            (0b0000_0000, 1), // 0
+            (0b0000_0001, 1), // 1
+            (0b0000_0011, 1), // 2
+            (0b0000_0101, 2), // 3.1, 3.2
+            (0b0001_0100, 2), // 4.1.1, 4.1.2
+            (0b0000_0111, 1), // 5
+            (0b0001_0101, 3), // 6.1.1, 6.1.2, 6.2
+            (0b0001_1010, 5), // 7.1, 7.2, 7.3, 7.4.1, 7.4.2
+            (0b0000_1111, 1), // 8
+            (0b0001_1011, 1), // 9
+            (0b0011_1100, 3), // 10.1.1, 10.1.2, 10.2
+            (0b0001_0111, 1), // 11
+            (0b0001_1110, 4), // 12.1.1, 12.1.2, 12.2, 12.3
+            (0b0101_1010, 6), // 13.1, 13.2, 13.3, 13.4, 13.5.1, 13.5.2
+            (0b0001_1101, 1), // 14
+        ];
+
+        let mut rng = Xorshift::new(1);
+        let mut reached = HashMap::<_, HashSet<_>>::new();
+
+        for case in 0..=255 {
+            let (lowest, ..) = canonical_subcase(case, 0, false);
+            if lowest != case {
+                continue;
+            }
+
+            // every face decision and tunnel the interpolation can make
+            let mut subcases = HashSet::new();
+            for _ in 0..10000 {
+                let values: [f32; 8] = std::array::from_fn(|corner| {
+                    let magnitude = rng.magnitude();
+                    if case & (1 << corner) != 0 {
+                        magnitude
+                    } else {
+                        -magnitude
+                    }
+                });
+
+                let faces = crate::resolve_faces(values, case);
+                let tunnel = SUBCASES[&(case, faces)].tunnels.iter().any(|&(a, b)| {
+                    let values = if values[a] > 0.0 {
+                        values
+                    } else {
+                        values.map(|x| -x)
+                    };
+                    trilinear_connected(values, a, b)
+                });
+                subcases.insert((faces, tunnel));
+            }
+
+            reached.insert(
+                case,
+                subcases
+                    .into_iter()
+                    .map(|(faces, tunnel)| canonical_subcase(case, faces, tunnel))
+                    .collect(),
+            );
+        }
+
+        assert_eq!(reached.len(), mc33.len());
+        for (case, count) in mc33 {
+            assert_eq!(reached[&case].len(), count, "case {case:#010b}");
+        }
+        assert_eq!(reached.values().map(HashSet::len).sum::<usize>(), 33);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn gpu_components_agree_with_subcases() {
+        let gpu = TestGpu::new();
+
+        // every case a few times, with random magnitudes so the ambiguous
+        // faces go both ways
+        let mut rng = Xorshift::new(1);
+        let corners: Vec<[f32; 8]> = (0..256 * 8)
+            .map(|i| {
+                std::array::from_fn(|corner| {
+                    let magnitude = rng.magnitude();
+
+                    if is_set(i % 256, corner) {
+                        magnitude
+                    } else {
+                        -magnitude
+                    }
+                })
+            })
+            .collect();
+
+        let source = ShaderComposer::new()
+            .add_asset("shaders/bindings.wgsl")
+            .add_module(&edge_components_wgsl())
+            .add_asset("shaders/manifold.wgsl")
+            .compose_source(
+                "
+#import \"shaders/manifold.wgsl\"::corners_components
+
+@group(0) @binding(0) var<storage, read> cells: array<array<f32, 8>>;
+@group(0) @binding(1) var<storage, read_write> components: array<u32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < arrayLength(&components) {
+        components[id.x] = corners_components(cells[id.x]);
+    }
+}
+",
+                "components_test.wgsl",
+                default(),
+            );
+
+        let input = gpu.buffer(bytemuck::cast_slice(&corners));
+        let output = gpu.buffer(&vec![0; corners.len() * size_of::<u32>()]);
+
+        gpu.dispatch(
+            source,
+            "main",
+            &[&[
+                (0, input.as_entire_binding()),
+                (1, output.as_entire_binding()),
+            ]],
+            UVec3::new(corners.len().div_ceil(64) as u32, 1, 1),
+        );
+
+        let components: Vec<u32> = bytemuck::cast_slice(&gpu.read_buffer(&output)).to_vec();
+
+        for (i, (values, gpu)) in corners.iter().zip(components).enumerate() {
+            let case = (i % 256) as u8;
+            let faces = crate::resolve_faces(*values, case);
+            let cpu = SUBCASES[&(case, faces)].components.pack();
+
+            assert_eq!(
+                cpu, gpu,
+                "case {case:#010b}, faces {faces:#08b}, corners {values:?}"
+            );
+        }
+    }
 }
@@ -0,0 +1,1090 @@
+//! A world split into chunks of [`CHUNK_SIZE`] cells, each with its own
+//! [`DensityMap`] and mesh entity, streamed in and out around the camera.
+//! Chunks further away are sampled at a lower level of detail, each level
+//! doubling the width of their cells.
+//!
+//! A chunk's own mesh only has the quads whose cells are all inside it. The
+//! quads across its borders come from a seam: an octree put together from
+//! the cells on both sides, whatever their size, and contoured the way
+//! adaptive dual contouring stitches leaves of different sizes. Every chunk
+//! builds the seams on its negative sides, so each quad is built exactly
+//! once.
+//!
+//! Chunks meshed by marching cubes have no seams, the triangles of their
+//! own cells already meet the ones of neighbours at the same level of
+//! detail. Towards neighbours with finer cells, the coarser chunk adds
+//! [transition cells](transition_cells) on the face between them. Those
+//! don't close up along an edge where the finer chunks are diagonally
+//! across from each other, which [`ChunkStreaming`] never does: of the four
+//! chunks around an edge, the two across from each other can't both be
+//! closer to the camera than the other two.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+
+use crate::{
+    camera::PanOrbitState,
+    dual_contouring::{cell_crossings, dual_contour_cells, solve_cell, CellVertices},
+    marching_cubes::{marching_cubes_cells, transition_cells, Ambiguity, TransitionFace},
+    mesh::{ContourMesh, MeshingJobs},
+    octree::{Leaf, Octree},
+    qef::QefSettings,
+    sdf::Sdf,
+    splat::{SplatLayers, SplatMaterial},
+    uv::UvSettings,
+    DensityMap,
+};
+
+/// Number of cells along each axis of a chunk at full detail.
+pub const CHUNK_SIZE: u32 = 16;
+
+/// Samples each chunk copies from its neighbours on every side, at its own
+/// level of detail. The gradients on its border need them for their central
+/// differences.
+pub const APRON: u32 = 1;
+
+/// The coarsest level of detail, with cells `1 << MAX_LOD` wide.
+pub const MAX_LOD: u32 = 3;
+
+#[derive(Clone)]
+pub struct Chunk {
+    /// Samples from `origin - APRON * stride` to
+    /// `origin + CHUNK_SIZE + APRON * stride` along each axis, `stride`
+    /// apart.
+    map: DensityMap,
+    /// Level of detail, the chunk's cells are `1 << lod` wide.
+    lod: u32,
+    /// The entity showing the chunk, spawned when it's first meshed.
+    entity: Option<Entity>,
+    /// Whether any of the samples the chunk owns were edited since it was
+    /// generated or loaded, so it has to be saved when it's unloaded. Only
+    /// chunks at full detail have all their samples, the others have to be
+    /// [refined](ChunkMap::refine) before they're edited, or the edits are
+    /// lost when they're resampled.
+    modified: bool,
+}
+
+/// Every loaded chunk, by chunk coordinates.
+#[derive(Resource, Default)]
+pub struct ChunkMap {
+    chunks: HashMap<IVec3, Chunk>,
+}
+
+/// The field new chunks are sampled from.
+#[derive(Resource, Clone, Deref)]
+pub struct Terrain(pub Sdf);
+
+impl Default for Terrain {
+    fn default() -> Self {
+        // rolling ground below the editor's density map
+        Self(
+            Sdf::Plane {
+                normal: Vec3::Y,
+                distance: -4.0,
+            }
+            .smooth_union(
+                Sdf::Sphere {
+                    center: Vec3::new(12.0, -6.0, -10.0),
+                    radius: 7.0,
+                },
+                3.0,
+            ),
+        )
+    }
+}
+
+/// Which chunks are kept around the camera. Distances are measured from the
+/// camera to the centers of the chunks, in chunks.
+#[derive(Resource, Clone, Debug)]
+pub struct ChunkStreaming {
+    /// Chunks closer than this are loaded, or generated from the
+    /// [`Terrain`] if they were never saved.
+    pub load_radius: f32,
+    /// Chunks further away than this are unloaded, after saving them if
+    /// they were modified. Larger than `load_radius` so chunks on the edge
+    /// don't get unloaded and loaded again as the camera moves around.
+    pub unload_radius: f32,
+    /// Chunks further away than this get cells twice as wide, further than
+    /// twice this four times as wide, and so on up to [`MAX_LOD`].
+    pub lod_distance: f32,
+    /// How many chunks are loaded or resampled per frame, the nearest ones
+    /// first.
+    pub loads_per_frame: usize,
+    /// How many chunks start meshing per frame, the nearest ones first.
+    pub meshes_per_frame: usize,
+    /// How the chunks are meshed.
+    pub mesher: ChunkMesher,
+    /// Where modified chunks are saved, one file per chunk.
+    pub save_dir: PathBuf,
+}
+
+/// How chunks are meshed, both crack free between levels of detail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkMesher {
+    /// Dual contouring, with seams stitched together like the leaves of
+    /// an octree.
+    #[default]
+    DualContouring,
+    /// Marching cubes over [`CASES`](crate::CASES), with transition cells
+    /// towards finer neighbours.
+    MarchingCubes,
+}
+
+impl ChunkMesher {
+    pub const ALL: [ChunkMesher; 2] = [ChunkMesher::DualContouring, ChunkMesher::MarchingCubes];
+}
+
+impl Default for ChunkStreaming {
+    fn default() -> Self {
+        Self {
+            load_radius: 8.0,
+            unload_radius: 9.0,
+            lod_distance: 1.5,
+            loads_per_frame: 16,
+            meshes_per_frame: 8,
+            mesher: ChunkMesher::default(),
+            save_dir: PathBuf::from("chunks"),
+        }
+    }
+}
+
+impl ChunkStreaming {
+    /// The level of detail of a chunk `distance` chunks away.
+    pub fn lod(&self, distance: f32) -> u32 {
+        let mut lod = 0;
+        let mut threshold = self.lod_distance;
+
+        while lod < MAX_LOD && distance > threshold {
+            lod += 1;
+            threshold *= 2.0;
+        }
+
+        lod
+    }
+}
+
+/// The chunk an entity shows.
+#[derive(Component, Clone, Copy, Debug, Deref)]
+pub struct ChunkCoord(pub IVec3);
+
+/// Distance between the samples of a chunk at `lod`.
+fn stride(lod: u32) -> i32 {
+    1 << lod
+}
+
+fn chunk_grid(lod: u32) -> UVec3 {
+    UVec3::splat((CHUNK_SIZE >> lod) + 2 * APRON)
+}
+
+/// World position of the first sample in the map of a chunk at `lod`.
+fn map_min(coord: IVec3, lod: u32) -> IVec3 {
+    ChunkMap::origin(coord) - APRON as i32 * stride(lod)
+}
+
+fn save_path(dir: &Path, coord: IVec3) -> PathBuf {
+    dir.join(format!("{}_{}_{}.bin", coord.x, coord.y, coord.z))
+}
+
+/// Distance from `camera` to the center of the chunk at `coord`, in chunks.
+fn chunk_distance(coord: IVec3, camera: Vec3) -> f32 {
+    (coord.as_vec3() + 0.5).distance(camera / CHUNK_SIZE as f32)
+}
+
+/// The chunks whose maps can overlap the one at `coord`, `coord` itself
+/// excluded. Aprons are never wider than a chunk.
+fn neighbours(coord: IVec3) -> impl Iterator<Item = IVec3> {
+    crate::all_cells(UVec3::splat(3))
+        .into_iter()
+        .map(move |offset| coord + offset.as_ivec3() - 1)
+        .filter(move |&neighbour| neighbour != coord)
+}
+
+impl Chunk {
+    /// Samples `density` over a chunk at `coord` and `lod`.
+    fn sample(coord: IVec3, lod: u32, density: impl Fn(IVec3) -> f32) -> Self {
+        let mut map = DensityMap::new(chunk_grid(lod));
+        let min = map_min(coord, lod);
+
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = density(min + pos.as_ivec3() * stride(lod));
+        }
+
+        Self {
+            map,
+            lod,
+            entity: None,
+            modified: false,
+        }
+    }
+
+    /// Where the sample at world position `pos` is in the chunk's map, if
+    /// it has one there.
+    fn local(&self, coord: IVec3, pos: IVec3) -> Option<UVec3> {
+        let stride = stride(self.lod);
+        let offset = pos - map_min(coord, self.lod);
+
+        if offset.cmplt(IVec3::ZERO).any() || offset % stride != IVec3::ZERO {
+            return None;
+        }
+
+        let local = (offset / stride).as_uvec3();
+        self.map.contains(local).then_some(local)
+    }
+
+    /// The cells of the chunk's map that aren't in its aprons.
+    fn own_cells(&self) -> Range<UVec3> {
+        UVec3::splat(APRON)..UVec3::splat(APRON + (CHUNK_SIZE >> self.lod))
+    }
+
+    /// The quads whose cells are all inside the chunk, in map coordinates.
+    fn contour(&self, qef: &QefSettings) -> ContourMesh {
+        dual_contour_cells(
+            &self.map,
+            |pos| self.map.gradient(pos),
+            CellVertices::One,
+            qef,
+            self.own_cells(),
+        )
+    }
+
+    /// The triangles of the chunk's own cells, in map coordinates.
+    fn march(&self) -> ContourMesh {
+        marching_cubes_cells(
+            &self.map,
+            |pos| self.map.gradient(pos),
+            Ambiguity::Fixed,
+            self.own_cells(),
+        )
+    }
+
+    /// An octree leaf for the chunk's own cell `cell` if the surface passes
+    /// through it, with world position `root` as the octree's origin. The
+    /// vertex is placed the same way as in the chunk's own mesh.
+    fn leaf(&self, coord: IVec3, cell: UVec3, root: IVec3, qef: &QefSettings) -> Option<Leaf> {
+        let local = cell + APRON;
+        let crossings = cell_crossings(&self.map, local, |pos| self.map.gradient(pos));
+        if crossings.is_empty() {
+            return None;
+        }
+
+        let solution = solve_cell(local, &crossings, qef);
+        let normal = crossings.iter().map(|crossing| crossing.normal).sum();
+        let corners = self.map.cell_corners(local);
+
+        let stride = stride(self.lod);
+        let min = map_min(coord, self.lod) - root;
+
+        Some(Leaf::placed(
+            (min + local.as_ivec3() * stride).as_uvec3(),
+            stride as u32,
+            corners,
+            min.as_vec3() + solution.pos * stride as f32,
+            normal,
+            solution.error,
+        ))
+    }
+}
+
+/// Copies the samples owned by the chunk at `from` into the map of the one
+/// at `to`, wherever both have them.
+fn copy_owned((from, source): (IVec3, &Chunk), (to, target): (IVec3, &mut Chunk)) {
+    let stride = stride(target.lod);
+    let min = map_min(to, target.lod);
+    let origin = ChunkMap::origin(from);
+
+    // the samples of the target in the owned range, rounding inwards
+    let first = |pos: IVec3| (pos - min + stride - 1).div_euclid(IVec3::splat(stride));
+    let lo = first(origin).max(IVec3::ZERO);
+    let hi = first(origin + CHUNK_SIZE as i32).min(target.map.grid_size().as_ivec3());
+
+    if lo.cmpge(hi).any() {
+        return;
+    }
+
+    for pos in crate::all_cells((hi - lo).as_uvec3()) {
+        let local = lo.as_uvec3() + pos;
+        let world = min + local.as_ivec3() * stride;
+
+        if let Some(src) = source.local(from, world) {
+            let value = source.map[src];
+            if target.map[local] != value {
+                target.map[local] = value;
+            }
+        }
+    }
+}
+
+impl ChunkMap {
+    /// The chunk owning the sample at `pos`. Samples on a border belong to
+    /// the chunk on its positive side.
+    pub fn chunk_of(pos: IVec3) -> IVec3 {
+        pos.div_euclid(IVec3::splat(CHUNK_SIZE as i32))
+    }
+
+    /// World position of the first sample a chunk owns.
+    pub fn origin(coord: IVec3) -> IVec3 {
+        coord * CHUNK_SIZE as i32
+    }
+
+    /// Samples `terrain` over a new chunk at `coord` and `lod`, unless it's
+    /// already loaded.
+    pub fn generate(&mut self, coord: IVec3, lod: u32, terrain: &Sdf) {
+        if self.chunks.contains_key(&coord) {
+            return;
+        }
+
+        let chunk = Chunk::sample(coord, lod, |pos| terrain.eval(pos.as_vec3()));
+        self.insert(coord, chunk);
+    }
+
+    /// Loads the chunk at `coord` at `lod` from `dir` if it was saved
+    /// there, and generates it otherwise. A chunk that's already loaded at
+    /// another level of detail is resampled, after saving it if it was
+    /// modified, and keeps its entity.
+    pub fn load(&mut self, coord: IVec3, lod: u32, terrain: &Sdf, dir: &Path) {
+        let mut entity = None;
+
+        if let Some(chunk) = self.chunks.get(&coord) {
+            if chunk.lod == lod {
+                return;
+            }
+
+            entity = self.unload(coord, dir);
+        }
+
+        // saves are always at full detail
+        let path = save_path(dir, coord);
+        let saved = match fs::read(&path) {
+            Ok(bytes) => {
+                let densities = bytes
+                    .chunks_exact(4)
+                    .map(|value| f32::from_ne_bytes(value.try_into().unwrap()))
+                    .collect();
+
+                let map = DensityMap::from_densities(chunk_grid(0), densities);
+                if map.is_none() {
+                    log::warn!("{} has the wrong size, regenerating it", path.display());
+                }
+
+                map
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                log::warn!("Couldn't load chunk {coord} from {}: {err}", path.display());
+                None
+            }
+        };
+
+        match saved {
+            Some(saved) => {
+                let min = map_min(coord, 0);
+
+                let chunk = Chunk::sample(coord, lod, |pos| {
+                    let local = pos - min;
+                    if local.cmpge(IVec3::ZERO).all() && saved.contains(local.as_uvec3()) {
+                        saved[local.as_uvec3()]
+                    } else {
+                        terrain.eval(pos.as_vec3())
+                    }
+                });
+
+                self.insert(coord, chunk);
+            }
+            None => self.generate(coord, lod, terrain),
+        }
+
+        self.chunks.get_mut(&coord).unwrap().entity = entity;
+    }
+
+    /// Removes the chunk at `coord`, saving it to `dir` first if it was
+    /// modified. Returns the chunk's entity, which is left to the caller to
+    /// despawn.
+    pub fn unload(&mut self, coord: IVec3, dir: &Path) -> Option<Entity> {
+        let chunk = self.chunks.remove(&coord)?;
+        self.touch_seams(coord);
+
+        if chunk.modified {
+            let path = save_path(dir, coord);
+
+            // native endian, the saves aren't meant to be portable
+            let saved = fs::create_dir_all(dir)
+                .and_then(|()| fs::write(&path, bytemuck::cast_slice(chunk.map.densities())));
+
+            if let Err(err) = saved {
+                log::warn!("Couldn't save chunk {coord} to {}: {err}", path.display());
+            }
+        }
+
+        chunk.entity
+    }
+
+    /// Adds a chunk, bringing the aprons of it and its loaded neighbours up
+    /// to date with the samples of the chunks owning them. Saved aprons go
+    /// stale when a neighbour is edited while the chunk is unloaded.
+    fn insert(&mut self, coord: IVec3, mut chunk: Chunk) {
+        for neighbour in neighbours(coord) {
+            if let Some(source) = self.chunks.get(&neighbour) {
+                copy_owned((neighbour, source), (coord, &mut chunk));
+            }
+        }
+
+        for neighbour in neighbours(coord) {
+            if let Some(target) = self.chunks.get_mut(&neighbour) {
+                copy_owned((coord, &chunk), (neighbour, target));
+            }
+        }
+
+        self.chunks.insert(coord, chunk);
+        self.touch_seams(coord);
+    }
+
+    /// Marks the chunks whose seams or transition cells have cells of the
+    /// one at `coord` for remeshing, the ones on its positive sides and
+    /// the ones across its faces.
+    fn touch_seams(&mut self, coord: IVec3) {
+        let positive = crate::all_cells(UVec3::splat(2))
+            .into_iter()
+            .skip(1)
+            .map(|offset| offset.as_ivec3());
+        let negative = IVec3::AXES.map(|axis| -axis);
+
+        for offset in positive.chain(negative) {
+            if let Some(chunk) = self.chunks.get_mut(&(coord + offset)) {
+                chunk.map.mark_dirty(UVec3::ZERO);
+            }
+        }
+    }
+
+    /// Brings the loaded chunks owning samples from world position `min` to
+    /// `max` to full detail, so all their samples can be edited and saved.
+    /// Streaming takes them back down to their level of detail afterwards,
+    /// saving the edits first.
+    pub fn refine(&mut self, min: IVec3, max: IVec3, terrain: &Sdf, dir: &Path) {
+        let (min, max) = (Self::chunk_of(min), Self::chunk_of(max));
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let coord = IVec3::new(x, y, z);
+                    if self.chunks.get(&coord).is_some_and(|chunk| chunk.lod != 0) {
+                        self.load(coord, 0, terrain, dir);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The sample at world position `pos`, if its chunk is loaded with a
+    /// sample there.
+    pub fn get(&self, pos: IVec3) -> Option<f32> {
+        let coord = Self::chunk_of(pos);
+        let chunk = self.chunks.get(&coord)?;
+
+        chunk.local(coord, pos).map(|local| chunk.map[local])
+    }
+
+    /// Writes the sample at world position `pos` into every loaded chunk
+    /// holding it, the one owning it and the aprons of its neighbours. Does
+    /// nothing if the chunk owning it isn't loaded.
+    pub fn set(&mut self, pos: IVec3, value: f32) {
+        let owner = Self::chunk_of(pos);
+        if !self.chunks.contains_key(&owner) {
+            return;
+        }
+
+        let mut changed = false;
+        self.for_each_holder(pos, |coord, chunk, local| {
+            if chunk.map[local] != value {
+                chunk.map[local] = value;
+                chunk.modified |= coord == owner && chunk.lod == 0;
+                changed = true;
+            }
+        });
+
+        if changed {
+            self.touch_seams(owner);
+        }
+    }
+
+    /// Calls `f` with every loaded chunk whose map has the sample at world
+    /// position `pos`, and the sample's position in that map.
+    fn for_each_holder(&mut self, pos: IVec3, mut f: impl FnMut(IVec3, &mut Chunk, UVec3)) {
+        let apron = IVec3::splat((APRON << MAX_LOD) as i32);
+        let (min, max) = (Self::chunk_of(pos - apron), Self::chunk_of(pos + apron));
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let coord = IVec3::new(x, y, z);
+                    let Some(chunk) = self.chunks.get_mut(&coord) else {
+                        continue;
+                    };
+
+                    if let Some(local) = chunk.local(coord, pos) {
+                        f(coord, chunk, local);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A copy of the chunk at `coord` and its loaded neighbours, all
+    /// [`contour`](Self::contour) needs to mesh it.
+    fn around(&self, coord: IVec3) -> ChunkMap {
+        let chunks = neighbours(coord)
+            .chain([coord])
+            .filter_map(|coord| Some((coord, self.chunks.get(&coord)?.clone())))
+            .collect();
+
+        ChunkMap { chunks }
+    }
+
+    /// The mesh of the chunk at `coord` in its map coordinates. By dual
+    /// contouring its own quads and the seams on its negative sides, by
+    /// marching cubes its own triangles and the transition cells towards
+    /// finer neighbours.
+    pub fn contour(&self, coord: IVec3, mesher: ChunkMesher, qef: &QefSettings) -> ContourMesh {
+        let chunk = &self.chunks[&coord];
+
+        match mesher {
+            ChunkMesher::DualContouring => {
+                let mut mesh = chunk.contour(qef);
+
+                let root = Self::origin(coord) - CHUNK_SIZE as i32;
+                let offset = (root - map_min(coord, chunk.lod)).as_vec3();
+                let scale = stride(chunk.lod) as f32;
+
+                let seam = self.seam(coord, root, qef);
+                append(&mut mesh, seam, |pos| (pos + offset) / scale);
+                mesh
+            }
+            ChunkMesher::MarchingCubes => {
+                let mut mesh = chunk.march();
+                for transitions in self.transitions(coord) {
+                    append(&mut mesh, transitions, |pos| pos);
+                }
+                mesh
+            }
+        }
+    }
+
+    /// The quads across the negative borders of the chunk at `coord`, from
+    /// an octree of its cells along them and the cells of its neighbours
+    /// touching them. Positions are relative to world position `root`, one
+    /// chunk below the chunk's origin.
+    fn seam(&self, coord: IVec3, root: IVec3, qef: &QefSettings) -> ContourMesh {
+        let mut leaves = Vec::new();
+        let mut own = 0;
+
+        // the chunk itself comes first, so its vertices are the first `own`
+        for offset in crate::all_cells(UVec3::splat(2)) {
+            let offset = -offset.as_ivec3();
+            let neighbour = coord + offset;
+            let Some(chunk) = self.chunks.get(&neighbour) else {
+                continue;
+            };
+
+            // the last layer of cells on the sides where the neighbour is
+            // below the chunk, and the first layer of the chunk's own cells
+            let last = (CHUNK_SIZE >> chunk.lod) - 1;
+            let cells = crate::all_cells(UVec3::splat(last + 1))
+                .into_iter()
+                .filter(|&cell| {
+                    (0..3).all(|axis| offset[axis] == 0 || cell[axis] == last)
+                        && (offset != IVec3::ZERO || cell.min_element() == 0)
+                });
+
+            leaves.extend(cells.filter_map(|cell| chunk.leaf(neighbour, cell, root, qef)));
+
+            if offset == IVec3::ZERO {
+                own = leaves.len() as u32;
+            }
+        }
+
+        let chunk = IVec3::splat(CHUNK_SIZE as i32);
+        Octree::from_leaves(2 * CHUNK_SIZE, leaves).contour_where(|start, quad| {
+            // quads of the chunk's own cells are in its own mesh, and the
+            // edges starting outside it belong to other chunks' seams
+            let start = start.as_ivec3();
+            start.cmpge(chunk).all()
+                && start.cmplt(chunk * 2).all()
+                && quad.iter().any(|&vtx| vtx >= own)
+        })
+    }
+
+    /// The transition cells on the faces of the chunk at `coord` towards
+    /// loaded neighbours with finer cells, in its map coordinates.
+    fn transitions(&self, coord: IVec3) -> Vec<ContourMesh> {
+        let chunk = &self.chunks[&coord];
+        let mut meshes = Vec::new();
+
+        for (axis, positive) in (0..3).flat_map(|axis| [(axis, false), (axis, true)]) {
+            let step = if positive { 1 } else { -1 } * IVec3::AXES[axis];
+            let neighbour = coord + step;
+            let Some(fine) = self.chunks.get(&neighbour) else {
+                continue;
+            };
+            if fine.lod >= chunk.lod {
+                continue;
+            }
+
+            // the lowest corner of the face between them
+            let corner = Self::origin(coord) + step.max(IVec3::ZERO) * CHUNK_SIZE as i32;
+            let face = TransitionFace {
+                axis,
+                positive,
+                cells: CHUNK_SIZE >> chunk.lod,
+                ratio: 1 << (chunk.lod - fine.lod),
+                coarse_min: chunk.local(coord, corner).unwrap(),
+                fine_min: fine.local(neighbour, corner).unwrap(),
+            };
+
+            let offset = (map_min(neighbour, fine.lod) - map_min(coord, chunk.lod)).as_vec3();
+            let (fine_stride, stride) = (stride(fine.lod) as f32, stride(chunk.lod) as f32);
+
+            meshes.push(transition_cells(&chunk.map, &fine.map, &face, |pos| {
+                (offset + pos * fine_stride) / stride
+            }));
+        }
+
+        meshes
+    }
+}
+
+/// Adds the triangles of `other` to `mesh`, moving its vertices with
+/// `to_mesh`.
+fn append(mesh: &mut ContourMesh, other: ContourMesh, to_mesh: impl Fn(Vec3) -> Vec3) {
+    let first = mesh.positions.len() as u32;
+
+    mesh.positions
+        .extend(other.positions.into_iter().map(to_mesh));
+    mesh.normals.extend(other.normals);
+    mesh.qef_errors.extend(other.qef_errors);
+    mesh.materials.extend(other.materials);
+    mesh.indices
+        .extend(other.indices.iter().map(|&i| first + i));
+}
+
+pub fn chunk_plugin(app: &mut App) {
+    app.init_resource::<ChunkMap>()
+        .init_resource::<Terrain>()
+        .init_resource::<ChunkStreaming>()
+        .add_systems(Startup, make_chunk_material)
+        .add_systems(Update, stream_chunks)
+        .add_systems(PostUpdate, (apply_chunk_meshes, mesh_chunks).chain());
+}
+
+#[derive(Resource)]
+struct ChunkMaterial(Handle<SplatMaterial>);
+
+fn make_chunk_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<SplatMaterial>>,
+    layers: Res<SplatLayers>,
+) {
+    let material = materials.add(layers.material(StandardMaterial::default()));
+    commands.insert_resource(ChunkMaterial(material));
+}
+
+/// Unloads the chunks that got too far from the camera, and loads the
+/// nearest missing ones or the ones at the wrong level of detail.
+fn stream_chunks(
+    mut commands: Commands,
+    mut chunks: ResMut<ChunkMap>,
+    camera: Query<&Transform, With<PanOrbitState>>,
+    terrain: Res<Terrain>,
+    settings: Res<ChunkStreaming>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let camera = camera.translation;
+
+    let far: Vec<IVec3> = chunks
+        .chunks
+        .keys()
+        .copied()
+        .filter(|&coord| chunk_distance(coord, camera) > settings.unload_radius)
+        .collect();
+
+    for coord in far {
+        if let Some(entity) = chunks.unload(coord, &settings.save_dir) {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    let center = ChunkMap::chunk_of(camera.floor().as_ivec3());
+    let radius = settings.load_radius.ceil() as i32;
+
+    let mut missing = Vec::new();
+    for x in -radius..=radius {
+        for y in -radius..=radius {
+            for z in -radius..=radius {
+                let coord = center + IVec3::new(x, y, z);
+                let distance = chunk_distance(coord, camera);
+                let lod = settings.lod(distance);
+
+                if distance <= settings.load_radius
+                    && chunks
+                        .chunks
+                        .get(&coord)
+                        .is_none_or(|chunk| chunk.lod != lod)
+                {
+                    missing.push((coord, lod));
+                }
+            }
+        }
+    }
+
+    missing.sort_by(|&(a, _), &(b, _)| {
+        chunk_distance(a, camera).total_cmp(&chunk_distance(b, camera))
+    });
+
+    for (coord, lod) in missing.into_iter().take(settings.loads_per_frame) {
+        chunks.load(coord, lod, &terrain, &settings.save_dir);
+    }
+}
+
+/// Starts meshing the nearest chunks whose samples changed, spawning the
+/// entities of new ones. Chunks whose jobs are all busy stay dirty until
+/// one of them finishes.
+fn mesh_chunks(
+    mut commands: Commands,
+    mut chunks: ResMut<ChunkMap>,
+    camera: Query<&Transform, With<PanOrbitState>>,
+    (qef, uv): (Res<QefSettings>, Res<UvSettings>),
+    settings: Res<ChunkStreaming>,
+    material: Res<ChunkMaterial>,
+    mut jobs: Query<&mut MeshingJobs>,
+) {
+    let camera = camera
+        .get_single()
+        .map_or(Vec3::ZERO, |transform| transform.translation);
+    let chunks = chunks.bypass_change_detection();
+
+    if qef.is_changed() || uv.is_changed() || settings.is_changed() {
+        for chunk in chunks.chunks.values_mut() {
+            chunk.map.mark_dirty(UVec3::ZERO);
+        }
+    }
+
+    let mut dirty: Vec<IVec3> = chunks
+        .chunks
+        .iter()
+        .filter(|(_, chunk)| chunk.map.is_dirty())
+        .filter(|(_, chunk)| {
+            chunk
+                .entity
+                .is_none_or(|entity| jobs.get(entity).is_ok_and(|jobs| !jobs.is_full()))
+        })
+        .map(|(&coord, _)| coord)
+        .collect();
+
+    dirty.sort_by(|&a, &b| chunk_distance(a, camera).total_cmp(&chunk_distance(b, camera)));
+
+    for coord in dirty.into_iter().take(settings.meshes_per_frame) {
+        chunks.chunks.get_mut(&coord).unwrap().map.take_dirty();
+
+        // the mesh is in world units from the chunk's origin, so it fits
+        // the entity whichever level of detail it was built at
+        let origin = ChunkMap::origin(coord);
+        let transform = Transform::from_translation(origin.as_vec3());
+
+        let lod = chunks.chunks[&coord].lod;
+        let offset = (map_min(coord, lod) - origin).as_vec3();
+        let scale = stride(lod) as f32;
+
+        let around = chunks.around(coord);
+        let (mesher, qef, uv) = (settings.mesher, *qef, *uv);
+
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let mut contour = around.contour(coord, mesher, &qef);
+            for pos in &mut contour.positions {
+                *pos = *pos * scale + offset;
+            }
+
+            contour.generate_uvs(&uv, &transform);
+            contour
+        });
+
+        let chunk = chunks.chunks.get_mut(&coord).unwrap();
+
+        match chunk.entity {
+            Some(entity) => {
+                jobs.get_mut(entity).unwrap().start(task);
+            }
+            None => {
+                let mut jobs = MeshingJobs::default();
+                jobs.start(task);
+
+                let entity = commands
+                    .spawn((
+                        jobs,
+                        MeshMaterial3d(material.0.clone()),
+                        transform,
+                        ChunkCoord(coord),
+                    ))
+                    .observe(dig)
+                    .id();
+
+                chunk.entity = Some(entity);
+            }
+        }
+    }
+}
+
+/// Gives the chunks the meshes of their finished jobs, see
+/// [`MeshingJobs::take_finished`].
+fn apply_chunk_meshes(
+    mut commands: Commands,
+    mut query: Query<(Entity, Option<&Mesh3d>, &mut MeshingJobs), With<ChunkCoord>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (entity, mesh, mut jobs) in &mut query {
+        let Some(contour) = jobs.take_finished() else {
+            continue;
+        };
+
+        match mesh.and_then(|mesh| meshes.get_mut(&mesh.0)) {
+            Some(mesh) => contour.write_to(mesh),
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(Mesh3d(meshes.add(contour.to_mesh())));
+            }
+        }
+    }
+}
+
+/// Carves a sphere out of the terrain where a chunk is middle clicked, the
+/// other buttons move the camera.
+fn dig(
+    trigger: Trigger<Pointer<Click>>,
+    mut chunks: ResMut<ChunkMap>,
+    terrain: Res<Terrain>,
+    settings: Res<ChunkStreaming>,
+) {
+    const RADIUS: f32 = 2.5;
+
+    let event = trigger.event();
+    let (PointerButton::Middle, Some(hit)) = (event.button, event.hit.position) else {
+        return;
+    };
+
+    let min = (hit - RADIUS).floor().as_ivec3();
+    let max = (hit + RADIUS).ceil().as_ivec3();
+    chunks.refine(min, max, &terrain, &settings.save_dir);
+
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let pos = IVec3::new(x, y, z);
+                let Some(value) = chunks.get(pos) else {
+                    continue;
+                };
+
+                let carved = value.max(RADIUS - pos.as_vec3().distance(hit));
+                if carved != value {
+                    chunks.set(pos, carved);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_writes_into_the_neighbours_aprons() {
+        let mut chunks = ChunkMap::default();
+        let terrain = Sdf::default();
+
+        for x in -1..=1 {
+            chunks.generate(IVec3::new(x, 0, 0), 0, &terrain);
+        }
+
+        let pos = IVec3::new(CHUNK_SIZE as i32 - 1, 3, 3);
+        chunks.set(pos, 42.0);
+
+        let holding = chunks
+            .chunks
+            .iter()
+            .filter(|&(&coord, chunk)| {
+                chunk
+                    .local(coord, pos)
+                    .is_some_and(|local| chunk.map[local] == 42.0)
+            })
+            .count();
+
+        assert_eq!(holding, 2);
+        assert_eq!(chunks.get(pos), Some(42.0));
+    }
+
+    #[test]
+    fn modified_chunks_are_saved_when_unloaded() {
+        let dir = std::env::temp_dir().join(format!("chunks-{}", std::process::id()));
+        let terrain = Sdf::default();
+
+        let mut chunks = ChunkMap::default();
+        chunks.load(IVec3::ZERO, 0, &terrain, &dir);
+        chunks.load(IVec3::X, 0, &terrain, &dir);
+
+        // owned by the first chunk, in the apron of the second
+        let pos = IVec3::new(CHUNK_SIZE as i32 - 1, 3, 3);
+        chunks.set(pos, 42.0);
+
+        chunks.unload(IVec3::X, &dir);
+        assert!(!save_path(&dir, IVec3::X).exists());
+
+        chunks.unload(IVec3::ZERO, &dir);
+        assert!(save_path(&dir, IVec3::ZERO).exists());
+
+        // the second chunk gets its apron from the first one, even though
+        // it was never saved with the edit
+        chunks.load(IVec3::X, 0, &terrain, &dir);
+        chunks.load(IVec3::ZERO, 0, &terrain, &dir);
+        assert_eq!(chunks.get(pos), Some(42.0));
+
+        let neighbour = &chunks.chunks[&IVec3::X];
+        assert_eq!(neighbour.map[neighbour.local(IVec3::X, pos).unwrap()], 42.0);
+
+        // and the edit survives a trip through a lower level of detail
+        let pos = IVec3::new(4, 2, 6);
+        chunks.set(pos, 42.0);
+        chunks.load(IVec3::ZERO, 1, &terrain, &dir);
+        chunks.load(IVec3::ZERO, 0, &terrain, &dir);
+        assert_eq!(chunks.get(pos), Some(42.0));
+
+        // edits to chunks at lower detail are made at full detail
+        let pos = IVec3::new(5, 3, 7);
+        chunks.load(IVec3::ZERO, 2, &terrain, &dir);
+        assert_eq!(chunks.get(pos), None);
+
+        chunks.refine(pos, pos, &terrain, &dir);
+        chunks.set(pos, 42.0);
+        chunks.load(IVec3::ZERO, 2, &terrain, &dir);
+        chunks.load(IVec3::ZERO, 0, &terrain, &dir);
+        assert_eq!(chunks.get(pos), Some(42.0));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lower_detail_chunks_sample_every_other_point() {
+        let terrain = Sdf::default();
+        let mut chunks = ChunkMap::default();
+        chunks.generate(IVec3::ZERO, 1, &terrain);
+
+        assert_eq!(chunks.chunks[&IVec3::ZERO].map.size(), chunk_grid(1));
+        assert!(chunks.get(IVec3::new(2, 4, 6)).is_some());
+        assert!(chunks.get(IVec3::new(2, 3, 6)).is_none());
+
+        let settings = ChunkStreaming::default();
+        assert_eq!(settings.lod(0.0), 0);
+        assert_eq!(settings.lod(settings.lod_distance * 1.5), 1);
+        assert_eq!(settings.lod(1000.0), MAX_LOD);
+    }
+
+    /// Checks that the meshes of all the loaded chunks form a closed
+    /// surface together, with no triangle built twice.
+    fn assert_seamless(chunks: &ChunkMap, mesher: ChunkMesher) {
+        let qef = QefSettings::default();
+
+        // weld the vertices of all the chunks by position, the seams place
+        // their own copies of the vertices on both sides, up to rounding
+        let mut welded: Vec<Vec3> = Vec::new();
+        let mut edges = HashMap::<_, usize>::new();
+
+        for (&coord, chunk) in &chunks.chunks {
+            let mesh = chunks.contour(coord, mesher, &qef);
+            let min = map_min(coord, chunk.lod).as_vec3();
+            let scale = stride(chunk.lod) as f32;
+
+            let ids: Vec<u32> = mesh
+                .positions
+                .iter()
+                .map(|&pos| {
+                    let pos = pos * scale + min;
+                    match welded.iter().position(|other| other.distance(pos) < 1e-4) {
+                        Some(id) => id as u32,
+                        None => {
+                            welded.push(pos);
+                            welded.len() as u32 - 1
+                        }
+                    }
+                })
+                .collect();
+
+            for tri in mesh.indices.chunks(3) {
+                for i in 0..3 {
+                    let edge = (ids[tri[i] as usize], ids[tri[(i + 1) % 3] as usize]);
+                    *edges.entry(edge).or_default() += 1;
+                }
+            }
+        }
+
+        assert!(!edges.is_empty());
+
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "{mesher:?}, {a} -> {b}");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "{mesher:?}, {a} -> {b}");
+        }
+    }
+
+    // off the grid, across the corner shared by eight chunks
+    fn sphere() -> Sdf {
+        Sdf::Sphere {
+            center: Vec3::splat(0.3),
+            radius: 5.0,
+        }
+    }
+
+    #[test]
+    fn chunk_borders_are_seamless() {
+        let mut chunks = ChunkMap::default();
+        for coord in crate::all_cells(UVec3::splat(2)) {
+            chunks.generate(coord.as_ivec3() - 1, 0, &sphere());
+        }
+
+        for mesher in ChunkMesher::ALL {
+            assert_seamless(&chunks, mesher);
+        }
+    }
+
+    #[test]
+    fn borders_between_levels_of_detail_are_seamless() {
+        let mut chunks = ChunkMap::default();
+        for coord in crate::all_cells(UVec3::splat(2)) {
+            let lod = (coord.x + coord.y + coord.z) % 2;
+            chunks.generate(coord.as_ivec3() - 1, lod, &sphere());
+        }
+
+        // transition cells leave folds where the finer chunks meet across
+        // an edge, see the module docs
+        assert_seamless(&chunks, ChunkMesher::DualContouring);
+    }
+
+    #[test]
+    fn levels_of_detail_growing_away_from_a_corner_are_seamless() {
+        // the way streaming places them, unlike the checkerboard above
+        for far in 1..=3 {
+            let mut chunks = ChunkMap::default();
+            for coord in crate::all_cells(UVec3::splat(2)) {
+                let lod = if coord.element_sum() < far { 0 } else { 1 };
+                chunks.generate(coord.as_ivec3() - 1, lod, &sphere());
+            }
+
+            for mesher in ChunkMesher::ALL {
+                assert_seamless(&chunks, mesher);
+            }
+        }
+    }
+}
@@ -0,0 +1,453 @@
+//! CPU reference implementation of the dual contouring done by
+//! `contour.wgsl` and `adaptivity.wgsl`, for validating the GPU output and
+//! for machines without a usable GPU.
+
+use std::ops::Range;
+
+use arrayvec::ArrayVec;
+use bevy::prelude::*;
+
+use crate::{
+    cases::{EdgeComponents, SUBCASES},
+    mesh::{dominant_material, pack_material, ContourMesh},
+    qef::{Plane, Qef, QefSettings, QefSolution},
+    resolve_faces, sample_density_map, DensityMap, EDGES, VERTICES,
+};
+
+/// Where the surface crosses an edge of a cell.
+#[derive(Clone, Copy, Debug)]
+pub struct EdgeCrossing {
+    /// Index of the edge in [`EDGES`].
+    pub edge: usize,
+    pub pos: Vec3,
+    pub normal: Vec3,
+}
+
+/// How many vertices [`contour`] gives each cell the surface passes
+/// through.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CellVertices {
+    /// One for all the crossings. Cells the surface passes through more
+    /// than once join the pieces at a non-manifold vertex.
+    #[default]
+    One,
+    /// One for each separate piece of surface, see
+    /// [`Subcases::components`]. Like `MANIFOLD` in `contour.wgsl`.
+    ///
+    /// Ambiguous faces are decided with the asymptotic decider, so the
+    /// pieces split where the interpolated surface does. A tube through an
+    /// ambiguous face, with one piece on either side crossing all four of
+    /// its edges, still has the edge between those two vertices shared by
+    /// four triangles.
+    ///
+    /// [`Subcases::components`]: crate::cases::Subcases::components
+    PerComponent,
+}
+
+const AXES: [UVec3; 3] = [UVec3::X, UVec3::Y, UVec3::Z];
+
+// same as AXIS_TABLE in contour.wgsl
+pub const AXIS_TABLE: [bool; 6] = [
+    // negative axes
+    true,
+    false,
+    false,
+    // positive axes
+    false,
+    true,
+    true,
+];
+
+// the four cells sharing an edge along each axis, relative to the edge's
+// starting point. Same as EDGE_OFFSETS in contour.wgsl
+pub const EDGE_OFFSETS: [[IVec3; 4]; 3] = [
+    [
+        IVec3::new(0, 0, 0),
+        IVec3::new(0, -1, 0),
+        IVec3::new(0, 0, -1),
+        IVec3::new(0, -1, -1),
+    ],
+    [
+        IVec3::new(0, 0, 0),
+        IVec3::new(-1, 0, 0),
+        IVec3::new(0, 0, -1),
+        IVec3::new(-1, 0, -1),
+    ],
+    [
+        IVec3::new(0, 0, 0),
+        IVec3::new(0, -1, 0),
+        IVec3::new(-1, 0, 0),
+        IVec3::new(-1, -1, 0),
+    ],
+];
+
+pub fn inside(x: f32) -> bool {
+    x <= 0.0
+}
+
+pub fn is_edge(a: f32, b: f32) -> bool {
+    inside(a) != inside(b)
+}
+
+pub fn adapt(v0: f32, v1: f32) -> f32 {
+    (0.0 - v0) / (v1 - v0)
+}
+
+/// Dual contours `map`, placing each vertex at the minimum of the QEF built
+/// from the planes at its edge crossings.
+///
+/// `gradient` is sampled at the grid points and interpolated along the
+/// edges, like `sample_grad` in `adaptivity.wgsl`.
+pub fn dual_contour(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    vertices: CellVertices,
+    settings: &QefSettings,
+) -> ContourMesh {
+    let cells = UVec3::ZERO..map.size();
+    dual_contour_cells(map, gradient, vertices, settings, cells)
+}
+
+/// [`dual_contour`], but only emitting the quads whose four cells are all
+/// in `cells`. Chunks use this to leave the quads across their borders to
+/// the seams between them.
+pub fn dual_contour_cells(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    vertices: CellVertices,
+    settings: &QefSettings,
+    cells: Range<UVec3>,
+) -> ContourMesh {
+    let mut errors = Vec::new();
+
+    let mut mesh = contour(map, gradient, vertices, cells, |cell, crossings| {
+        let solution = solve_cell(cell, crossings, settings);
+
+        errors.push(solution.error);
+        solution.pos
+    });
+
+    mesh.qef_errors = errors;
+    mesh
+}
+
+/// The minimum within `cell` of the QEF built from the planes at
+/// `crossings`.
+pub fn solve_cell(cell: UVec3, crossings: &[EdgeCrossing], settings: &QefSettings) -> QefSolution {
+    let qef = Qef::from_planes(crossings.iter().map(|crossing| Plane {
+        pos: crossing.pos,
+        normal: crossing.normal,
+    }));
+
+    let min = cell.as_vec3();
+    qef.solve(settings, min, min + Vec3::ONE)
+}
+
+/// Shared by all the dual methods, emits the vertices of every surface cell
+/// at the positions `place_vertex` picks from their crossings, and a quad
+/// for every edge with a sign change whose four cells are in `cells`.
+pub fn contour(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    vertices: CellVertices,
+    cells: Range<UVec3>,
+    mut place_vertex: impl FnMut(UVec3, &[EdgeCrossing]) -> Vec3,
+) -> ContourMesh {
+    let size = map.size();
+    let mut mesh = ContourMesh::default();
+
+    // the first vertex of every cell, the others follow it, and which of
+    // them each edge of the cell belongs to
+    let mut cell_lookup: Vec<Option<(u32, EdgeComponents)>> =
+        vec![None; (size.x * size.y * size.z) as usize];
+    let cell_index = |cell: UVec3| (cell.x + size.x * (cell.y + size.y * cell.z)) as usize;
+
+    for cell in crate::all_cells(size) {
+        let crossings = cell_crossings(map, cell, &gradient);
+        if crossings.is_empty() {
+            continue;
+        }
+
+        let components = cell_components(map, cell, vertices);
+        cell_lookup[cell_index(cell)] = Some((mesh.positions.len() as u32, components));
+
+        // shared by all the vertices of the cell, like compute_vertices
+        let (densities, materials) = map.cell_corners(cell);
+        let material = dominant_material(densities, materials);
+
+        for i in 0..components.count {
+            let crossings: ArrayVec<EdgeCrossing, 12> = crossings
+                .iter()
+                .filter(|crossing| components.edges[crossing.edge] == i)
+                .copied()
+                .collect();
+
+            let normal = crossings
+                .iter()
+                .map(|crossing| crossing.normal)
+                .sum::<Vec3>()
+                .normalize_or_zero();
+
+            mesh.positions.push(place_vertex(cell, &crossings));
+            mesh.normals.push(normal);
+            mesh.materials.push(pack_material(material, material, 0.0));
+        }
+    }
+
+    for start in crate::all_cells(map.grid_size()) {
+        for (axis, offsets) in EDGE_OFFSETS.iter().enumerate() {
+            let end = start + AXES[axis];
+            if !map.contains(end) {
+                continue;
+            }
+
+            let (a, b) = (map[start], map[end]);
+            if !is_edge(a, b) {
+                continue;
+            }
+
+            let mut quad = [0; 4];
+            let mut complete = true;
+
+            for (vtx, offset) in quad.iter_mut().zip(offsets) {
+                let cell = start.as_ivec3() + *offset;
+
+                // edges on the border of the map only have some of their
+                // cells
+                if cell.cmplt(cells.start.as_ivec3()).any()
+                    || cell.cmpge(cells.end.as_ivec3()).any()
+                {
+                    complete = false;
+                    break;
+                }
+
+                let cell = cell.as_uvec3();
+                let edge = local_edge(start - cell, axis);
+                // the surface crosses one of the cell's edges, so it has
+                // vertices
+                let (first, components) = cell_lookup[cell_index(cell)].unwrap();
+
+                *vtx = first + components.edges[edge] as u32;
+            }
+
+            if complete {
+                let is_positive = inside(a);
+                let winding = AXIS_TABLE[is_positive as usize * 3 + axis];
+
+                write_quad(&mut mesh.indices, quad, winding);
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Which of the vertices of `cell` each of its edges belongs to.
+fn cell_components(map: &DensityMap, cell: UVec3, vertices: CellVertices) -> EdgeComponents {
+    match vertices {
+        CellVertices::One => EdgeComponents {
+            count: 1,
+            edges: [0; 12],
+        },
+        CellVertices::PerComponent => {
+            let case = sample_density_map(map, cell).0;
+            let faces = resolve_faces(map.cell_corners(cell).0, case);
+
+            SUBCASES[&(case, faces)].components
+        }
+    }
+}
+
+/// The index in [`EDGES`] of the edge of a cell starting at the corner
+/// `start` and running along `axis`.
+fn local_edge(start: UVec3, axis: usize) -> usize {
+    let end = start + AXES[axis];
+
+    EDGES
+        .iter()
+        .position(|&(v0, v1)| {
+            let (a, b) = (UVec3::from(VERTICES[v0]), UVec3::from(VERTICES[v1]));
+            (a, b) == (start, end) || (a, b) == (end, start)
+        })
+        .unwrap()
+}
+
+/// The points where the surface crosses the edges of `cell`, in world
+/// space.
+pub fn cell_crossings(
+    map: &DensityMap,
+    cell: UVec3,
+    gradient: impl Fn(UVec3) -> Vec3,
+) -> ArrayVec<EdgeCrossing, 12> {
+    let mut crossings = ArrayVec::new();
+
+    for (edge, &(v0, v1)) in EDGES.iter().enumerate() {
+        let start = cell + UVec3::from(VERTICES[v0]);
+        let end = cell + UVec3::from(VERTICES[v1]);
+
+        let (a, b) = (map[start], map[end]);
+        if !is_edge(a, b) {
+            continue;
+        }
+
+        let t = adapt(a, b);
+
+        crossings.push(EdgeCrossing {
+            edge,
+            pos: start.as_vec3().lerp(end.as_vec3(), t),
+            normal: gradient(start).lerp(gradient(end), t).normalize_or_zero(),
+        });
+    }
+
+    crossings
+}
+
+// same vertex order as write_quad in contour.wgsl
+pub fn write_quad(indices: &mut Vec<u32>, quad: [u32; 4], invert_winding: bool) {
+    if invert_winding {
+        indices.extend_from_slice(&[quad[1], quad[0], quad[2]]);
+        indices.extend_from_slice(&[quad[3], quad[1], quad[2]]);
+    } else {
+        indices.extend_from_slice(&[quad[0], quad[1], quad[2]]);
+        indices.extend_from_slice(&[quad[1], quad[3], quad[2]]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    /// Vertices whose triangles don't form a single fan, and edges with
+    /// more than two triangles.
+    fn non_manifold(mesh: &ContourMesh) -> usize {
+        let mut edges = HashMap::<_, usize>::new();
+        let mut links = vec![Vec::new(); mesh.positions.len()];
+
+        for tri in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                let (a, b, c) = (tri[i], tri[(i + 1) % 3], tri[(i + 2) % 3]);
+
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+                links[a as usize].push((b, c));
+            }
+        }
+
+        let split_fans = links
+            .iter()
+            .filter(|link| {
+                // flood the edges of the link starting from the first one
+                let mut reached = HashSet::new();
+                let mut stack = vec![link[0].0];
+
+                while let Some(v) = stack.pop() {
+                    if reached.insert(v) {
+                        for &(b, c) in link.iter() {
+                            if b == v {
+                                stack.push(c);
+                            } else if c == v {
+                                stack.push(b);
+                            }
+                        }
+                    }
+                }
+
+                link.iter().any(|(b, _)| !reached.contains(b))
+            })
+            .count();
+
+        split_fans + edges.values().filter(|&&n| n > 2).count()
+    }
+
+    #[test]
+    fn per_component_vertices_are_manifold() {
+        // two corners of the middle cell inside, their blobs only meet
+        // at the cell's vertex
+        let mut map = DensityMap::new(UVec3::splat(3));
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = 1.0;
+        }
+        map[UVec3::new(1, 1, 1)] = -1.0;
+        map[UVec3::new(2, 2, 2)] = -1.0;
+
+        let gradient = |pos| map.gradient(pos);
+        let settings = QefSettings::default();
+
+        let one = dual_contour(&map, gradient, CellVertices::One, &settings);
+        let per_component = dual_contour(&map, gradient, CellVertices::PerComponent, &settings);
+
+        assert_eq!(per_component.positions.len(), one.positions.len() + 1);
+        assert_eq!(per_component.indices.len(), one.indices.len());
+
+        assert!(non_manifold(&one) > 0);
+        assert_eq!(non_manifold(&per_component), 0);
+    }
+
+    #[test]
+    fn ambiguous_faces_are_manifold() {
+        // two diagonal corners of the face between the middle cells
+        // inside, shallow enough that the decider keeps them apart
+        let mut map = DensityMap::new(UVec3::splat(3));
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = 1.0;
+        }
+        map[UVec3::new(1, 1, 2)] = -0.5;
+        map[UVec3::new(2, 2, 2)] = -0.5;
+
+        for cell in [UVec3::new(1, 1, 1), UVec3::new(1, 1, 2)] {
+            let case = sample_density_map(&map, cell).0;
+            assert_ne!(crate::cases::ambiguous_faces(case), 0);
+        }
+
+        let gradient = |pos| map.gradient(pos);
+        let mesh = dual_contour(
+            &map,
+            gradient,
+            CellVertices::PerComponent,
+            &QefSettings::default(),
+        );
+
+        let mut edges = HashMap::<_, usize>::new();
+        for tri in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+
+        assert!(!edges.is_empty());
+        for (edge, triangles) in edges {
+            assert_eq!(triangles, 2, "{edge:?}");
+        }
+    }
+
+    #[test]
+    fn vertices_lie_on_a_plane_and_quads_face_out() {
+        // tilted so the crossings aren't all at the same height, the
+        // densities grow along `normal`
+        let normal = Vec3::new(0.2, -0.3, 1.0).normalize();
+        let offset = 2.3;
+
+        let mut map = DensityMap::new(UVec3::splat(5));
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = pos.as_vec3().dot(normal) - offset;
+        }
+
+        let mesh = dual_contour(&map, |_| normal, CellVertices::One, &QefSettings::default());
+
+        assert!(!mesh.indices.is_empty());
+        for &pos in &mesh.positions {
+            assert!(
+                (pos.dot(normal) - offset).abs() < 1e-4,
+                "{pos} is off the plane"
+            );
+        }
+
+        for tri in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[tri[i] as usize]);
+            let face = (b - a).cross(c - a);
+            assert!(face.dot(normal) > 0.0, "{tri:?} faces in");
+        }
+    }
+}
@@ -5,10 +5,23 @@ use bevy::{
     color::palettes::css::{RED, WHITE},
     prelude::*,
     render::storage::ShaderStorageBuffer,
+    tasks::AsyncComputeTaskPool,
 };
 use bevy_egui::{egui, EguiContexts};
 
-use crate::{sample_density_map, Case, DensityMap, CASES};
+use crate::{
+    chunk::{ChunkMesher, ChunkStreaming},
+    dual_contouring::{dual_contour, CellVertices},
+    marching_cubes::{extended_marching_cubes, marching_cubes, Ambiguity, FeatureSettings},
+    mesh::{ContourMesh, MeshingJobs},
+    octree::{adaptive_dual_contour, OctreeSettings},
+    qef::QefSettings,
+    sample_density_map,
+    shader::{ContouringSettings, Mesher},
+    surface_nets::{surface_nets, SurfaceNetsSettings},
+    uv::{UvMode, UvSettings},
+    Case, DensityMap, CASES,
+};
 
 trait HasOptionsMenu {}
 
@@ -20,6 +33,7 @@ struct Node {
     pos: Transform,
     density_idx: DensityIdx,
     density_value: DensityValue,
+    density_material: DensityMaterial,
 }
 
 #[derive(Resource)]
@@ -30,18 +44,20 @@ struct Materials {
 
 pub fn editor_plugin(app: &mut App) {
     app.init_resource::<VisibilitySettings>()
+        .init_resource::<OctreeSettings>()
+        .init_resource::<FeatureSettings>()
         .add_systems(
             Startup,
-            (
-                make_materials,
-                spawn_mesh,
-                spawn_light,
-                spawn_clickable_points,
-            )
-                .chain(),
+            (make_materials, spawn_mesh, spawn_light).chain(),
         )
-        .add_systems(Update, (make_edit_ui, set_materials))
-        .add_systems(Update, update_density_map.after(make_edit_ui));
+        .add_systems(Update, (make_edit_ui, set_materials, spawn_clickable_points))
+        .add_systems(Update, update_density_map.after(make_edit_ui))
+        .add_systems(
+            Update,
+            (start_meshing, apply_meshes)
+                .chain()
+                .after(update_density_map),
+        );
 }
 
 fn draw_gizmos(map: Res<DensityMap>, mut gizmos: Gizmos, vis: Res<VisibilitySettings>) {
@@ -49,7 +65,7 @@ fn draw_gizmos(map: Res<DensityMap>, mut gizmos: Gizmos, vis: Res<VisibilitySett
         return;
     }
 
-    let cells = crate::all_cells();
+    let cells = crate::all_cells(map.size());
 
     for cell in cells {
         let case = sample_density_map(&*map, cell);
@@ -72,6 +88,9 @@ struct DensityIdx(UVec3);
 #[derive(Component, Deref, DerefMut, Copy, Clone, Default, Debug)]
 struct DensityValue(f32);
 
+#[derive(Component, Deref, DerefMut, Copy, Clone, Default, Debug)]
+struct DensityMaterial(u8);
+
 fn make_materials(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
     commands.insert_resource(Materials {
         unselected: MeshMaterial3d(materials.add(Color::WHITE)),
@@ -83,12 +102,49 @@ fn make_materials(mut commands: Commands, mut materials: ResMut<Assets<StandardM
 struct VisibilitySettings {
     nodes: bool,
     gizmos: bool,
+    cpu_mesher: CpuMesher,
+}
+
+/// Which CPU mesher builds the [`MarchedMesh`], if any.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum CpuMesher {
+    #[default]
+    None,
+    MarchingCubes,
+    MarchingCubes33,
+    ExtendedMarchingCubes,
+    DualContouring,
+    ManifoldDualContouring,
+    AdaptiveDualContouring,
+    SurfaceNets,
+}
+
+impl CpuMesher {
+    const ALL: [CpuMesher; 8] = [
+        CpuMesher::None,
+        CpuMesher::MarchingCubes,
+        CpuMesher::MarchingCubes33,
+        CpuMesher::ExtendedMarchingCubes,
+        CpuMesher::DualContouring,
+        CpuMesher::ManifoldDualContouring,
+        CpuMesher::AdaptiveDualContouring,
+        CpuMesher::SurfaceNets,
+    ];
 }
 
 fn make_edit_ui(
     mut context: EguiContexts,
     mut visibilities: ResMut<VisibilitySettings>,
-    mut query: Query<(&Clicked, &DensityIdx, &mut DensityValue)>,
+    mut map: ResMut<DensityMap>,
+    (mut contouring, mut uv): (ResMut<ContouringSettings>, ResMut<UvSettings>),
+    (mut surface_nets, mut streaming): (ResMut<SurfaceNetsSettings>, ResMut<ChunkStreaming>),
+    (mut octree, mut features): (ResMut<OctreeSettings>, ResMut<FeatureSettings>),
+    mut query: Query<(
+        &Clicked,
+        &DensityIdx,
+        &mut DensityValue,
+        &mut DensityMaterial,
+    )>,
 ) {
     let ctx = context.ctx_mut();
 
@@ -106,13 +162,101 @@ fn make_edit_ui(
             visibilities.gizmos = show_gizmos;
         }
 
+        let mut cpu_mesher = visibilities.cpu_mesher;
+        egui::ComboBox::from_label("CPU mesher")
+            .selected_text(format!("{cpu_mesher:?}"))
+            .show_ui(ui, |ui| {
+                for mesher in CpuMesher::ALL {
+                    ui.selectable_value(&mut cpu_mesher, mesher, format!("{mesher:?}"));
+                }
+            });
+        if cpu_mesher != visibilities.cpu_mesher {
+            visibilities.cpu_mesher = cpu_mesher;
+        }
+
+        let mut gpu_mesher = contouring.mesher;
+        egui::ComboBox::from_label("GPU mesher")
+            .selected_text(format!("{gpu_mesher:?}"))
+            .show_ui(ui, |ui| {
+                for mesher in [
+                    Mesher::DualContouring,
+                    Mesher::ManifoldDualContouring,
+                    Mesher::SurfaceNets,
+                ] {
+                    ui.selectable_value(&mut gpu_mesher, mesher, format!("{mesher:?}"));
+                }
+            });
+        if gpu_mesher != contouring.mesher {
+            contouring.mesher = gpu_mesher;
+        }
+
+        let mut chunk_mesher = streaming.mesher;
+        egui::ComboBox::from_label("Chunk mesher")
+            .selected_text(format!("{chunk_mesher:?}"))
+            .show_ui(ui, |ui| {
+                for mesher in ChunkMesher::ALL {
+                    ui.selectable_value(&mut chunk_mesher, mesher, format!("{mesher:?}"));
+                }
+            });
+        if chunk_mesher != streaming.mesher {
+            streaming.mesher = chunk_mesher;
+        }
+
+        let mut uv_mode = uv.mode;
+        egui::ComboBox::from_label("UV mode")
+            .selected_text(format!("{uv_mode:?}"))
+            .show_ui(ui, |ui| {
+                for mode in UvMode::ALL {
+                    ui.selectable_value(&mut uv_mode, mode, format!("{mode:?}"));
+                }
+            });
+        if uv_mode != uv.mode {
+            uv.mode = uv_mode;
+        }
+
+        let mut readback = contouring.readback;
+        ui.checkbox(&mut readback, "Read back GPU mesh");
+        if readback != contouring.readback {
+            contouring.readback = readback;
+        }
+
+        let mut relax_iterations = surface_nets.relax_iterations;
+        ui.add(egui::Slider::new(&mut relax_iterations, 0..=16).text("Relax iterations"));
+        if relax_iterations != surface_nets.relax_iterations {
+            surface_nets.relax_iterations = relax_iterations;
+        }
+
+        let mut tolerance = octree.tolerance;
+        ui.add(
+            egui::Slider::new(&mut tolerance, 0.0..=1.0)
+                .logarithmic(true)
+                .text("Octree tolerance"),
+        );
+        if tolerance != octree.tolerance {
+            octree.tolerance = tolerance;
+        }
+
+        let mut sharp_angle = features.sharp_angle.to_degrees();
+        ui.add(egui::Slider::new(&mut sharp_angle, 0.0..=180.0).text("Sharp angle (°)"));
+        if sharp_angle != features.sharp_angle.to_degrees() {
+            features.sharp_angle = sharp_angle.to_radians();
+        }
+
+        let mut size = map.size().x;
+        ui.add(egui::Slider::new(&mut size, 1..=256).text("Grid size"));
+        if size != map.size().x {
+            map.resize(UVec3::splat(size));
+        }
+
         ui.heading("Densities");
         ui.separator();
-        for (clicked, idx, mut value) in &mut query {
+        for (clicked, idx, mut value, mut material) in &mut query {
             let mut slider = **value;
+            let mut material_slider = **material;
             if **clicked {
                 ui.label(format!("Index: {:?}", **idx));
                 ui.add(egui::Slider::new(&mut slider, -10.0..=10.0));
+                ui.add(egui::Slider::new(&mut material_slider, 0..=255).text("Material"));
                 ui.separator();
             }
 
@@ -120,6 +264,9 @@ fn make_edit_ui(
             if slider != **value {
                 **value = slider;
             }
+            if material_slider != **material {
+                **material = material_slider;
+            }
         }
     });
 }
@@ -137,12 +284,27 @@ fn set_materials(
     }
 }
 
-fn update_density_map(
-    mut map: ResMut<DensityMap>,
-    query: Query<(&DensityIdx, &DensityValue), Changed<DensityValue>>,
-) {
-    for (idx, val) in &query {
+/// Nodes whose density or material was edited.
+type EditedNodes<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static DensityIdx,
+        &'static DensityValue,
+        &'static DensityMaterial,
+    ),
+    Or<(Changed<DensityValue>, Changed<DensityMaterial>)>,
+>;
+
+fn update_density_map(mut map: ResMut<DensityMap>, query: EditedNodes) {
+    for (idx, val, material) in &query {
+        // nodes from before a resize might still be around this frame
+        if !map.contains(**idx) {
+            continue;
+        }
+
         map[**idx] = **val;
+        map.set_material(**idx, **material);
     }
 }
 
@@ -155,45 +317,121 @@ fn spawn_mesh(mut commands: Commands, mtls: Res<Materials>) {
         Mesh3d(Handle::weak_from_u128(0xdeadbeef)),
         mtls.unselected.clone(),
         MarchedMesh,
+        MeshingJobs {
+            pending: true,
+            ..default()
+        },
     ));
 }
 
-fn rebuild_mesh(
+/// Meshes `map` with `mesher`, on whichever thread the job runs.
+fn run_mesher(
+    mesher: CpuMesher,
+    map: &DensityMap,
+    qef_settings: &QefSettings,
+    feature_settings: &FeatureSettings,
+    surface_nets_settings: &SurfaceNetsSettings,
+    octree_settings: &OctreeSettings,
+) -> ContourMesh {
+    match mesher {
+        CpuMesher::None => ContourMesh::default(),
+        CpuMesher::MarchingCubes => marching_cubes(map, |pos| map.gradient(pos), Ambiguity::Fixed),
+        CpuMesher::MarchingCubes33 => {
+            marching_cubes(map, |pos| map.gradient(pos), Ambiguity::Resolved)
+        }
+        CpuMesher::ExtendedMarchingCubes => {
+            extended_marching_cubes(map, |pos| map.gradient(pos), qef_settings, feature_settings)
+        }
+        CpuMesher::DualContouring => dual_contour(
+            map,
+            |pos| map.gradient(pos),
+            CellVertices::One,
+            qef_settings,
+        ),
+        CpuMesher::ManifoldDualContouring => dual_contour(
+            map,
+            |pos| map.gradient(pos),
+            CellVertices::PerComponent,
+            qef_settings,
+        ),
+        CpuMesher::AdaptiveDualContouring => {
+            adaptive_dual_contour(map, |pos| map.gradient(pos), qef_settings, octree_settings)
+        }
+        CpuMesher::SurfaceNets => surface_nets(map, |pos| map.gradient(pos), surface_nets_settings),
+    }
+}
+
+/// Starts a meshing job with a copy of the current inputs whenever they
+/// change and there's a free slot.
+fn start_meshing(
     map: Res<DensityMap>,
-    mesh_query: Query<&Mesh3d, With<MarchedMesh>>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    vis: Res<VisibilitySettings>,
+    (qef_settings, feature_settings): (Res<QefSettings>, Res<FeatureSettings>),
+    surface_nets_settings: Res<SurfaceNetsSettings>,
+    octree_settings: Res<OctreeSettings>,
+    uv_settings: Res<UvSettings>,
+    mut jobs: Query<&mut MeshingJobs, With<MarchedMesh>>,
 ) {
-    if !map.is_changed() {
-        return;
+    let changed = map.is_changed()
+        || vis.is_changed()
+        || qef_settings.is_changed()
+        || feature_settings.is_changed()
+        || surface_nets_settings.is_changed()
+        || octree_settings.is_changed()
+        || uv_settings.is_changed();
+
+    for mut jobs in &mut jobs {
+        jobs.pending |= changed;
+        if !jobs.pending || jobs.is_full() {
+            continue;
+        }
+
+        let mesher = vis.cpu_mesher;
+        let map = map.clone();
+        let qef_settings = *qef_settings;
+        let feature_settings = *feature_settings;
+        let surface_nets_settings = *surface_nets_settings;
+        let octree_settings = *octree_settings;
+        let uv_settings = *uv_settings;
+
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let mut mesh = run_mesher(
+                mesher,
+                &map,
+                &qef_settings,
+                &feature_settings,
+                &surface_nets_settings,
+                &octree_settings,
+            );
+
+            // the mesh is drawn where it is in the map
+            mesh.generate_uvs(&uv_settings, &Transform::IDENTITY);
+            mesh
+        });
+
+        jobs.start(task);
+        jobs.pending = false;
     }
+}
 
-    for mesh in &mesh_query {
-        let mesh_id = mesh.0.id();
+/// Writes the meshes of finished jobs into their [`Mesh3d`], see
+/// [`MeshingJobs::take_finished`].
+fn apply_meshes(
+    mut query: Query<(&Mesh3d, &mut MeshingJobs), With<MarchedMesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (mesh, mut jobs) in &mut query {
+        let Some(contour) = jobs.take_finished() else {
+            continue;
+        };
 
-        let mesh = meshes.get_or_insert_with(mesh_id, || {
+        let mesh = meshes.get_or_insert_with(mesh.0.id(), || {
             Mesh::new(
                 bevy::render::mesh::PrimitiveTopology::TriangleList,
                 RenderAssetUsages::default(),
             )
         });
-
-        let cells = crate::all_cells();
-
-        let mut vtx = Vec::new();
-
-        for cell in cells {
-            let case = sample_density_map(&*map, cell);
-            let edges: &Case = &CASES[case.0 as usize];
-
-            for &edge_tri in &edges.tris {
-                let tri = crate::edge_tri_to_triangle(cell, edge_tri);
-
-                vtx.extend_from_slice(&bytemuck::cast::<[Vec3; 3], [[f32; 3]; 3]>(tri));
-            }
-        }
-
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vtx);
-        mesh.compute_flat_normals();
+        contour.write_to(mesh);
     }
 }
 
@@ -214,26 +452,50 @@ fn update_mesh_visibility(
     }
 }
 
+/// The largest grid that gets a clickable node per sample. Larger grids
+/// would spawn millions of entities, so they are left without nodes.
+const MAX_NODE_GRID: u32 = 33;
+
+// respawns the nodes whenever the density map changes size
 fn spawn_clickable_points(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut spawned_size: Local<Option<UVec3>>,
+    map: Res<DensityMap>,
     materials: Res<Materials>,
+    nodes: Query<Entity, With<DensityIdx>>,
 ) {
-    const N: u32 = super::N as u32;
+    if *spawned_size == Some(map.size()) {
+        return;
+    }
+    *spawned_size = Some(map.size());
+
+    for node in &nodes {
+        commands.entity(node).despawn();
+    }
+
+    let grid = map.grid_size();
+    if grid.max_element() > MAX_NODE_GRID {
+        log::info!("Not spawning nodes for a {grid} grid, the limit is {MAX_NODE_GRID} per axis");
+        return;
+    }
 
     let sphere = meshes.add(Sphere::new(0.1).mesh());
 
-    for x in 0..N + 1 {
-        for y in 0..N + 1 {
-            for z in 0..N + 1 {
+    for x in 0..grid.x {
+        for y in 0..grid.y {
+            for z in 0..grid.z {
+                let idx = UVec3 { x, y, z };
+
                 commands
                     .spawn(Node {
                         mesh: Mesh3d(sphere.clone()),
                         mtl: materials.unselected.clone(),
                         click: Clicked(false),
                         pos: Transform::from_xyz(x as f32, y as f32, z as f32),
-                        density_idx: DensityIdx(UVec3 { x, y, z }),
-                        density_value: DensityValue(0.0),
+                        density_idx: DensityIdx(idx),
+                        density_value: DensityValue(map[idx]),
+                        density_material: DensityMaterial(map.material(idx)),
                     })
                     .observe(update_clicked);
             }
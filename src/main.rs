@@ -10,8 +10,20 @@ use arrayvec::ArrayVec;
 
 mod camera;
 mod cases;
+mod chunk;
+mod dual_contouring;
 mod editor;
+mod marching_cubes;
+mod mesh;
+mod octree;
+mod qef;
+mod sdf;
 mod shader;
+mod splat;
+mod surface_nets;
+#[cfg(test)]
+mod test;
+mod uv;
 
 use cases::CASES;
 
@@ -24,27 +36,45 @@ fn main() {
 
     App::new()
         .add_plugins((DefaultPlugins, EguiPlugin, MeshPickingPlugin))
-        .add_plugins(shader::DualContouringPlugin)
-        .add_plugins((editor::editor_plugin, camera::camera_plugin))
+        .add_plugins(splat::splat_plugin)
+        .add_plugins(shader::DualContouringPlugin {
+            source: shader::DensitySource::Cpu,
+            ..default()
+        })
+        .add_plugins((
+            editor::editor_plugin,
+            camera::camera_plugin,
+            chunk::chunk_plugin,
+        ))
         .init_resource::<DensityMap>()
         .run();
 }
 
-const N: usize = 5;
+/// Number of cells along each axis of the default density map.
+const DEFAULT_SIZE: u32 = 5;
 
 struct CaseIndex(u8);
+
+/// A [`CaseIndex`] with its ambiguities resolved, see [`cases::SUBCASES`].
+struct SubcaseIndex {
+    case: u8,
+    /// Bit `i` is set if the set corners of the ambiguous face
+    /// `cases::FACES[i]` are connected across it.
+    faces: u8,
+    /// Which of the [`cases::Subcases::tunnels`] the interior connects.
+    tunnels: u8,
+}
+
 struct Case {
-    tris: ArrayVec<[u8; 3], 5>,
+    tris: ArrayVec<[u8; 3], { cases::MAX_TRIS }>,
 }
 
 // holy kludge
-fn all_cells() -> Vec<UVec3> {
-    let n = N as u32;
-
-    let mut v = Vec::with_capacity((N * N * N) as usize);
-    for x in 0..n {
-        for y in 0..n {
-            for z in 0..n {
+fn all_cells(size: UVec3) -> Vec<UVec3> {
+    let mut v = Vec::with_capacity((size.x * size.y * size.z) as usize);
+    for x in 0..size.x {
+        for y in 0..size.y {
+            for z in 0..size.z {
                 v.push(UVec3 { x, y, z });
             }
         }
@@ -52,16 +82,145 @@ fn all_cells() -> Vec<UVec3> {
     v
 }
 
-#[derive(Resource)]
+/// Densities sampled on the corners of a grid of `size` cells, so there
+/// are `size + 1` samples along each axis.
+#[derive(Resource, Clone)]
 struct DensityMap {
-    densities: [[[f32; N + 1]; N + 1]; N + 1],
+    size: UVec3,
+    // x-major, i.e. the same layout as a 3d texture
+    densities: Vec<f32>,
+    /// Material ID of every sample, in the same layout as `densities`.
+    /// Meshers carry them through to [`mesh::ATTRIBUTE_MATERIAL`].
+    materials: Vec<u8>,
+    /// Inclusive bounds of the samples written since the last
+    /// [`DensityMap::take_dirty`].
+    dirty: Option<(UVec3, UVec3)>,
 }
 
 impl Default for DensityMap {
     fn default() -> Self {
+        Self::new(UVec3::splat(DEFAULT_SIZE))
+    }
+}
+
+impl DensityMap {
+    fn new(size: UVec3) -> Self {
+        let grid = size + 1;
+
+        let samples = (grid.x * grid.y * grid.z) as usize;
+
         Self {
-            densities: [[[0.0; N + 1]; N + 1]; N + 1],
+            size,
+            densities: vec![0.0; samples],
+            materials: vec![0; samples],
+            dirty: Some((UVec3::ZERO, size)),
+        }
+    }
+
+    /// A map of `size` cells holding `densities` in the layout of
+    /// [`DensityMap::densities`], if there's the right number of them. The
+    /// materials are all 0.
+    fn from_densities(size: UVec3, densities: Vec<f32>) -> Option<Self> {
+        let map = Self::new(size);
+        (densities.len() == map.densities.len()).then_some(Self { densities, ..map })
+    }
+
+    /// Number of cells along each axis.
+    fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    /// Number of samples along each axis.
+    fn grid_size(&self) -> UVec3 {
+        self.size + 1
+    }
+
+    fn contains(&self, pos: UVec3) -> bool {
+        pos.cmple(self.size).all()
+    }
+
+    fn densities(&self) -> &[f32] {
+        &self.densities
+    }
+
+    fn material(&self, pos: UVec3) -> u8 {
+        self.materials[self.linear_index(pos)]
+    }
+
+    fn set_material(&mut self, pos: UVec3, material: u8) {
+        self.mark_dirty(pos);
+        let idx = self.linear_index(pos);
+
+        self.materials[idx] = material;
+    }
+
+    /// The densities and materials at the corners of `cell`, in the order
+    /// of [`VERTICES`].
+    fn cell_corners(&self, cell: UVec3) -> ([f32; 8], [u8; 8]) {
+        let corners = VERTICES.map(|corner| cell + UVec3::from(corner));
+        (
+            corners.map(|pos| self[pos]),
+            corners.map(|pos| self.material(pos)),
+        )
+    }
+
+    /// Changes the number of cells, keeping the samples and materials that
+    /// are still inside the grid and zeroing the new ones.
+    fn resize(&mut self, size: UVec3) {
+        if size == self.size {
+            return;
         }
+
+        let mut new = Self::new(size);
+        let common = self.grid_size().min(new.grid_size());
+
+        for x in 0..common.x {
+            for y in 0..common.y {
+                for z in 0..common.z {
+                    let pos = UVec3 { x, y, z };
+                    new[pos] = self[pos];
+                    new.set_material(pos, self.material(pos));
+                }
+            }
+        }
+
+        *self = new;
+    }
+
+    /// Normalized gradient at a sample, using central differences clamped
+    /// to the grid like `compute_gradient` does on the GPU.
+    fn gradient(&self, pos: UVec3) -> Vec3 {
+        let max = self.size.as_ivec3();
+        let pos = pos.as_ivec3();
+
+        let sample = |p: IVec3| self[p.clamp(IVec3::ZERO, max).as_uvec3()];
+        let d = |axis: IVec3| sample(pos + axis) - sample(pos - axis);
+
+        Vec3::new(d(IVec3::X), d(IVec3::Y), d(IVec3::Z)).normalize_or_zero()
+    }
+
+    fn mark_dirty(&mut self, pos: UVec3) {
+        self.dirty = Some(match self.dirty {
+            Some((min, max)) => (min.min(pos), max.max(pos)),
+            None => (pos, pos),
+        });
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// Returns the inclusive bounds of every sample written since the last
+    /// call, if there are any.
+    fn take_dirty(&mut self) -> Option<(UVec3, UVec3)> {
+        self.dirty.take()
+    }
+
+    fn linear_index(&self, pos: UVec3) -> usize {
+        debug_assert!(self.contains(pos), "{pos} is outside of {}", self.size);
+        let grid = self.grid_size();
+
+        (pos.x + grid.x * (pos.y + grid.y * pos.z)) as usize
     }
 }
 
@@ -69,21 +228,22 @@ impl Index<UVec3> for DensityMap {
     type Output = f32;
 
     fn index(&self, idx: UVec3) -> &Self::Output {
-        let UVec3 { x, y, z } = idx;
-
-        &self.densities[x as usize][y as usize][z as usize]
+        &self.densities[self.linear_index(idx)]
     }
 }
 
 impl IndexMut<UVec3> for DensityMap {
     fn index_mut(&mut self, idx: UVec3) -> &mut f32 {
-        let UVec3 { x, y, z } = idx;
+        self.mark_dirty(idx);
+        let idx = self.linear_index(idx);
 
-        &mut self.densities[x as usize][y as usize][z as usize]
+        &mut self.densities[idx]
     }
 }
 
-fn corners_from_cell(pos: UVec3) -> impl Iterator<Item = UVec3> {
+fn corners_from_cell(size: UVec3, pos: UVec3) -> impl Iterator<Item = UVec3> {
+    debug_assert!(pos.cmplt(size).all(), "cell {pos} is outside of {size}");
+
     VERTICES
         .into_iter()
         .map(move |(x, y, z)| UVec3 { x, y, z } + pos)
@@ -92,7 +252,7 @@ fn corners_from_cell(pos: UVec3) -> impl Iterator<Item = UVec3> {
 fn sample_density_map(map: &DensityMap, pos: UVec3) -> CaseIndex {
     let mut case = 0u8;
 
-    for (i, corner_pos) in corners_from_cell(pos).enumerate() {
+    for (i, corner_pos) in corners_from_cell(map.size(), pos).enumerate() {
         let sample = map[corner_pos];
         if sample > 0.0 {
             case |= 1 << i as usize;
@@ -102,6 +262,48 @@ fn sample_density_map(map: &DensityMap, pos: UVec3) -> CaseIndex {
     CaseIndex(case)
 }
 
+/// Resolves the ambiguous faces of a cell with the asymptotic decider and
+/// its interior with the trilinear interpolation of its corners.
+fn resolve_subcase(map: &DensityMap, pos: UVec3, case: CaseIndex) -> SubcaseIndex {
+    let mut values = [0.0; 8];
+    for (value, corner_pos) in values.iter_mut().zip(corners_from_cell(map.size(), pos)) {
+        *value = map[corner_pos];
+    }
+
+    let faces = resolve_faces(values, case.0);
+
+    let mut tunnels = 0;
+    for (i, &(a, b)) in cases::SUBCASES[&(case.0, faces)].tunnels.iter().enumerate() {
+        // unset corners are connected where the negated densities are set
+        let values = if values[a] > 0.0 { values } else { values.map(|x| -x) };
+
+        if cases::trilinear_connected(values, a, b) {
+            tunnels |= 1 << i;
+        }
+    }
+
+    SubcaseIndex {
+        case: case.0,
+        faces,
+        tunnels,
+    }
+}
+
+/// The [`SubcaseIndex::faces`] of a cell with the corner `values`, decided
+/// with the asymptotic decider.
+fn resolve_faces(values: [f32; 8], case: u8) -> u8 {
+    let mut faces = 0;
+    for (i, face) in cases::FACES.iter().enumerate() {
+        if cases::ambiguous_faces(case) & (1 << i) != 0
+            && cases::joins_set_corners(face.map(|corner| values[corner]))
+        {
+            faces |= 1 << i;
+        }
+    }
+
+    faces
+}
+
 fn edge_to_vtx(edge: usize) -> Vec3 {
     let (v0_idx, v1_idx) = EDGES[edge];
 
@@ -137,19 +339,6 @@ const EDGES: [(usize, usize); 12] = [
     (3, 7),
 ];
 
-fn edge_tri_to_triangle(pos: UVec3, tri: [u8; 3]) -> [Vec3; 3] {
-    let pos_f = pos.as_vec3();
-
-    let tri = tri
-        .into_iter()
-        .map(|edge| edge_to_vtx(edge as usize) + pos_f)
-        .collect::<ArrayVec<Vec3, 3>>()
-        .into_inner()
-        .unwrap();
-
-    tri
-}
-
 fn edge_tri_to_lines(pos: UVec3, tri: [u8; 3]) -> [(Vec3, Vec3); 3] {
     let ab = (tri[0], tri[1]);
     let bc = (tri[1], tri[2]);
@@ -168,19 +357,3 @@ fn edge_tri_to_lines(pos: UVec3, tri: [u8; 3]) -> [(Vec3, Vec3); 3] {
         edge_pair_to_vtx_pair(ac),
     ]
 }
-
-// def edge_to_boundary_vertex(edge):
-// """Returns the vertex in the middle of the specified edge"""
-//     # Find the two vertices specified by this edge, and interpolate between
-// # them according to adapt, as in the 2d case
-// v0, v1 = EDGES[edge]
-//     f0 = f_eval[v0]
-//     f1 = f_eval[v1]
-//     t0 = 1 - adapt(f0, f1)
-//     t1 = 1 - t0
-//     vert_pos0 = VERTICES[v0]
-//     vert_pos1 = VERTICES[v1]
-//     return V3(x + vert_pos0[0] * t0 + vert_pos1[0] * t1,
-//               y + vert_pos0[1] * t0 + vert_pos1[1] * t1,
-//               z + vert_pos0[2] * t0 + vert_pos1[2] * t1)
-//
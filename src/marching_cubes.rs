@@ -0,0 +1,776 @@
+//! CPU marching cubes over [`CASES`], with the vertices interpolated along
+//! the edges and shared between neighbouring cells. Also the extended
+//! variant, which adds vertices inside the cells to keep sharp features,
+//! and the transition cells joining cells of different sizes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts::FRAC_PI_4,
+    ops::Range,
+};
+
+use arrayvec::ArrayVec;
+use bevy::prelude::*;
+
+use crate::{
+    cases::MAX_TRIS,
+    dual_contouring::{adapt, inside, solve_cell, EdgeCrossing},
+    mesh::{dominant_material, pack_material, ContourMesh},
+    qef::QefSettings,
+    resolve_subcase, sample_density_map, DensityMap, CASES, EDGES, VERTICES,
+};
+
+/// How cells with ambiguous faces or interiors are triangulated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ambiguity {
+    /// Use [`CASES`], which always keeps the set corners of a face apart.
+    /// Crack free, but the topology doesn't always match the densities.
+    #[default]
+    Fixed,
+    /// Pick the subcase matching the trilinear interpolation of the
+    /// corners, like Marching Cubes 33.
+    Resolved,
+}
+
+/// Marching cubes `map`. Triangles face the same way as the dual contouring
+/// quads, from the inside out.
+///
+/// `gradient` is sampled at the grid points and interpolated along the
+/// edges for the normals.
+pub fn marching_cubes(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    ambiguity: Ambiguity,
+) -> ContourMesh {
+    marching_cubes_cells(map, gradient, ambiguity, UVec3::ZERO..map.size())
+}
+
+/// [`marching_cubes`], but only the cells in `cells`. Chunks use this to
+/// leave their aprons to their neighbours.
+pub fn marching_cubes_cells(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    ambiguity: Ambiguity,
+    cells: Range<UVec3>,
+) -> ContourMesh {
+    let mut mesh = ContourMesh::default();
+    let mut vertices = EdgeVertices::new(map);
+
+    for cell in crate::all_cells(cells.end - cells.start) {
+        let cell = cells.start + cell;
+        let case = sample_density_map(map, cell);
+        let case = match ambiguity {
+            Ambiguity::Fixed => &CASES[case.0 as usize],
+            Ambiguity::Resolved => resolve_subcase(map, cell, case).case(),
+        };
+
+        for tri in &case.tris {
+            let indices = vertices.triangle(map, &gradient, &mut mesh, cell, *tri);
+            mesh.indices.extend_from_slice(&indices);
+        }
+    }
+
+    mesh
+}
+
+/// How [`extended_marching_cubes`] finds sharp features.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct FeatureSettings {
+    /// Pieces of surface whose crossing normals are further apart than
+    /// this, in radians, get a feature vertex.
+    pub sharp_angle: f32,
+}
+
+impl Default for FeatureSettings {
+    fn default() -> Self {
+        Self {
+            sharp_angle: FRAC_PI_4,
+        }
+    }
+}
+
+/// Marching cubes that keeps sharp features, after Kobbelt et al. 2001,
+/// "Feature Sensitive Surface Extraction from Volume Data".
+///
+/// Each piece of surface in a cell whose normals say it bends sharply gets
+/// an extra vertex at the minimum of the QEF of its crossings, and is
+/// fanned out from it instead of triangulated by [`CASES`]. The edges
+/// between the fans of neighbouring cells are then flipped to join their
+/// feature vertices, which recovers the feature lines running across
+/// cells.
+pub fn extended_marching_cubes(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    qef_settings: &QefSettings,
+    settings: &FeatureSettings,
+) -> ContourMesh {
+    let mut mesh = ContourMesh::default();
+    let mut vertices = EdgeVertices::new(map);
+    let mut features = HashSet::new();
+    let min_cos = settings.sharp_angle.cos();
+
+    for cell in crate::all_cells(map.size()) {
+        let case = &CASES[sample_density_map(map, cell).0 as usize];
+
+        // the edge of the cell each vertex is on
+        let mut edges = ArrayVec::<(u32, usize), 12>::new();
+        let mut tris = ArrayVec::<[u32; 3], MAX_TRIS>::new();
+
+        for &tri in &case.tris {
+            let indices = vertices.triangle(map, &gradient, &mut mesh, cell, tri);
+            for (vtx, edge) in indices.into_iter().zip([tri[0], tri[2], tri[1]]) {
+                if edges.iter().all(|&(other, _)| other != vtx) {
+                    edges.push((vtx, edge as usize));
+                }
+            }
+
+            tris.push(indices);
+        }
+
+        for piece in pieces(&tris) {
+            let crossings: ArrayVec<EdgeCrossing, 12> = edges
+                .iter()
+                .filter(|(vtx, _)| piece.iter().flatten().any(|other| other == vtx))
+                .map(|&(vtx, edge)| EdgeCrossing {
+                    edge,
+                    pos: mesh.positions[vtx as usize],
+                    normal: mesh.normals[vtx as usize],
+                })
+                .collect();
+
+            let sharp = crossings.iter().enumerate().any(|(i, a)| {
+                crossings[i + 1..]
+                    .iter()
+                    .any(|b| a.normal.dot(b.normal) < min_cos)
+            });
+
+            if !sharp {
+                mesh.indices.extend(piece.iter().flatten());
+                continue;
+            }
+
+            let feature = mesh.positions.len() as u32;
+            features.insert(feature);
+
+            let normal = crossings
+                .iter()
+                .map(|crossing| crossing.normal)
+                .sum::<Vec3>();
+            let (densities, materials) = map.cell_corners(cell);
+            let material = dominant_material(densities, materials);
+
+            mesh.positions
+                .push(solve_cell(cell, &crossings, qef_settings).pos);
+            mesh.normals.push(normal.normalize_or_zero());
+            mesh.materials.push(pack_material(material, material, 0.0));
+
+            // every vertex of a piece is on its boundary, so the fan is a
+            // triangle to each boundary edge, wound the same way
+            let edges = || {
+                piece
+                    .iter()
+                    .flat_map(|tri| (0..3).map(move |i| (tri[i], tri[(i + 1) % 3])))
+            };
+            for (a, b) in edges() {
+                if !edges().any(|edge| edge == (b, a)) {
+                    mesh.indices.extend_from_slice(&[a, b, feature]);
+                }
+            }
+        }
+    }
+
+    flip_feature_edges(&mut mesh.indices, &features);
+    mesh
+}
+
+/// The vertices on the edges of the grid, created the first time a cell
+/// needs them so neighbouring cells share them.
+struct EdgeVertices {
+    // one slot per axis per grid point, for the edge starting there
+    lookup: Vec<u32>,
+}
+
+impl EdgeVertices {
+    fn new(map: &DensityMap) -> Self {
+        let grid = map.grid_size();
+
+        Self {
+            lookup: vec![u32::MAX; (grid.x * grid.y * grid.z * 3) as usize],
+        }
+    }
+
+    /// The vertices of a triangle of [`CASES`] in `cell`, wound from the
+    /// inside out, so the second and third are on the last and second edge
+    /// of `tri`.
+    fn triangle(
+        &mut self,
+        map: &DensityMap,
+        gradient: impl Fn(UVec3) -> Vec3,
+        mesh: &mut ContourMesh,
+        cell: UVec3,
+        tri: [u8; 3],
+    ) -> [u32; 3] {
+        let [a, b, c] = tri.map(|edge| {
+            let (start, axis) = edge_start(cell, edge as usize);
+            let slot = (map.linear_index(start) * 3) + axis;
+
+            if self.lookup[slot] == u32::MAX {
+                self.lookup[slot] = push_edge_vertex(map, &gradient, mesh, start, axis);
+            }
+
+            self.lookup[slot]
+        });
+
+        // CASES winds the triangles facing the corners with negative
+        // densities, i.e. inwards
+        [a, c, b]
+    }
+}
+
+/// Adds the vertex where the surface crosses the edge from grid point
+/// `start` along `axis`, returning its index.
+fn push_edge_vertex(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    mesh: &mut ContourMesh,
+    start: UVec3,
+    axis: usize,
+) -> u32 {
+    let end = start + UVec3::AXES[axis];
+    let t = adapt(map[start], map[end]);
+    // air that was never given a material would blend the surface towards
+    // material 0, it takes the one of the inside end instead
+    let material = |pos: UVec3, other: UVec3| match map.material(pos) {
+        0 if !inside(map[pos]) => map.material(other),
+        material => material,
+    };
+
+    mesh.positions.push(start.as_vec3().lerp(end.as_vec3(), t));
+    mesh.normals
+        .push(gradient(start).lerp(gradient(end), t).normalize_or_zero());
+    mesh.materials
+        .push(pack_material(material(start, end), material(end, start), t));
+
+    mesh.positions.len() as u32 - 1
+}
+
+/// A square of cells of a coarse map, all on one side of a plane, and the
+/// cells of a finer map on the other side.
+#[derive(Clone, Copy, Debug)]
+pub struct TransitionFace {
+    /// The axis the plane is perpendicular to.
+    pub axis: usize,
+    /// Whether the finer cells are on the positive side of the plane.
+    pub positive: bool,
+    /// Number of coarse cells along each side of the square.
+    pub cells: u32,
+    /// How many times wider the coarse cells are, a power of two.
+    pub ratio: u32,
+    /// The lowest corner of the square in the coarse map.
+    pub coarse_min: UVec3,
+    /// The same corner in the fine map.
+    pub fine_min: UVec3,
+}
+
+/// A corner of a transition cell, a sample of the fine or the coarse map
+/// by its position on the face, in cells of that map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum TransitionCorner {
+    Fine(u32, u32),
+    Coarse(u32, u32),
+}
+
+/// Transition cells closing the gap between the surface of the coarse
+/// cells on `face` and the surface of the finer cells across it, after
+/// Lengyel's Transvoxel. Positions are in coarse map coordinates,
+/// `fine_to_coarse` converts the ones of the fine map.
+///
+/// Each coarse face gets a cell bounded by the finer faces on one side,
+/// the coarse face on the other, and the strips between their edges. The
+/// surface is traced around its boundary with the same rule as [`CASES`],
+/// so it meets both sides exactly, and fanned out like the loops of
+/// [`crate::cases::SUBCASES`]. Unlike Lengyel's, the cells are flattened
+/// into the face rather than taking room from the coarse cells, so their
+/// triangles lie in the plane.
+pub fn transition_cells(
+    coarse: &DensityMap,
+    fine: &DensityMap,
+    face: &TransitionFace,
+    fine_to_coarse: impl Fn(Vec3) -> Vec3,
+) -> ContourMesh {
+    use TransitionCorner::{Coarse, Fine};
+
+    let mut mesh = ContourMesh::default();
+    let mut lookup = HashMap::new();
+
+    let (u, v) = ((face.axis + 1) % 3, (face.axis + 2) % 3);
+    let pos = |corner| match corner {
+        Fine(x, y) => face.fine_min + x * UVec3::AXES[u] + y * UVec3::AXES[v],
+        Coarse(x, y) => face.coarse_min + x * UVec3::AXES[u] + y * UVec3::AXES[v],
+    };
+    let is_set = |corner| match corner {
+        Fine(..) => fine[pos(corner)] > 0.0,
+        Coarse(..) => coarse[pos(corner)] > 0.0,
+    };
+
+    // the cells of a square, across the face
+    let square = |n: u32| {
+        crate::all_cells(UVec3::new(n, n, 1))
+            .into_iter()
+            .map(|at| at.xy())
+    };
+
+    for cell in square(face.cells) {
+        let r = face.ratio;
+        let fine_at = |x, y| Fine(cell.x * r + x, cell.y * r + y);
+        let coarse_at = |x, y| Coarse(cell.x + x, cell.y + y);
+
+        // counterclockwise seen from outside the cell when the finer cells
+        // are on the positive side: the finer faces, the coarse face and
+        // the strips, whose coarse and fine corners are in the same places
+        let mut faces: Vec<Vec<TransitionCorner>> = square(r)
+            .map(|at| {
+                let corners = [(0, 0), (1, 0), (1, 1), (0, 1)];
+                corners.map(|(x, y)| fine_at(at.x + x, at.y + y)).to_vec()
+            })
+            .collect();
+
+        faces.push(vec![
+            coarse_at(0, 0),
+            coarse_at(0, 1),
+            coarse_at(1, 1),
+            coarse_at(1, 0),
+        ]);
+
+        let strip = |first: TransitionCorner, fine: Vec<TransitionCorner>, last| {
+            [vec![first], fine, vec![last]].concat()
+        };
+        faces.push(strip(
+            coarse_at(1, 0),
+            (0..=r).rev().map(|x| fine_at(x, 0)).collect(),
+            coarse_at(0, 0),
+        ));
+        faces.push(strip(
+            coarse_at(0, 0),
+            (0..=r).map(|y| fine_at(0, y)).collect(),
+            coarse_at(0, 1),
+        ));
+        faces.push(strip(
+            coarse_at(0, 1),
+            (0..=r).map(|x| fine_at(x, r)).collect(),
+            coarse_at(1, 1),
+        ));
+        faces.push(strip(
+            coarse_at(1, 1),
+            (0..=r).rev().map(|y| fine_at(r, y)).collect(),
+            coarse_at(1, 0),
+        ));
+
+        if !face.positive {
+            for face in &mut faces {
+                face.reverse();
+            }
+        }
+
+        // the same segments as Surface::trace in cases.rs, with the set
+        // corners of every face kept apart
+        let mut next = HashMap::new();
+        let mut ends = Vec::new();
+
+        for face in &faces {
+            let n = face.len();
+            for j in 0..n {
+                let (c0, c1) = (face[j], face[(j + 1) % n]);
+                if !is_set(c0) || is_set(c1) {
+                    continue;
+                }
+
+                let mut k = j;
+                loop {
+                    k = (k + n - 1) % n;
+                    if !is_set(face[k]) {
+                        break;
+                    }
+                }
+
+                let edge = |a: TransitionCorner, b: TransitionCorner| (a.min(b), a.max(b));
+                let end = edge(face[k], face[(k + 1) % n]);
+                next.insert(end, edge(c0, c1));
+                ends.push(end);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for first in ends {
+            let mut edges = Vec::new();
+            let mut edge = first;
+            while seen.insert(edge) {
+                edges.push(edge);
+                edge = next[&edge];
+            }
+
+            let mut vertex = |(a, b): (TransitionCorner, TransitionCorner)| {
+                *lookup.entry((a, b)).or_insert_with(|| match (a, b) {
+                    (Fine(..), Fine(..)) => {
+                        let axis = if pos(a)[u] != pos(b)[u] { u } else { v };
+                        let vtx =
+                            push_edge_vertex(fine, |p| fine.gradient(p), &mut mesh, pos(a), axis);
+                        let moved = &mut mesh.positions[vtx as usize];
+                        *moved = fine_to_coarse(*moved);
+                        vtx
+                    }
+                    (Coarse(..), Coarse(..)) => {
+                        let axis = if pos(a)[u] != pos(b)[u] { u } else { v };
+                        push_edge_vertex(coarse, |p| coarse.gradient(p), &mut mesh, pos(a), axis)
+                    }
+                    // both ends are the same sample, only crossed if the
+                    // maps disagree on it
+                    (Fine(..), Coarse(..)) | (Coarse(..), Fine(..)) => {
+                        let at = if matches!(a, Coarse(..)) {
+                            pos(a)
+                        } else {
+                            pos(b)
+                        };
+                        let material = coarse.material(at);
+
+                        mesh.positions.push(at.as_vec3());
+                        mesh.normals.push(coarse.gradient(at).normalize_or_zero());
+                        mesh.materials.push(pack_material(material, material, 0.0));
+                        mesh.positions.len() as u32 - 1
+                    }
+                })
+            };
+
+            let indices: Vec<u32> = edges.into_iter().map(&mut vertex).collect();
+            for i in 1..indices.len().saturating_sub(1) {
+                // wound the other way than the loops, like the cells
+                let (a, b, c) = (indices[0], indices[i], indices[i + 1]);
+
+                // a loop running along a strip can cross a fine and a coarse
+                // edge in the same place, the triangle between them is empty
+                let at = |i: u32| mesh.positions[i as usize];
+                let apart = |x, y| at(x).distance(at(y)) > 1e-5;
+                if apart(a, b) && apart(b, c) && apart(c, a) {
+                    mesh.indices.extend_from_slice(&[a, c, b]);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Groups the triangles of a cell into its separate pieces of surface,
+/// which never share a vertex.
+fn pieces(tris: &[[u32; 3]]) -> ArrayVec<ArrayVec<[u32; 3], MAX_TRIS>, MAX_TRIS> {
+    let mut pieces: ArrayVec<ArrayVec<[u32; 3], MAX_TRIS>, MAX_TRIS> = ArrayVec::new();
+
+    for tri in tris {
+        let touches = |piece: &ArrayVec<[u32; 3], MAX_TRIS>| {
+            piece.iter().flatten().any(|vtx| tri.contains(vtx))
+        };
+
+        // a triangle can join pieces that didn't touch before it
+        let mut merged: ArrayVec<[u32; 3], MAX_TRIS> = ArrayVec::new();
+        merged.push(*tri);
+
+        let mut i = 0;
+        while i < pieces.len() {
+            if touches(&pieces[i]) {
+                merged.extend(pieces.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        pieces.push(merged);
+    }
+
+    pieces
+}
+
+/// Flips the edges shared by two fan triangles whose third vertices are
+/// the feature vertices of neighbouring cells, so the two are joined
+/// directly.
+fn flip_feature_edges(indices: &mut [u32], features: &HashSet<u32>) {
+    let mut tris: Vec<[u32; 3]> = indices
+        .chunks(3)
+        .map(|tri| [tri[0], tri[1], tri[2]])
+        .collect();
+
+    let mut owners = HashMap::new();
+    let mut edges = HashSet::new();
+    for (t, tri) in tris.iter().enumerate() {
+        for i in 0..3 {
+            let (a, b) = (tri[i], tri[(i + 1) % 3]);
+            owners.insert((a, b), (t, tri[(i + 2) % 3]));
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+
+    let mut flipped = vec![false; tris.len()];
+
+    for t in 0..tris.len() {
+        for i in 0..3 {
+            let tri = tris[t];
+            let (a, b, feature) = (tri[i], tri[(i + 1) % 3], tri[(i + 2) % 3]);
+
+            // only the base of a fan, the edge opposite its feature vertex
+            let is_base =
+                features.contains(&feature) && !features.contains(&a) && !features.contains(&b);
+            if flipped[t] || !is_base {
+                continue;
+            }
+
+            let Some(&(u, other)) = owners.get(&(b, a)) else {
+                continue;
+            };
+
+            // joining the two must not duplicate an edge already there
+            let joined = (feature.min(other), feature.max(other));
+            if flipped[u]
+                || other == feature
+                || !features.contains(&other)
+                || edges.contains(&joined)
+            {
+                continue;
+            }
+
+            tris[t] = [a, other, feature];
+            tris[u] = [other, b, feature];
+            flipped[t] = true;
+            flipped[u] = true;
+            edges.insert(joined);
+        }
+    }
+
+    for (dst, tri) in indices.chunks_mut(3).zip(tris) {
+        dst.copy_from_slice(&tri);
+    }
+}
+
+/// The grid point an edge of `cell` starts at and the axis it runs along,
+/// the same for every cell sharing the edge.
+fn edge_start(cell: UVec3, edge: usize) -> (UVec3, usize) {
+    let (v0, v1) = EDGES[edge];
+    let (a, b) = (UVec3::from(VERTICES[v0]), UVec3::from(VERTICES[v1]));
+
+    let axis = (a.as_ivec3() - b.as_ivec3())
+        .abs()
+        .to_array()
+        .iter()
+        .position(|&d| d == 1)
+        .unwrap();
+
+    (cell + a.min(b), axis)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::mesh::unpack_material;
+
+    use super::*;
+    use crate::test::Xorshift;
+
+    fn random_map(size: u32, seed: u32) -> DensityMap {
+        let mut map = DensityMap::new(UVec3::splat(size));
+        let mut rng = Xorshift::new(seed);
+
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = rng.density();
+        }
+
+        map
+    }
+
+    /// Triangle edges without a matching edge in the opposite direction,
+    /// which aren't on the border of the map.
+    fn cracks(mesh: &ContourMesh, size: u32) -> usize {
+        let edges: HashSet<_> = mesh
+            .indices
+            .chunks(3)
+            .flat_map(|tri| (0..3).map(move |i| (tri[i], tri[(i + 1) % 3])))
+            .collect();
+
+        let on_border = |v: u32| {
+            let pos = mesh.positions[v as usize];
+            pos.cmpeq(Vec3::ZERO).any() || pos.cmpeq(Vec3::splat(size as f32)).any()
+        };
+
+        edges
+            .iter()
+            .filter(|&&(a, b)| !edges.contains(&(b, a)))
+            .filter(|&&(a, b)| !(on_border(a) && on_border(b)))
+            .count()
+    }
+
+    // V - E + F, for a single cell
+    fn euler_characteristic(mesh: &ContourMesh) -> i32 {
+        let edges: HashSet<_> = mesh
+            .indices
+            .chunks(3)
+            .flat_map(|tri| {
+                (0..3).map(move |i| (tri[i].min(tri[(i + 1) % 3]), tri[i].max(tri[(i + 1) % 3])))
+            })
+            .collect();
+
+        mesh.positions.len() as i32 - edges.len() as i32 + mesh.indices.len() as i32 / 3
+    }
+
+    #[test]
+    fn crack_free() {
+        for seed in 1..20 {
+            let map = random_map(8, seed);
+
+            for ambiguity in [Ambiguity::Fixed, Ambiguity::Resolved] {
+                let mesh = marching_cubes(&map, |pos| map.gradient(pos), ambiguity);
+                assert_eq!(cracks(&mesh, 8), 0, "seed {seed}, {ambiguity:?}");
+            }
+
+            let mesh = extended_marching_cubes(
+                &map,
+                |pos| map.gradient(pos),
+                &QefSettings::default(),
+                &FeatureSettings::default(),
+            );
+            assert_eq!(cracks(&mesh, 8), 0, "seed {seed}, extended");
+        }
+    }
+
+    #[test]
+    fn extended_keeps_ridges() {
+        // a roof along z, sloping down at 45° on both sides. The grid points
+        // on either side of the ridge only see one of the planes, so the
+        // crossings and their normals are exact
+        let apex = Vec3::new(2.5, 2.7, 0.0);
+        let planes = [Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0)].map(Vec3::normalize);
+        let plane = |pos: Vec3| {
+            let [a, b] = planes.map(|normal| normal.dot(pos - apex));
+            if a > b {
+                (a, planes[0])
+            } else {
+                (b, planes[1])
+            }
+        };
+
+        let mut map = DensityMap::new(UVec3::splat(5));
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = plane(pos.as_vec3()).0;
+        }
+
+        let gradient = |pos: UVec3| plane(pos.as_vec3()).1;
+        let plain = marching_cubes(&map, gradient, Ambiguity::Fixed);
+        let extended = extended_marching_cubes(
+            &map,
+            gradient,
+            &QefSettings::default(),
+            &FeatureSettings::default(),
+        );
+
+        let nearest = |mesh: &ContourMesh, target: Vec3| {
+            (0..mesh.positions.len())
+                .min_by(|&a, &b| {
+                    let distance = |i: usize| mesh.positions[i].distance(target);
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap() as u32
+        };
+        let distance =
+            |mesh: &ContourMesh, vtx: u32| mesh.positions[vtx as usize].xy().distance(apex.xy());
+
+        let ridge: Vec<u32> = (0..5)
+            .map(|z| nearest(&extended, apex.with_z(z as f32 + 0.5)))
+            .collect();
+
+        for (z, &vtx) in ridge.iter().enumerate() {
+            let target = apex.with_z(z as f32 + 0.5);
+            assert!(
+                distance(&extended, vtx) < 0.05,
+                "{}",
+                extended.positions[vtx as usize]
+            );
+            assert!(distance(&plain, nearest(&plain, target)) > 0.5);
+        }
+
+        // the flips join the feature vertices into a line along the ridge
+        let edges: HashSet<_> = extended
+            .indices
+            .chunks(3)
+            .flat_map(|tri| (0..3).map(move |i| (tri[i], tri[(i + 1) % 3])))
+            .collect();
+
+        for pair in ridge.windows(2) {
+            assert!(edges.contains(&(pair[0], pair[1])) || edges.contains(&(pair[1], pair[0])));
+        }
+
+        assert_eq!(cracks(&extended, 5), 0);
+    }
+
+    #[test]
+    fn resolved_tunnel() {
+        let mut map = DensityMap::new(UVec3::ONE);
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = -0.2;
+        }
+
+        // opposite corners that meet in the middle of the cell
+        map[UVec3::ZERO] = 1.0;
+        map[UVec3::ONE] = 1.0;
+
+        let fixed = marching_cubes(&map, |pos| map.gradient(pos), Ambiguity::Fixed);
+        let resolved = marching_cubes(&map, |pos| map.gradient(pos), Ambiguity::Resolved);
+
+        // two disks, and one tube
+        assert_eq!(euler_characteristic(&fixed), 2);
+        assert_eq!(euler_characteristic(&resolved), 0);
+    }
+
+    #[test]
+    fn materials_blend_along_edges() {
+        let mut map = random_map(4, 7);
+        for pos in crate::all_cells(map.grid_size()) {
+            map.set_material(pos, pos.x as u8 + 1);
+        }
+
+        let mesh = marching_cubes(&map, |pos| map.gradient(pos), Ambiguity::Fixed);
+        assert_eq!(mesh.materials.len(), mesh.positions.len());
+
+        let mut blended = 0;
+        for (pos, &packed) in mesh.positions.iter().zip(&mesh.materials) {
+            let (a, b, blend) = unpack_material(packed);
+
+            // only the edges along x have ends with different materials
+            let (start, end) = (pos.floor().as_uvec3(), pos.ceil().as_uvec3());
+            assert_eq!((a, b), (start.x as u8 + 1, end.x as u8 + 1), "{pos}");
+            if a != b {
+                // blend only has 8 bits
+                assert!((blend - pos.x.fract()).abs() < 0.01, "{pos}");
+                blended += (blend > 0.0) as usize;
+            }
+        }
+        assert!(blended > 0);
+    }
+
+    #[test]
+    fn air_material_stays_out_of_the_surface() {
+        let mut map = DensityMap::new(UVec3::splat(6));
+        for pos in crate::all_cells(map.grid_size()) {
+            let density = pos.as_vec3().distance(Vec3::splat(3.0)) - 2.2;
+            map[pos] = density;
+            // the air keeps the default material
+            if inside(density) {
+                map.set_material(pos, 2);
+            }
+        }
+
+        let mesh = marching_cubes(&map, |pos| map.gradient(pos), Ambiguity::Fixed);
+        assert!(!mesh.materials.is_empty());
+
+        for &packed in &mesh.materials {
+            let (a, b, _) = unpack_material(packed);
+            assert_eq!((a, b), (2, 2));
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::{
+        mesh::{Indices, MeshVertexAttribute, PrimitiveTopology},
+        render_resource::VertexFormat,
+    },
+    tasks::{block_on, futures_lite::future, Task},
+};
+
+use crate::{
+    dual_contouring::inside,
+    splat::{splat_weights, ATTRIBUTE_SPLAT_WEIGHTS},
+    uv::{uv_tangent, UvSettings},
+};
+
+/// Residual of the QEF each dual contouring vertex was placed with, high
+/// values mean the planes in the cell didn't agree on a point.
+pub const ATTRIBUTE_QEF_ERROR: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_QefError", 0x9ef0_e880, VertexFormat::Float32);
+
+/// The materials of the two samples a vertex is blended between and how
+/// far towards the second one it is, packed by [`pack_material`]. Vertices
+/// with a single material have it twice.
+pub const ATTRIBUTE_MATERIAL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Material", 0x3a7e_41a1, VertexFormat::Uint32);
+
+/// The first material in the lowest byte, the second in the next one and
+/// the blend towards it in the third, from 0 to 255. Same layout as
+/// `material` in `VertexInfo`.
+pub fn pack_material(a: u8, b: u8, t: f32) -> u32 {
+    let blend = (t.clamp(0.0, 1.0) * 255.0).round() as u32;
+    a as u32 | (b as u32) << 8 | blend << 16
+}
+
+/// The inverse of [`pack_material`], up to the precision of the blend.
+pub fn unpack_material(packed: u32) -> (u8, u8, f32) {
+    let blend = (packed >> 16) as u8 as f32 / 255.0;
+    (packed as u8, (packed >> 8) as u8, blend)
+}
+
+/// The material of the corner furthest inside the surface, which the dual
+/// methods give the vertices of a cell. 0 if none of them are inside.
+pub fn dominant_material(densities: [f32; 8], materials: [u8; 8]) -> u8 {
+    densities
+        .into_iter()
+        .zip(materials)
+        .filter(|&(density, _)| inside(density))
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map_or(0, |(_, material)| material)
+}
+
+/// An indexed triangle mesh built by one of the CPU meshers.
+#[derive(Clone, Debug, Default)]
+pub struct ContourMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+    /// Per vertex [`ATTRIBUTE_QEF_ERROR`], empty for meshers that don't
+    /// solve a QEF.
+    pub qef_errors: Vec<f32>,
+    /// Per vertex [`ATTRIBUTE_MATERIAL`].
+    pub materials: Vec<u32>,
+    /// Empty until [`ContourMesh::generate_uvs`] is called.
+    pub uvs: Vec<Vec2>,
+    pub tangents: Vec<Vec4>,
+}
+
+impl ContourMesh {
+    pub fn to_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default());
+        self.write_to(&mut mesh);
+        mesh
+    }
+
+    /// Fills in the UVs and tangents of the vertices, `transform` being the
+    /// one the mesh is drawn with so the projections line up in world
+    /// space.
+    pub fn generate_uvs(&mut self, settings: &UvSettings, transform: &Transform) {
+        (self.uvs, self.tangents) = self
+            .positions
+            .iter()
+            .zip(&self.normals)
+            .map(|(&pos, &normal)| {
+                let pos = transform.transform_point(pos);
+                let (uv, tangent) = uv_tangent(settings, pos, transform.rotation * normal);
+                let local = transform.rotation.inverse() * tangent.xyz();
+
+                (uv, local.extend(tangent.w))
+            })
+            .unzip();
+    }
+
+    /// Replaces the geometry of an existing mesh.
+    pub fn write_to(&self, mesh: &mut Mesh) {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals.clone());
+        mesh.insert_indices(Indices::U32(self.indices.clone()));
+
+        if self.uvs.is_empty() {
+            mesh.remove_attribute(Mesh::ATTRIBUTE_UV_0);
+            mesh.remove_attribute(Mesh::ATTRIBUTE_TANGENT);
+        } else {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs.clone());
+            mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, self.tangents.clone());
+        }
+
+        if self.materials.is_empty() {
+            mesh.remove_attribute(ATTRIBUTE_MATERIAL);
+            mesh.remove_attribute(ATTRIBUTE_SPLAT_WEIGHTS);
+        } else {
+            let weights: Vec<_> = self.materials.iter().map(|&m| splat_weights(m)).collect();
+            mesh.insert_attribute(ATTRIBUTE_MATERIAL, self.materials.clone());
+            mesh.insert_attribute(ATTRIBUTE_SPLAT_WEIGHTS, weights);
+        }
+
+        if self.qef_errors.is_empty() {
+            mesh.remove_attribute(ATTRIBUTE_QEF_ERROR);
+        } else {
+            mesh.insert_attribute(ATTRIBUTE_QEF_ERROR, self.qef_errors.clone());
+        }
+    }
+}
+
+/// At most this many meshing jobs run at once for each mesh. Edits made
+/// while they're all busy are picked up by the next one to start.
+pub const MAX_MESHING_JOBS: usize = 2;
+
+/// The CPU meshing jobs of a mesh, running on the
+/// [`AsyncComputeTaskPool`](bevy::tasks::AsyncComputeTaskPool) so big maps
+/// don't stall the frame.
+#[derive(Component, Default)]
+pub struct MeshingJobs {
+    /// Bumped every time a job starts, jobs are tagged with it.
+    pub generation: u64,
+    /// Generation of the mesh being shown. Jobs finishing with an older
+    /// one are stale and get dropped.
+    pub applied: u64,
+    /// Whether the inputs changed since the last job started.
+    pub pending: bool,
+    pub running: Vec<(u64, Task<ContourMesh>)>,
+}
+
+impl MeshingJobs {
+    /// Whether [`MAX_MESHING_JOBS`] are already running.
+    pub fn is_full(&self) -> bool {
+        self.running.len() >= MAX_MESHING_JOBS
+    }
+
+    /// Tags `task` with the next generation.
+    pub fn start(&mut self, task: Task<ContourMesh>) {
+        self.generation += 1;
+        self.running.push((self.generation, task));
+    }
+
+    /// The mesh of the newest finished job, unless a newer one was already
+    /// applied. Older jobs still running are cancelled.
+    pub fn take_finished(&mut self) -> Option<ContourMesh> {
+        let mut finished = None;
+
+        self.running.retain_mut(
+            |(generation, task)| match block_on(future::poll_once(task)) {
+                Some(contour) => {
+                    if finished
+                        .as_ref()
+                        .is_none_or(|&(newest, _)| *generation > newest)
+                    {
+                        finished = Some((*generation, contour));
+                    }
+                    false
+                }
+                None => true,
+            },
+        );
+
+        let (generation, contour) = finished?;
+        if generation <= self.applied {
+            return None;
+        }
+
+        self.applied = generation;
+        self.running.retain(|&(running, _)| running > generation);
+
+        Some(contour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use bevy::tasks::{AsyncComputeTaskPool, TaskPool};
+
+    use super::*;
+
+    /// A job of `generation` finishing with a mesh of that many vertices,
+    /// or never.
+    fn job(generation: u64, finishes: bool) -> (u64, Task<ContourMesh>) {
+        let pool = AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let task = if finishes {
+            pool.spawn(async move {
+                ContourMesh {
+                    positions: vec![Vec3::ZERO; generation as usize],
+                    ..default()
+                }
+            })
+        } else {
+            pool.spawn(future::pending())
+        };
+
+        (generation, task)
+    }
+
+    fn wait_for(jobs: &MeshingJobs, generation: u64) {
+        let (_, task) = jobs.running.iter().find(|(g, _)| *g == generation).unwrap();
+        while !task.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn newest_finished_job_is_applied() {
+        let mut jobs = MeshingJobs {
+            generation: 3,
+            running: vec![job(1, true), job(2, true), job(3, false)],
+            ..default()
+        };
+        wait_for(&jobs, 1);
+        wait_for(&jobs, 2);
+
+        let mesh = jobs.take_finished().unwrap();
+        assert_eq!(mesh.positions.len(), 2);
+        assert_eq!(jobs.applied, 2);
+
+        // the newer job is still running
+        assert_eq!(jobs.running.len(), 1);
+        assert!(jobs.take_finished().is_none());
+    }
+
+    #[test]
+    fn older_jobs_finishing_late_are_dropped() {
+        // the first job is cancelled when the second one is applied
+        let mut jobs = MeshingJobs {
+            generation: 2,
+            running: vec![job(1, false), job(2, true)],
+            ..default()
+        };
+        wait_for(&jobs, 2);
+
+        assert_eq!(jobs.take_finished().unwrap().positions.len(), 2);
+        assert!(jobs.running.is_empty());
+
+        // and one that finishes after a newer one was applied is stale
+        jobs.running.push(job(1, true));
+        wait_for(&jobs, 1);
+
+        assert!(jobs.take_finished().is_none());
+        assert!(jobs.running.is_empty());
+        assert_eq!(jobs.applied, 2);
+    }
+}
@@ -0,0 +1,634 @@
+//! Adaptive dual contouring over an octree, after Ju et al. 2002, "Dual
+//! Contouring of Hermite Data". Sibling leaves are collapsed into their
+//! parent while the merged QEF still fits within
+//! [`OctreeSettings::tolerance`] and the merge can't change the topology,
+//! and the mesh is built with the recursive cell, face and edge procedures.
+//! Octrees can also be put together from leaves of different sizes with
+//! [`Octree::from_leaves`], which is how chunks at different levels of
+//! detail get the seams between them.
+//!
+//! CPU only for now, the GPU path always contours the uniform grid.
+
+use bevy::prelude::*;
+
+use crate::{
+    cases::EDGE_COMPONENTS,
+    dual_contouring::{cell_crossings, inside, is_edge, write_quad, AXIS_TABLE, EDGE_OFFSETS},
+    mesh::{dominant_material, pack_material, ContourMesh},
+    qef::{Plane, Qef, QefSettings},
+    DensityMap, VERTICES,
+};
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct OctreeSettings {
+    /// Largest QEF error a collapsed leaf may have. 0 still collapses
+    /// regions where all the planes agree, like flat areas.
+    pub tolerance: f32,
+}
+
+impl Default for OctreeSettings {
+    fn default() -> Self {
+        Self { tolerance: 0.01 }
+    }
+}
+
+pub struct Octree {
+    root: Option<Node>,
+}
+
+/// Children are indexed by their offset in the parent, x in the lowest bit.
+/// Empty space is `None`.
+enum Node {
+    Internal(Box<[Option<Node>; 8]>),
+    Leaf(Leaf),
+}
+
+pub struct Leaf {
+    min: UVec3,
+    size: u32,
+    /// Densities at the corners, in the order of [`VERTICES`].
+    corners: [f32; 8],
+    /// The [`dominant_material`] of the corners.
+    material: u8,
+    qef: Qef,
+    /// Sum of the normals at the crossings.
+    normal: Vec3,
+    pos: Vec3,
+    error: f32,
+    /// Index of the leaf's vertex in the mesh.
+    index: u32,
+}
+
+struct Builder<'a, G> {
+    map: &'a DensityMap,
+    gradient: G,
+    qef: &'a QefSettings,
+    tolerance: f32,
+}
+
+fn child_offset(i: usize) -> UVec3 {
+    UVec3::new(i as u32 & 1, (i as u32 >> 1) & 1, (i as u32 >> 2) & 1)
+}
+
+fn child_index(bits: UVec3) -> usize {
+    (bits.x | bits.y << 1 | bits.z << 2) as usize
+}
+
+/// The two axes perpendicular to `axis`.
+fn perpendicular(axis: usize) -> (usize, usize) {
+    ((axis + 1) % 3, (axis + 2) % 3)
+}
+
+/// The index in [`VERTICES`] of the corner at `offset`.
+fn corner_index(offset: UVec3) -> usize {
+    VERTICES
+        .iter()
+        .position(|&corner| UVec3::from(corner) == offset)
+        .unwrap()
+}
+
+impl Leaf {
+    /// A leaf with its vertex at the minimum of `qef` within the leaf.
+    /// `normal` is the sum of the normals of the planes.
+    pub fn new(
+        qef: Qef,
+        normal: Vec3,
+        min: UVec3,
+        size: u32,
+        (corners, materials): ([f32; 8], [u8; 8]),
+        settings: &QefSettings,
+    ) -> Self {
+        let lo = min.as_vec3();
+        let solution = qef.solve(settings, lo, lo + size as f32);
+
+        Self {
+            min,
+            size,
+            corners,
+            material: dominant_material(corners, materials),
+            qef,
+            normal,
+            pos: solution.pos,
+            error: solution.error,
+            index: 0,
+        }
+    }
+
+    /// A leaf whose vertex was already placed, like the cells of a chunk
+    /// meshed on its own. It can't be collapsed with others.
+    pub fn placed(
+        min: UVec3,
+        size: u32,
+        (corners, materials): ([f32; 8], [u8; 8]),
+        pos: Vec3,
+        normal: Vec3,
+        error: f32,
+    ) -> Self {
+        Self {
+            min,
+            size,
+            corners,
+            material: dominant_material(corners, materials),
+            qef: Qef::default(),
+            normal,
+            pos,
+            error,
+            index: 0,
+        }
+    }
+}
+
+impl Node {
+    /// The child at `bits`. A leaf stands in for all of its children.
+    fn child(&self, bits: UVec3) -> Option<&Node> {
+        match self {
+            Node::Internal(children) => children[child_index(bits)].as_ref(),
+            Node::Leaf(_) => Some(self),
+        }
+    }
+
+    fn for_each_leaf<'a>(&'a self, f: &mut impl FnMut(&'a Leaf)) {
+        match self {
+            Node::Internal(children) => {
+                for child in children.iter().flatten() {
+                    child.for_each_leaf(f);
+                }
+            }
+            Node::Leaf(leaf) => f(leaf),
+        }
+    }
+
+    fn for_each_leaf_mut(&mut self, f: &mut impl FnMut(&mut Leaf)) {
+        match self {
+            Node::Internal(children) => {
+                for child in children.iter_mut().flatten() {
+                    child.for_each_leaf_mut(f);
+                }
+            }
+            Node::Leaf(leaf) => f(leaf),
+        }
+    }
+}
+
+impl<G: Fn(UVec3) -> Vec3> Builder<'_, G> {
+    /// The densities and materials at the corners of a leaf.
+    fn corners(&self, min: UVec3, size: u32) -> ([f32; 8], [u8; 8]) {
+        let corners = VERTICES.map(|corner| min + UVec3::from(corner) * size);
+        (
+            corners.map(|pos| self.map[pos]),
+            corners.map(|pos| self.map.material(pos)),
+        )
+    }
+
+    fn node(&self, min: UVec3, size: u32) -> Option<Node> {
+        // the root is rounded up to a power of two, the rest of it is empty
+        if min.cmpge(self.map.size()).any() {
+            return None;
+        }
+
+        if size == 1 {
+            let crossings = cell_crossings(self.map, min, &self.gradient);
+            if crossings.is_empty() {
+                return None;
+            }
+
+            let qef = Qef::from_planes(crossings.iter().map(|crossing| Plane {
+                pos: crossing.pos,
+                normal: crossing.normal,
+            }));
+            let normal = crossings.iter().map(|crossing| crossing.normal).sum();
+
+            let corners = self.corners(min, 1);
+            return Some(Node::Leaf(Leaf::new(
+                qef, normal, min, 1, corners, self.qef,
+            )));
+        }
+
+        let half = size / 2;
+        let children: [Option<Node>; 8] =
+            std::array::from_fn(|i| self.node(min + child_offset(i) * half, half));
+
+        if children.iter().all(Option::is_none) {
+            return None;
+        }
+
+        match self.collapse(&children, min, size) {
+            Some(leaf) => Some(Node::Leaf(leaf)),
+            None => Some(Node::Internal(Box::new(children))),
+        }
+    }
+
+    /// Merges `children` into a single leaf, if they're all leaves and the
+    /// merged vertex stays within the tolerance.
+    fn collapse(&self, children: &[Option<Node>; 8], min: UVec3, size: u32) -> Option<Leaf> {
+        // leaves sample their corners, they can't reach past the map
+        if (min + size).cmpgt(self.map.size()).any() || !self.preserves_topology(min, size) {
+            return None;
+        }
+
+        let mut qef = Qef::default();
+        let mut normal = Vec3::ZERO;
+
+        for child in children.iter().flatten() {
+            let Node::Leaf(leaf) = child else {
+                return None;
+            };
+
+            qef.merge(&leaf.qef);
+            normal += leaf.normal;
+        }
+
+        let corners = self.corners(min, size);
+        let leaf = Leaf::new(qef, normal, min, size, corners, self.qef);
+        (leaf.error <= self.tolerance).then_some(leaf)
+    }
+
+    /// Ju et al.'s safety test: the merged cell holds a single piece of
+    /// surface, and the samples at the middle of its edges, faces and
+    /// itself each match at least one of the corners around them.
+    fn preserves_topology(&self, min: UVec3, size: u32) -> bool {
+        let half = size / 2;
+        let sample = |pos: UVec3| inside(self.map[min + pos * half]);
+
+        let mut case = 0;
+        for (i, &corner) in VERTICES.iter().enumerate() {
+            if !sample(UVec3::from(corner) * 2) {
+                case |= 1 << i;
+            }
+        }
+
+        if EDGE_COMPONENTS[case].count > 1 {
+            return false;
+        }
+
+        crate::all_cells(UVec3::splat(3)).into_iter().all(|pos| {
+            // the corners around pos, a coordinate of 1 can round either way
+            let middle: Vec<usize> = (0..3).filter(|&axis| pos[axis] == 1).collect();
+
+            (0..1u32 << middle.len()).any(|bits| {
+                let mut corner = pos;
+                for (i, &axis) in middle.iter().enumerate() {
+                    corner[axis] = (bits >> i & 1) * 2;
+                }
+
+                sample(corner) == sample(pos)
+            })
+        })
+    }
+}
+
+impl Octree {
+    /// Builds the octree over the cells of `map`, bottom up from one leaf
+    /// per surface cell.
+    pub fn build(
+        map: &DensityMap,
+        gradient: impl Fn(UVec3) -> Vec3,
+        qef: &QefSettings,
+        settings: &OctreeSettings,
+    ) -> Self {
+        let builder = Builder {
+            map,
+            gradient,
+            qef,
+            tolerance: settings.tolerance,
+        };
+
+        let size = map.size().max_element().next_power_of_two();
+        let mut root = builder.node(UVec3::ZERO, size);
+
+        let mut count = 0;
+        if let Some(root) = &mut root {
+            root.for_each_leaf_mut(&mut |leaf| {
+                leaf.index = count;
+                count += 1;
+            });
+        }
+
+        Self { root }
+    }
+
+    /// An octree of `size` cells along each axis, a power of two, holding
+    /// `leaves`. The leaves mustn't overlap, and each has to be aligned to
+    /// its size. Their vertices are indexed in the order they're given.
+    pub fn from_leaves(size: u32, leaves: impl IntoIterator<Item = Leaf>) -> Self {
+        let mut root = None;
+
+        for (index, mut leaf) in leaves.into_iter().enumerate() {
+            leaf.index = index as u32;
+            insert(&mut root, UVec3::ZERO, size, leaf);
+        }
+
+        Self { root }
+    }
+
+    /// One vertex per leaf, and a quad for every minimal edge with a sign
+    /// change.
+    pub fn contour(&self) -> ContourMesh {
+        self.contour_where(|_, _| true)
+    }
+
+    /// [`Octree::contour`], but only emitting the quads `keep` accepts,
+    /// given the start of their edge and the indices of their four
+    /// vertices.
+    pub fn contour_where(&self, mut keep: impl FnMut(UVec3, [u32; 4]) -> bool) -> ContourMesh {
+        let mut mesh = ContourMesh::default();
+
+        let Some(root) = &self.root else {
+            return mesh;
+        };
+
+        let mut leaves = Vec::new();
+        root.for_each_leaf(&mut |leaf| leaves.push(leaf));
+        leaves.sort_by_key(|leaf| leaf.index);
+
+        for leaf in leaves {
+            mesh.positions.push(leaf.pos);
+            mesh.normals.push(leaf.normal.normalize_or_zero());
+            mesh.qef_errors.push(leaf.error);
+            mesh.materials
+                .push(pack_material(leaf.material, leaf.material, 0.0));
+        }
+
+        cell_proc(root, &mut keep, &mut mesh.indices);
+
+        mesh
+    }
+}
+
+fn insert(node: &mut Option<Node>, min: UVec3, size: u32, leaf: Leaf) {
+    if size == leaf.size {
+        *node = Some(Node::Leaf(leaf));
+        return;
+    }
+
+    let Node::Internal(children) = node.get_or_insert_with(|| Node::Internal(Box::default()))
+    else {
+        unreachable!("leaves overlap");
+    };
+
+    let half = size / 2;
+    let bits = (leaf.min - min) / half;
+    insert(
+        &mut children[child_index(bits)],
+        min + bits * half,
+        half,
+        leaf,
+    );
+}
+
+/// Dual contours `map` over an octree simplified to `settings.tolerance`.
+pub fn adaptive_dual_contour(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    qef: &QefSettings,
+    settings: &OctreeSettings,
+) -> ContourMesh {
+    Octree::build(map, gradient, qef, settings).contour()
+}
+
+/// The children touching half `s` of an edge along `axis`. `node_at` gives
+/// the node on each side of the edge, with the sides along the
+/// perpendicular axes set to 1 for the positive one. `across` is 1 along
+/// the axes where those nodes lie on either side of the edge rather than
+/// having it run through their middle.
+fn around_edge<'a>(
+    axis: usize,
+    s: u32,
+    across: UVec3,
+    node_at: impl Fn(UVec3) -> &'a Node,
+) -> [Option<&'a Node>; 4] {
+    let (p, q) = perpendicular(axis);
+
+    std::array::from_fn(|i| {
+        let mut side = UVec3::ZERO;
+        side[p] = i as u32 & 1;
+        side[q] = i as u32 >> 1;
+
+        // towards the edge
+        let mut bits = side ^ across;
+        bits[axis] = s;
+
+        node_at(side).child(bits)
+    })
+}
+
+/// Passed down the procedures, `keep` picks which quads make it into
+/// `indices`.
+type Keep<'a> = dyn FnMut(UVec3, [u32; 4]) -> bool + 'a;
+
+fn cell_proc(node: &Node, keep: &mut Keep, indices: &mut Vec<u32>) {
+    let Node::Internal(children) = node else {
+        return;
+    };
+
+    for child in children.iter().flatten() {
+        cell_proc(child, keep, indices);
+    }
+
+    for axis in 0..3 {
+        let (u, v) = perpendicular(axis);
+
+        // the four faces between the children along axis
+        for i in 0..4 {
+            let mut low = UVec3::ZERO;
+            low[u] = i & 1;
+            low[v] = i >> 1;
+
+            let mut high = low;
+            high[axis] = 1;
+
+            face_proc([node.child(low), node.child(high)], axis, keep, indices);
+        }
+
+        // and the two edges through the middle
+        for s in 0..2 {
+            edge_proc(
+                around_edge(axis, s, UVec3::ZERO, |_| node),
+                axis,
+                keep,
+                indices,
+            );
+        }
+    }
+}
+
+/// `nodes` are the two nodes on either side of a face perpendicular to
+/// `axis`, lowest first.
+fn face_proc(nodes: [Option<&Node>; 2], axis: usize, keep: &mut Keep, indices: &mut Vec<u32>) {
+    let [Some(low), Some(high)] = nodes else {
+        return;
+    };
+
+    if matches!((low, high), (Node::Leaf(_), Node::Leaf(_))) {
+        return;
+    }
+
+    let (u, v) = perpendicular(axis);
+
+    for i in 0..4 {
+        let mut bits = UVec3::ZERO;
+        bits[u] = i & 1;
+        bits[v] = i >> 1;
+
+        let mut low_bits = bits;
+        low_bits[axis] = 1;
+
+        face_proc([low.child(low_bits), high.child(bits)], axis, keep, indices);
+    }
+
+    // the four edges where the children of the face meet
+    for edge_axis in [u, v] {
+        for s in 0..2 {
+            let mut across = UVec3::ZERO;
+            across[axis] = 1;
+
+            let nodes = around_edge(edge_axis, s, across, |side| {
+                if side[axis] == 0 {
+                    low
+                } else {
+                    high
+                }
+            });
+            edge_proc(nodes, edge_axis, keep, indices);
+        }
+    }
+}
+
+/// `nodes` are the four nodes around an edge along `axis`, indexed by
+/// their sides as in [`around_edge`].
+fn edge_proc(nodes: [Option<&Node>; 4], axis: usize, keep: &mut Keep, indices: &mut Vec<u32>) {
+    let [Some(n0), Some(n1), Some(n2), Some(n3)] = nodes else {
+        return;
+    };
+    let nodes = [n0, n1, n2, n3];
+
+    let leaves = nodes.map(|node| match node {
+        Node::Leaf(leaf) => Some(leaf),
+        Node::Internal(_) => None,
+    });
+
+    if let [Some(l0), Some(l1), Some(l2), Some(l3)] = leaves {
+        return process_edge([l0, l1, l2, l3], axis, keep, indices);
+    }
+
+    let (p, q) = perpendicular(axis);
+
+    for s in 0..2 {
+        let children = around_edge(axis, s, UVec3::ONE, |side| {
+            nodes[(side[p] + side[q] * 2) as usize]
+        });
+        edge_proc(children, axis, keep, indices);
+    }
+}
+
+fn process_edge(leaves: [&Leaf; 4], axis: usize, keep: &mut Keep, indices: &mut Vec<u32>) {
+    let (p, q) = perpendicular(axis);
+
+    // the smallest leaf has the actual edge, the others only contain it
+    let (i, smallest) = leaves
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, leaf)| leaf.size)
+        .unwrap();
+
+    let mut corner = UVec3::ZERO;
+    corner[p] = 1 - (i as u32 & 1);
+    corner[q] = 1 - (i as u32 >> 1);
+
+    let a = smallest.corners[corner_index(corner)];
+    let b = smallest.corners[corner_index(corner + UVec3::AXES[axis])];
+    if !is_edge(a, b) {
+        return;
+    }
+
+    // in the order of EDGE_OFFSETS, an offset of 0 is the positive side
+    let quad = EDGE_OFFSETS[axis].map(|offset| {
+        let side = (offset + IVec3::ONE).as_uvec3();
+        leaves[(side[p] + side[q] * 2) as usize].index
+    });
+
+    if !keep(smallest.min + corner * smallest.size, quad) {
+        return;
+    }
+
+    let mut triangles = Vec::with_capacity(6);
+    write_quad(
+        &mut triangles,
+        quad,
+        AXIS_TABLE[inside(a) as usize * 3 + axis],
+    );
+
+    // a leaf next to two smaller ones shows up twice, leaving a triangle
+    for tri in triangles.chunks(3) {
+        if tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0] {
+            indices.extend_from_slice(tri);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::dual_contouring::{dual_contour, CellVertices};
+
+    fn density_map(size: u32, density: impl Fn(Vec3) -> f32) -> DensityMap {
+        let mut map = DensityMap::new(UVec3::splat(size));
+
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = density(pos.as_vec3());
+        }
+
+        map
+    }
+
+    /// Edges without a triangle running the other way along them.
+    fn open_edges(mesh: &ContourMesh) -> usize {
+        let edges: HashSet<_> = mesh
+            .indices
+            .chunks(3)
+            .flat_map(|tri| (0..3).map(move |i| (tri[i], tri[(i + 1) % 3])))
+            .collect();
+
+        edges
+            .iter()
+            .filter(|&&(a, b)| !edges.contains(&(b, a)))
+            .count()
+    }
+
+    #[test]
+    fn matches_the_uniform_grid_without_collapsing() {
+        let map = density_map(8, |pos| pos.distance(Vec3::splat(4.0)) - 2.7);
+        let settings = QefSettings::default();
+
+        let uniform = dual_contour(&map, |pos| map.gradient(pos), CellVertices::One, &settings);
+        let adaptive = adaptive_dual_contour(
+            &map,
+            |pos| map.gradient(pos),
+            &settings,
+            &OctreeSettings { tolerance: -1.0 },
+        );
+
+        assert_eq!(adaptive.positions.len(), uniform.positions.len());
+        assert_eq!(adaptive.indices.len(), uniform.indices.len());
+    }
+
+    #[test]
+    fn flat_faces_collapse_without_cracks() {
+        let map = density_map(16, |pos| (pos - 8.0).abs().max_element() - 5.5);
+        let settings = QefSettings::default();
+
+        let uniform = dual_contour(&map, |pos| map.gradient(pos), CellVertices::One, &settings);
+        let adaptive = adaptive_dual_contour(
+            &map,
+            |pos| map.gradient(pos),
+            &settings,
+            &OctreeSettings::default(),
+        );
+
+        assert!(adaptive.positions.len() < uniform.positions.len() * 2 / 3);
+        assert_eq!(open_edges(&uniform), 0);
+        assert_eq!(open_edges(&adaptive), 0);
+    }
+}
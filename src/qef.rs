@@ -0,0 +1,408 @@
+//! Quadratic error function minimization for placing dual contouring
+//! vertices. Mirrored in `qef.wgsl`, so keep the two in sync.
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+/// Number of Jacobi sweeps, 3x3 matrices converge well before this.
+const SWEEPS: usize = 6;
+
+/// The tangent plane at an edge crossing.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub pos: Vec3,
+    pub normal: Vec3,
+}
+
+/// How dual contouring vertices are placed, used by both the CPU mesher and
+/// `adaptivity.wgsl`, whose pipelines are specialized again when they
+/// change.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, PartialEq)]
+pub struct QefSettings {
+    /// Singular values smaller than this fraction of the largest one are
+    /// treated as zero, which keeps the solution at the mass point along
+    /// directions the planes don't constrain.
+    pub svd_threshold: f32,
+    /// Weight of an extra plane-like pull towards the mass point along
+    /// every axis.
+    pub mass_point_bias: f32,
+    /// Clamp the solution to the bounds passed to [`Qef::solve`].
+    pub clamp: bool,
+}
+
+impl Default for QefSettings {
+    fn default() -> Self {
+        Self {
+            svd_threshold: 0.1,
+            mass_point_bias: 0.01,
+            clamp: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QefSolution {
+    pub pos: Vec3,
+    /// Sum of the squared distances from `pos` to the planes.
+    pub error: f32,
+}
+
+/// The planes of a cell in the compact `AᵀA`, `Aᵀb`, `bᵀb` form, so cells
+/// can be merged without keeping every plane around.
+#[derive(Clone, Copy, Debug)]
+pub struct Qef {
+    ata: Mat3,
+    atb: Vec3,
+    btb: f32,
+    mass_point_sum: Vec3,
+    count: u32,
+}
+
+// can't be derived, Mat3 defaults to the identity
+impl Default for Qef {
+    fn default() -> Self {
+        Self {
+            ata: Mat3::ZERO,
+            atb: Vec3::ZERO,
+            btb: 0.0,
+            mass_point_sum: Vec3::ZERO,
+            count: 0,
+        }
+    }
+}
+
+impl Qef {
+    pub fn from_planes(planes: impl IntoIterator<Item = Plane>) -> Self {
+        let mut qef = Qef::default();
+        for plane in planes {
+            qef.add(plane);
+        }
+        qef
+    }
+
+    pub fn add(&mut self, Plane { pos, normal }: Plane) {
+        let b = normal.dot(pos);
+
+        self.ata += outer(normal, normal);
+        self.atb += normal * b;
+        self.btb += b * b;
+        self.mass_point_sum += pos;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &Qef) {
+        self.ata += other.ata;
+        self.atb += other.atb;
+        self.btb += other.btb;
+        self.mass_point_sum += other.mass_point_sum;
+        self.count += other.count;
+    }
+
+    /// Average of the plane positions.
+    pub fn mass_point(&self) -> Vec3 {
+        self.mass_point_sum / self.count.max(1) as f32
+    }
+
+    /// Sum of the squared distances from `pos` to the planes.
+    pub fn error(&self, pos: Vec3) -> f32 {
+        (pos.dot(self.ata * pos) - 2.0 * pos.dot(self.atb) + self.btb).max(0.0)
+    }
+
+    /// Finds the point minimizing [`Qef::error`] using a truncated SVD
+    /// pseudo-inverse, solved relative to the mass point.
+    pub fn solve(&self, settings: &QefSettings, min: Vec3, max: Vec3) -> QefSolution {
+        let mass_point = self.mass_point();
+
+        // the bias pulls towards the mass point, which is the origin of the
+        // relative system so it doesn't show up in Aᵀb
+        let ata = self.ata + Mat3::from_diagonal(Vec3::splat(settings.mass_point_bias));
+        let atb = self.atb - self.ata * mass_point;
+
+        let mut pos = mass_point + pseudo_inverse(ata, settings.svd_threshold) * atb;
+
+        if settings.clamp {
+            pos = pos.clamp(min, max);
+        }
+
+        QefSolution {
+            pos,
+            error: self.error(pos),
+        }
+    }
+}
+
+fn outer(a: Vec3, b: Vec3) -> Mat3 {
+    Mat3::from_cols(a * b.x, a * b.y, a * b.z)
+}
+
+/// Eigenvalues and eigenvectors (as columns) of a symmetric matrix, using
+/// cyclic Jacobi rotations. For the positive semi-definite `AᵀA` these are
+/// the squared singular values and right singular vectors of `A`.
+fn symmetric_eigen(mut a: Mat3) -> (Vec3, Mat3) {
+    let mut vectors = Mat3::IDENTITY;
+
+    for _ in 0..SWEEPS {
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            let apq = a.col(q)[p];
+            if apq.abs() < 1e-12 {
+                continue;
+            }
+
+            let app = a.col(p)[p];
+            let aqq = a.col(q)[q];
+
+            let theta = (aqq - app) / (2.0 * apq);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            let mut rotation = Mat3::IDENTITY.to_cols_array_2d();
+            rotation[p][p] = c;
+            rotation[q][q] = c;
+            rotation[q][p] = s;
+            rotation[p][q] = -s;
+            let rotation = Mat3::from_cols_array_2d(&rotation);
+
+            a = rotation.transpose() * a * rotation;
+            vectors *= rotation;
+        }
+    }
+
+    (Vec3::new(a.x_axis.x, a.y_axis.y, a.z_axis.z), vectors)
+}
+
+fn pseudo_inverse(a: Mat3, threshold: f32) -> Mat3 {
+    let (values, vectors) = symmetric_eigen(a);
+
+    // the eigenvalues are squared singular values
+    let cutoff = threshold * threshold * values.max_element();
+
+    let mut inverse = Mat3::ZERO;
+    for i in 0..3 {
+        if values[i] > cutoff && values[i] > 1e-12 {
+            let v = vectors.col(i);
+            inverse += outer(v, v) * (1.0 / values[i]);
+        }
+    }
+
+    inverse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{ShaderComposer, TestGpu};
+
+    fn plane(pos: Vec3, normal: Vec3) -> Plane {
+        Plane {
+            pos,
+            normal: normal.normalize(),
+        }
+    }
+
+    /// Most planes a case of `gpu_agrees_with_solve` can have.
+    const MAX_PLANES: usize = 4;
+
+    /// Without the bias, so the solutions are exact.
+    fn unbiased() -> QefSettings {
+        QefSettings {
+            mass_point_bias: 0.0,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn single_plane_stays_at_the_mass_point() {
+        let qef = Qef::from_planes([
+            plane(Vec3::new(0.2, 0.5, 0.3), Vec3::Y),
+            plane(Vec3::new(0.8, 0.5, 0.6), Vec3::Y),
+            plane(Vec3::new(0.5, 0.5, 0.9), Vec3::Y),
+        ]);
+
+        let solution = qef.solve(&QefSettings::default(), Vec3::ZERO, Vec3::ONE);
+        assert!(solution.pos.distance(qef.mass_point()) < 1e-5);
+        assert!(solution.error < 1e-6);
+    }
+
+    #[test]
+    fn three_planes_meet_at_the_corner() {
+        let corner = Vec3::new(0.3, 0.6, 0.7);
+        let qef = Qef::from_planes([
+            plane(Vec3::new(0.3, 0.1, 0.2), Vec3::X),
+            plane(Vec3::new(0.9, 0.6, 0.4), Vec3::Y),
+            plane(Vec3::new(0.1, 0.2, 0.7), Vec3::Z),
+        ]);
+
+        let solution = qef.solve(&unbiased(), Vec3::ZERO, Vec3::ONE);
+        assert!(solution.pos.distance(corner) < 1e-5, "{}", solution.pos);
+        assert!(solution.error < 1e-6);
+    }
+
+    #[test]
+    fn two_planes_meet_on_the_edge() {
+        let qef = Qef::from_planes([
+            plane(Vec3::new(0.4, 0.1, 0.2), Vec3::X),
+            plane(Vec3::new(0.4, 0.9, 0.6), Vec3::X),
+            plane(Vec3::new(0.1, 0.7, 0.3), Vec3::Y),
+            plane(Vec3::new(0.8, 0.7, 0.7), Vec3::Y),
+        ]);
+
+        // on the edge, and at the mass point along it
+        let solution = qef.solve(&unbiased(), Vec3::ZERO, Vec3::ONE);
+        let expected = Vec3::new(0.4, 0.7, qef.mass_point().z);
+        assert!(solution.pos.distance(expected) < 1e-5, "{}", solution.pos);
+    }
+
+    #[test]
+    fn clamped_solutions_stay_in_the_cell() {
+        // nearly parallel planes meeting well outside the cell
+        let qef = Qef::from_planes([
+            plane(Vec3::new(0.2, 0.4, 0.5), Vec3::new(0.3, 1.0, 0.0)),
+            plane(Vec3::new(0.8, 0.6, 0.5), Vec3::new(0.1, 1.0, 0.0)),
+        ]);
+        let (min, max) = (Vec3::ZERO, Vec3::ONE);
+
+        let settings = QefSettings {
+            svd_threshold: 0.0,
+            clamp: false,
+            ..unbiased()
+        };
+        let free = qef.solve(&settings, min, max);
+        assert!(free.pos.x < -1.0, "{}", free.pos);
+
+        let clamped = qef.solve(
+            &QefSettings {
+                clamp: true,
+                ..settings
+            },
+            min,
+            max,
+        );
+        assert!(clamped.pos.cmpge(min).all() && clamped.pos.cmple(max).all());
+        assert!(clamped.error >= free.error);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn gpu_agrees_with_solve() {
+        let gpu = TestGpu::new();
+
+        let corner = [
+            plane(Vec3::new(0.3, 0.1, 0.2), Vec3::X),
+            plane(Vec3::new(0.9, 0.6, 0.4), Vec3::Y),
+            plane(Vec3::new(0.1, 0.2, 0.7), Vec3::Z),
+        ];
+        let edge = [
+            plane(Vec3::new(0.4, 0.1, 0.2), Vec3::X),
+            plane(Vec3::new(0.4, 0.9, 0.6), Vec3::X),
+            plane(Vec3::new(0.1, 0.7, 0.3), Vec3::Y),
+            plane(Vec3::new(0.8, 0.7, 0.7), Vec3::Y),
+        ];
+        let outside = [
+            plane(Vec3::new(0.2, 0.4, 0.5), Vec3::new(0.3, 1.0, 0.0)),
+            plane(Vec3::new(0.8, 0.6, 0.5), Vec3::new(0.1, 1.0, 0.0)),
+        ];
+        // a sphere's tangent planes, which don't meet in a single point
+        let curved = [
+            Vec3::new(0.9, 0.2, 0.3),
+            Vec3::new(0.1, 0.8, 0.4),
+            Vec3::new(0.5, 0.5, 0.95),
+            Vec3::new(0.7, 0.9, 0.1),
+        ]
+        .map(|pos| plane(pos, pos - Vec3::splat(0.5)));
+
+        let settings = [
+            QefSettings::default(),
+            unbiased(),
+            QefSettings {
+                svd_threshold: 0.0,
+                clamp: false,
+                ..unbiased()
+            },
+        ];
+        let cases: Vec<(&[Plane], QefSettings)> = [&corner[..], &edge, &outside, &curved]
+            .into_iter()
+            .flat_map(|planes| settings.map(|settings| (planes, settings)))
+            .collect();
+
+        // laid out like Case below
+        let input: Vec<f32> = cases
+            .iter()
+            .flat_map(|(planes, settings)| {
+                let mut case = [0.0; MAX_PLANES * 8 + 4];
+                for (i, plane) in planes.iter().enumerate() {
+                    case[i * 8..i * 8 + 3].copy_from_slice(&plane.pos.to_array());
+                    case[i * 8 + 4..i * 8 + 7].copy_from_slice(&plane.normal.to_array());
+                }
+                case[MAX_PLANES * 8..].copy_from_slice(&[
+                    planes.len() as f32,
+                    settings.svd_threshold,
+                    settings.mass_point_bias,
+                    settings.clamp as u32 as f32,
+                ]);
+                case
+            })
+            .collect();
+
+        let source = format!(
+            "
+#import \"shaders/qef.wgsl\"::{{qef_new, qef_add, qef_solve}}
+
+struct Case {{
+    // position and normal of every plane
+    planes: array<vec4<f32>, {}>,
+    count: f32,
+    threshold: f32,
+    bias: f32,
+    clamp_to_bounds: f32,
+}};
+
+@group(0) @binding(0) var<storage, read> cases: array<Case>;
+@group(0) @binding(1) var<storage, read_write> solutions: array<vec4<f32>>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {{
+    if id.x >= arrayLength(&solutions) {{
+        return;
+    }}
+
+    let c = cases[id.x];
+    var qef = qef_new();
+    for (var i = 0u; i < u32(c.count); i++) {{
+        qef = qef_add(qef, c.planes[2u * i].xyz, c.planes[2u * i + 1u].xyz);
+    }}
+
+    let solution = qef_solve(qef, c.threshold, c.bias, c.clamp_to_bounds > 0.5, vec3(0.0), vec3(1.0));
+    solutions[id.x] = vec4(solution.pos, solution.error);
+}}
+",
+            MAX_PLANES * 2
+        );
+        let source = ShaderComposer::new()
+            .add_asset("shaders/qef.wgsl")
+            .compose_source(&source, "qef_test.wgsl", default());
+
+        let input = gpu.buffer(bytemuck::cast_slice(&input));
+        let output = gpu.buffer(&vec![0; cases.len() * size_of::<Vec4>()]);
+
+        gpu.dispatch(
+            source,
+            "main",
+            &[&[
+                (0, input.as_entire_binding()),
+                (1, output.as_entire_binding()),
+            ]],
+            UVec3::new(cases.len().div_ceil(64) as u32, 1, 1),
+        );
+
+        let solutions: Vec<Vec4> = bytemuck::cast_slice(&gpu.read_buffer(&output)).to_vec();
+
+        for ((planes, settings), gpu) in cases.iter().zip(solutions) {
+            let cpu =
+                Qef::from_planes(planes.iter().copied()).solve(settings, Vec3::ZERO, Vec3::ONE);
+            assert!(
+                cpu.pos.distance(gpu.truncate()) < 1e-4 && (cpu.error - gpu.w).abs() < 1e-4,
+                "{settings:?} {planes:?}: {cpu:?} on the CPU, {gpu} on the GPU"
+            );
+        }
+    }
+}
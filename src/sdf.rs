@@ -0,0 +1,539 @@
+use std::fmt::Write;
+
+use bevy::prelude::*;
+
+use crate::DensityMap;
+
+/// Import path of the WGSL module generated by [`Sdf::to_wgsl`].
+pub const SDF_SCENE_IMPORT_PATH: &str = "contouring::sdf_scene";
+
+const PRIMITIVES_WGSL: &str = include_str!("../assets/shaders/sdf_primitives.wgsl");
+
+/// A signed distance field built from primitives and CSG operators.
+///
+/// Negative values are inside the surface. [`Sdf::eval`] and the WGSL
+/// generated by [`Sdf::to_wgsl`] use the same formulas (see
+/// `sdf_primitives.wgsl`), so filling a [`DensityMap`] on the CPU gives the
+/// same field the GPU computes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sdf {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        center: Vec3,
+        half_extents: Vec3,
+    },
+    RoundedBox {
+        center: Vec3,
+        half_extents: Vec3,
+        radius: f32,
+    },
+    /// Lies in the plane perpendicular to the y axis.
+    Torus {
+        center: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    /// Along the y axis.
+    Cylinder {
+        center: Vec3,
+        radius: f32,
+        half_height: f32,
+    },
+    /// Along the y axis, with the base at `center.y - half_height` and the
+    /// tip at `center.y + half_height`.
+    Cone {
+        center: Vec3,
+        radius: f32,
+        half_height: f32,
+    },
+    /// Everything below the plane is inside. `normal` has to be normalized.
+    Plane {
+        normal: Vec3,
+        distance: f32,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    /// The first shape with the second cut out of it.
+    Subtraction(Box<Sdf>, Box<Sdf>),
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f32),
+    SmoothIntersection(Box<Sdf>, Box<Sdf>, f32),
+    SmoothSubtraction(Box<Sdf>, Box<Sdf>, f32),
+}
+
+impl Default for Sdf {
+    fn default() -> Self {
+        Sdf::Sphere {
+            center: Vec3::splat(2.5),
+            radius: 1.0,
+        }
+    }
+}
+
+impl Sdf {
+    /// A bit of every primitive and operator, fitting in the default
+    /// density map.
+    pub fn showcase() -> Self {
+        let center = Vec3::splat(2.5);
+
+        let body = Sdf::RoundedBox {
+            center,
+            half_extents: Vec3::splat(1.6),
+            radius: 0.4,
+        }
+        .smooth_intersection(
+            Sdf::Sphere {
+                center,
+                radius: 2.1,
+            },
+            0.3,
+        );
+
+        let holes = Sdf::Cylinder {
+            center,
+            radius: 0.6,
+            half_height: 3.0,
+        }
+        .union(Sdf::Capsule {
+            a: Vec3::new(0.0, 2.5, 2.5),
+            b: Vec3::new(5.0, 2.5, 2.5),
+            radius: 0.5,
+        });
+
+        let groove = Sdf::Torus {
+            center: Vec3::new(2.5, 4.1, 2.5),
+            major_radius: 1.2,
+            minor_radius: 0.3,
+        };
+
+        // cut flat at the bottom and stood on a plinth, with a spike on top
+        let floor = Sdf::Plane {
+            normal: Vec3::NEG_Y,
+            distance: -0.8,
+        };
+        let plinth = Sdf::Box {
+            center: Vec3::new(2.5, 0.6, 2.5),
+            half_extents: Vec3::new(1.8, 0.2, 1.8),
+        };
+        let spike = Sdf::Cone {
+            center: Vec3::new(2.5, 4.4, 2.5),
+            radius: 0.5,
+            half_height: 0.5,
+        };
+
+        body.subtraction(holes)
+            .smooth_subtraction(groove, 0.2)
+            .intersection(floor)
+            .union(plinth)
+            .smooth_union(spike, 0.2)
+    }
+
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtraction(self, other: Sdf) -> Sdf {
+        Sdf::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn smooth_intersection(self, other: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothIntersection(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn smooth_subtraction(self, other: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothSubtraction(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn eval(&self, pos: Vec3) -> f32 {
+        match self {
+            &Sdf::Sphere { center, radius } => (pos - center).length() - radius,
+            &Sdf::Box {
+                center,
+                half_extents,
+            } => sd_box(pos - center, half_extents),
+            &Sdf::RoundedBox {
+                center,
+                half_extents,
+                radius,
+            } => sd_box(pos - center, half_extents - radius) - radius,
+            &Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let p = pos - center;
+                let q = Vec2::new(p.xz().length() - major_radius, p.y);
+                q.length() - minor_radius
+            }
+            &Sdf::Capsule { a, b, radius } => {
+                let pa = pos - a;
+                let ba = b - a;
+                let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            &Sdf::Cylinder {
+                center,
+                radius,
+                half_height,
+            } => {
+                let p = pos - center;
+                let d = Vec2::new(p.xz().length(), p.y).abs() - Vec2::new(radius, half_height);
+                d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
+            }
+            &Sdf::Cone {
+                center,
+                radius,
+                half_height,
+            } => sd_cone(pos - center, radius, half_height),
+            &Sdf::Plane { normal, distance } => pos.dot(normal) - distance,
+            Sdf::Union(a, b) => a.eval(pos).min(b.eval(pos)),
+            Sdf::Intersection(a, b) => a.eval(pos).max(b.eval(pos)),
+            Sdf::Subtraction(a, b) => a.eval(pos).max(-b.eval(pos)),
+            &Sdf::SmoothUnion(ref a, ref b, k) => {
+                let (a, b) = (a.eval(pos), b.eval(pos));
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+                mix(b, a, h) - k * h * (1.0 - h)
+            }
+            &Sdf::SmoothIntersection(ref a, ref b, k) => {
+                let (a, b) = (a.eval(pos), b.eval(pos));
+                let h = (0.5 - 0.5 * (b - a) / k).clamp(0.0, 1.0);
+                mix(b, a, h) + k * h * (1.0 - h)
+            }
+            &Sdf::SmoothSubtraction(ref a, ref b, k) => {
+                let (a, b) = (a.eval(pos), b.eval(pos));
+                let h = (0.5 - 0.5 * (a + b) / k).clamp(0.0, 1.0);
+                mix(a, -b, h) + k * h * (1.0 - h)
+            }
+        }
+    }
+
+    /// Samples the field at every grid point of `map`.
+    pub fn fill(&self, map: &mut DensityMap) {
+        let grid = map.grid_size();
+
+        for x in 0..grid.x {
+            for y in 0..grid.y {
+                for z in 0..grid.z {
+                    let pos = UVec3 { x, y, z };
+                    map[pos] = self.eval(pos.as_vec3());
+                }
+            }
+        }
+    }
+
+    /// Generates a WGSL module with the import path
+    /// [`SDF_SCENE_IMPORT_PATH`] that exports `fn sdf(pos: vec3<f32>) -> f32`.
+    pub fn to_wgsl(&self) -> String {
+        let mut body = String::new();
+        let result = self.write_wgsl(&mut body, &mut 0);
+
+        format!(
+            "#define_import_path {SDF_SCENE_IMPORT_PATH}\n\n\
+             {PRIMITIVES_WGSL}\n\
+             fn sdf(pos: vec3<f32>) -> f32 {{\n\
+             {body}    return {result};\n\
+             }}\n"
+        )
+    }
+
+    // writes one `let` per node and returns the name holding its distance
+    fn write_wgsl(&self, out: &mut String, counter: &mut u32) -> String {
+        let expr = match self {
+            &Sdf::Sphere { center, radius } => {
+                format!("sd_sphere(pos - {}, {})", vec3(center), float(radius))
+            }
+            &Sdf::Box {
+                center,
+                half_extents,
+            } => format!("sd_box(pos - {}, {})", vec3(center), vec3(half_extents)),
+            &Sdf::RoundedBox {
+                center,
+                half_extents,
+                radius,
+            } => format!(
+                "sd_rounded_box(pos - {}, {}, {})",
+                vec3(center),
+                vec3(half_extents),
+                float(radius)
+            ),
+            &Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => format!(
+                "sd_torus(pos - {}, {}, {})",
+                vec3(center),
+                float(major_radius),
+                float(minor_radius)
+            ),
+            &Sdf::Capsule { a, b, radius } => format!(
+                "sd_capsule(pos, {}, {}, {})",
+                vec3(a),
+                vec3(b),
+                float(radius)
+            ),
+            &Sdf::Cylinder {
+                center,
+                radius,
+                half_height,
+            } => format!(
+                "sd_cylinder(pos - {}, {}, {})",
+                vec3(center),
+                float(radius),
+                float(half_height)
+            ),
+            &Sdf::Cone {
+                center,
+                radius,
+                half_height,
+            } => format!(
+                "sd_cone(pos - {}, {}, {})",
+                vec3(center),
+                float(radius),
+                float(half_height)
+            ),
+            &Sdf::Plane { normal, distance } => {
+                format!("sd_plane(pos, {}, {})", vec3(normal), float(distance))
+            }
+            Sdf::Union(a, b) => {
+                let (a, b) = (a.write_wgsl(out, counter), b.write_wgsl(out, counter));
+                format!("op_union({a}, {b})")
+            }
+            Sdf::Intersection(a, b) => {
+                let (a, b) = (a.write_wgsl(out, counter), b.write_wgsl(out, counter));
+                format!("op_intersection({a}, {b})")
+            }
+            Sdf::Subtraction(a, b) => {
+                let (a, b) = (a.write_wgsl(out, counter), b.write_wgsl(out, counter));
+                format!("op_subtraction({a}, {b})")
+            }
+            &Sdf::SmoothUnion(ref a, ref b, k) => {
+                let (a, b) = (a.write_wgsl(out, counter), b.write_wgsl(out, counter));
+                format!("op_smooth_union({a}, {b}, {})", float(k))
+            }
+            &Sdf::SmoothIntersection(ref a, ref b, k) => {
+                let (a, b) = (a.write_wgsl(out, counter), b.write_wgsl(out, counter));
+                format!("op_smooth_intersection({a}, {b}, {})", float(k))
+            }
+            &Sdf::SmoothSubtraction(ref a, ref b, k) => {
+                let (a, b) = (a.write_wgsl(out, counter), b.write_wgsl(out, counter));
+                format!("op_smooth_subtraction({a}, {b}, {})", float(k))
+            }
+        };
+
+        let name = format!("d{counter}");
+        *counter += 1;
+
+        writeln!(out, "    let {name} = {expr};").unwrap();
+
+        name
+    }
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+fn sd_box(p: Vec3, half_extents: Vec3) -> f32 {
+    let q = p.abs() - half_extents;
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+}
+
+fn sd_cone(p: Vec3, radius: f32, half_height: f32) -> f32 {
+    let q = Vec2::new(p.xz().length(), p.y);
+    let k1 = Vec2::new(0.0, half_height);
+    let k2 = Vec2::new(-radius, 2.0 * half_height);
+
+    let r = if q.y < 0.0 { radius } else { 0.0 };
+
+    let ca = Vec2::new(q.x - q.x.min(r), q.y.abs() - half_height);
+    let cb = q - k1 + k2 * ((k1 - q).dot(k2) / k2.dot(k2)).clamp(0.0, 1.0);
+
+    let s = if cb.x < 0.0 && ca.y < 0.0 { -1.0 } else { 1.0 };
+
+    s * ca.dot(ca).min(cb.dot(cb)).sqrt()
+}
+
+// `{:?}` always prints a decimal point or an exponent, so the literal
+// can't be mistaken for an integer. WGSL has no literals for infinities or
+// NaN, those are clamped to the largest finite values and zero
+fn float(x: f32) -> String {
+    if !x.is_finite() {
+        log::warn!("Replacing {x} in the generated SDF shader");
+    }
+
+    let x = if x.is_nan() {
+        0.0
+    } else {
+        x.clamp(f32::MIN, f32::MAX)
+    };
+
+    format!("{x:?}")
+}
+
+fn vec3(v: Vec3) -> String {
+    format!("vec3<f32>({}, {}, {})", float(v.x), float(v.y), float(v.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::ShaderSource;
+    use wgpu_core::naga;
+
+    use super::*;
+    use crate::test::{ShaderComposer, TestGpu};
+
+    /// The generated module without the `naga_oil` directive, so it can be
+    /// used on its own.
+    fn standalone(sdf: &Sdf) -> String {
+        sdf.to_wgsl().replacen(
+            &format!("#define_import_path {SDF_SCENE_IMPORT_PATH}"),
+            "",
+            1,
+        )
+    }
+
+    fn validate(source: &str) {
+        let module = naga::front::wgsl::parse_str(source).unwrap_or_else(|err| {
+            panic!("{}\n{source}", err.emit_to_string(source));
+        });
+
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .unwrap_or_else(|err| panic!("{}\n{source}", err.emit_to_string(source)));
+    }
+
+    #[test]
+    fn generated_wgsl_is_valid() {
+        validate(&standalone(&Sdf::showcase()));
+
+        let odd = Sdf::Sphere {
+            center: Vec3::new(f32::NAN, 1e-30, -0.0),
+            radius: f32::INFINITY,
+        }
+        .smooth_union(Sdf::default(), f32::NEG_INFINITY);
+        validate(&standalone(&odd));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn gpu_agrees_with_eval() {
+        let gpu = TestGpu::new();
+
+        let scene = Sdf::showcase();
+
+        // off the grid, so every primitive is hit somewhere in between
+        let points: Vec<Vec4> = crate::all_cells(UVec3::splat(12))
+            .into_iter()
+            .map(|pos| (pos.as_vec3() * 0.45 + 0.1).extend(0.0))
+            .collect();
+
+        let source = format!(
+            "{}
+@group(0) @binding(0) var<storage, read> points: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> distances: array<f32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {{
+    if id.x < arrayLength(&distances) {{
+        distances[id.x] = sdf(points[id.x].xyz);
+    }}
+}}
+",
+            standalone(&scene)
+        );
+
+        let input = gpu.buffer(bytemuck::cast_slice(&points));
+        let output = gpu.buffer(&vec![0; points.len() * size_of::<f32>()]);
+
+        gpu.dispatch(
+            ShaderSource::Wgsl(source.into()),
+            "main",
+            &[&[
+                (0, input.as_entire_binding()),
+                (1, output.as_entire_binding()),
+            ]],
+            UVec3::new(points.len().div_ceil(64) as u32, 1, 1),
+        );
+
+        let distances: Vec<f32> = bytemuck::cast_slice(&gpu.read_buffer(&output)).to_vec();
+
+        for (point, gpu) in points.iter().zip(distances) {
+            let cpu = scene.eval(point.truncate());
+            assert!(
+                (cpu - gpu).abs() < 1e-4,
+                "{point}: {cpu} on the CPU, {gpu} on the GPU"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter with 3d storage textures"]
+    fn compute_sdf_writes_the_gradient() {
+        let gpu = TestGpu::new();
+        gpu.require_3d_storage_textures();
+
+        let scene = Sdf::showcase();
+        let size = UVec3::splat(6);
+
+        let source = ShaderComposer::new()
+            .add_asset("shaders/bindings.wgsl")
+            .add_module(&scene.to_wgsl())
+            .compose("shaders/sdf.wgsl", default());
+
+        let input_tex = gpu.texture_3d(size, wgpu::TextureFormat::R32Float);
+        let normal_tex = gpu.texture_3d(size, wgpu::TextureFormat::Rgba8Snorm);
+        let input_view = input_tex.create_view(&default());
+        let normal_view = normal_tex.create_view(&default());
+
+        gpu.dispatch(
+            source,
+            "compute_sdf",
+            &[
+                &[(0, wgpu::BindingResource::TextureView(&input_view))],
+                &[(0, wgpu::BindingResource::TextureView(&normal_view))],
+            ],
+            crate::shader::workgroups(size).into(),
+        );
+
+        let normals: Vec<[i8; 4]> = bytemuck::cast_slice(&gpu.read_texture(&normal_tex)).to_vec();
+
+        for pos in crate::all_cells(size) {
+            let gpu = normals[(pos.x + size.x * (pos.y + size.y * pos.z)) as usize];
+
+            // same step as sdf_grad
+            let pos = pos.as_vec3();
+            let e = 0.01;
+            let cpu = Vec3::new(
+                scene.eval(pos + Vec3::X * e) - scene.eval(pos - Vec3::X * e),
+                scene.eval(pos + Vec3::Y * e) - scene.eval(pos - Vec3::Y * e),
+                scene.eval(pos + Vec3::Z * e) - scene.eval(pos - Vec3::Z * e),
+            )
+            .normalize_or_zero();
+            let gpu = Vec3::new(gpu[0] as f32, gpu[1] as f32, gpu[2] as f32) / 127.0;
+
+            assert!(
+                cpu.distance(gpu) < 0.03,
+                "{pos}: {cpu} on the CPU, {gpu} on the GPU"
+            );
+        }
+    }
+}
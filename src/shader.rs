@@ -1,46 +1,217 @@
-use std::{borrow::Cow, mem};
+use std::{
+    borrow::Cow,
+    mem,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 
 use bevy::{
     asset::RenderAssetUsages,
-    color::palettes::css::WHITE,
+    core_pipeline::{
+        core_3d::{Opaque3d, Opaque3dBinKey},
+        oit::OrderIndependentTransparencySettings,
+        prepass::{DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass},
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    image::ImageSampler,
+    math::{Affine3, Affine3A},
+    pbr::{
+        irradiance_volume::IrradianceVolume, screen_space_specular_transmission_pipeline_key,
+        tonemapping_pipeline_key, MaterialPipeline, MaterialPipelineKey, MeshFlags,
+        MeshPipelineKey, MeshTransforms, MeshUniform, PreparedMaterial, RenderViewLightProbes,
+        ScreenSpaceAmbientOcclusion, SetMeshViewBindGroup, ShadowFilteringMethod,
+    },
     prelude::*,
     render::{
+        camera::TemporalJitter,
         extract_resource::{ExtractResource, ExtractResourcePlugin},
         gpu_readback::{GpuReadbackPlugin, Readback, ReadbackComplete},
-        mesh::{allocator::MeshAllocator, Indices, MeshVertexAttribute},
+        mesh::{
+            allocator::MeshAllocator, Indices, MeshVertexAttribute, MeshVertexBufferLayoutRef,
+            MeshVertexBufferLayouts, PrimitiveTopology,
+        },
         render_asset::RenderAssets,
         render_graph::{self, RenderGraph, RenderLabel},
-        render_resource::*,
+        render_phase::{
+            AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, PhaseItem, RenderCommand,
+            RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+        },
+        render_resource::{encase::internal::ReadFrom, *},
         renderer::{RenderContext, RenderDevice, RenderQueue},
         storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
+        sync_world::{MainEntity, RenderEntity},
         texture::GpuImage,
-        Render, RenderApp, RenderSet,
+        view::ExtractedView,
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
     },
 };
 
-const SIZE_GRID: u32 = crate::N as u32 + 1;
-const SIZE_CELLS: u32 = crate::N as u32;
+use crate::{
+    cases::edge_components_wgsl,
+    mesh::{ATTRIBUTE_MATERIAL, ATTRIBUTE_QEF_ERROR},
+    qef::QefSettings,
+    sdf::{Sdf, SDF_SCENE_IMPORT_PATH},
+    splat::{splat_weights, SplatLayers, SplatMaterial, ATTRIBUTE_SPLAT_WEIGHTS},
+    surface_nets::SurfaceNetsSettings,
+    uv::UvSettings,
+    DensityMap,
+};
+
+/// Where the densities fed to the contouring passes come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DensitySource {
+    /// Evaluate the SDF in `sdf.wgsl` on the GPU.
+    #[default]
+    Sdf,
+    /// Upload the [`DensityMap`] whenever it's edited. The gradients are
+    /// computed from the uploaded densities.
+    Cpu,
+}
+
+/// How the GPU places the vertex of each surface cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mesher {
+    /// Minimize the QEF of the edge crossings, see [`QefSettings`].
+    #[default]
+    DualContouring,
+    /// Average the edge crossings, then relax the vertices as configured by
+    /// [`SurfaceNetsSettings`].
+    SurfaceNets,
+    /// Like [`Mesher::DualContouring`], but with a vertex for every
+    /// separate piece of surface in a cell so the mesh stays manifold.
+    ManifoldDualContouring,
+}
 
-const SIZE_CELLS_3: u32 = SIZE_CELLS * SIZE_CELLS * SIZE_CELLS;
-const SIZE_GRID_3: u32 = SIZE_GRID * SIZE_GRID * SIZE_GRID;
+/// The SDF evaluated by `compute_sdf`. With [`DensitySource::Cpu`] it's
+/// used to fill the [`DensityMap`] instead, whenever it changes.
+#[derive(Resource, Clone, Deref, DerefMut)]
+pub struct SdfScene(pub Sdf);
+
+impl Default for SdfScene {
+    fn default() -> Self {
+        Self(Sdf::showcase())
+    }
+}
+
+/// Handle of the shader generated from [`SdfScene`], imported by `sdf.wgsl`
+/// as `contouring::sdf_scene`.
+const SDF_SCENE_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(0x5df5ce4e);
+
+/// Handle of the shader generated from [`Subcases::components`], imported
+/// by `manifold.wgsl` as `contouring::edge_components`.
+///
+/// [`Subcases::components`]: crate::cases::Subcases::components
+const EDGE_COMPONENTS_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(0xed6ec0);
+
+#[derive(Resource, Clone, ExtractResource)]
+pub struct ContouringSettings {
+    source: DensitySource,
+    /// Can be switched at runtime, both variants of the vertex pipeline
+    /// are always compiled.
+    pub mesher: Mesher,
+    /// Read the mesh back into a [`Mesh`] asset every frame, for physics or
+    /// exporting. It's drawn straight from the GPU buffers either way.
+    pub readback: bool,
+}
+
+/// Forces the GPU to contour again, even if nothing it reads has changed.
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct RequestRemesh;
+
+/// Bumped whenever the contouring output is out of date, see
+/// [`mark_remesh`]. The render world only dispatches when this is ahead of
+/// the generation it last contoured, so idle scenes cost nothing on the
+/// GPU.
+#[derive(Resource, Clone, Copy, Default, PartialEq, Eq, ExtractResource)]
+struct RemeshGeneration(u32);
+
+/// The last [`RemeshGeneration`] [`ContouringNode`] couldn't dispatch
+/// because one of its pipelines failed to compile, shared with the node so
+/// [`sync_readbacks`] stops waiting for it.
+#[derive(Resource, Clone, Default)]
+struct FailedGeneration(Arc<AtomicU32>);
+
+/// A box of samples from the [`DensityMap`] to be written into the input
+/// texture.
+#[derive(Clone)]
+struct DensityUpload {
+    /// Size of the map the samples were taken from, uploads for a
+    /// different size than the current textures are dropped.
+    size: UVec3,
+    origin: UVec3,
+    extent: UVec3,
+    data: Vec<f32>,
+    materials: Vec<u8>,
+}
+
+/// Uploads queued this frame, cleared again at the start of the next one.
+#[derive(Resource, Clone, Default, ExtractResource)]
+struct DensityUploads(Vec<DensityUpload>);
+
+/// Uploads that couldn't be written yet because their texture wasn't
+/// prepared.
+#[derive(Resource, Default)]
+struct PendingDensityUploads(Vec<DensityUpload>);
 
 #[derive(Resource)]
 struct DualContouringPipeline {
     sdf_pipeline: CachedComputePipelineId,
+    gradient_pipeline: CachedComputePipelineId,
     vertex_pipeline: CachedComputePipelineId,
     edge_pipeline: CachedComputePipelineId,
     adaptivity_pipeline: CachedComputePipelineId,
+    /// `adaptivity_pipeline` for [`Mesher::SurfaceNets`].
+    surface_nets_pipeline: CachedComputePipelineId,
+    relax_pipeline: CachedComputePipelineId,
+    apply_relaxation_pipeline: CachedComputePipelineId,
+    /// The vertex, edge and adaptivity pipelines for
+    /// [`Mesher::ManifoldDualContouring`].
+    manifold_vertex_pipeline: CachedComputePipelineId,
+    manifold_edge_pipeline: CachedComputePipelineId,
+    manifold_adaptivity_pipeline: CachedComputePipelineId,
     cleanup_pipeline: CachedComputePipelineId,
+    /// Turns the vertex count into the arguments of the per vertex passes.
+    vertex_dispatch_pipeline: CachedComputePipelineId,
+    uv_pipeline: CachedComputePipelineId,
     bind_group_layout: BindGroupLayout,
     adaptivity_bind_group_layout: BindGroupLayout,
     sdf_bind_group_layout: BindGroupLayout,
+    uv_bind_group_layout: BindGroupLayout,
+    adaptivity_shader: Handle<Shader>,
+    /// The settings `adaptivity_pipeline` and `manifold_adaptivity_pipeline`
+    /// were specialized on, see [`specialize_qef_pipelines`].
+    qef_settings: QefSettings,
 }
 
+/// `Counts` in `bindings.wgsl`, which doubles as the arguments of the
+/// indexed indirect draw.
 #[derive(ShaderType, Clone, Copy, Debug, Default)]
 #[repr(C)]
 struct MeshCounts {
-    vtx: u32,
     idx: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    vtx: u32,
+    idx_reserved: u32,
+    overflow: u32,
+}
+
+// same as in bindings.wgsl
+const OVERFLOW_VERTICES: u32 = 1;
+const OVERFLOW_INDICES: u32 = 2;
+const WORKGROUP_SIZE: u32 = 4;
+
+/// Workgroups of [`WORKGROUP_SIZE`] cubed invocations covering `size`, the
+/// kernels skip the invocations hanging over its far sides.
+pub(crate) fn workgroups(size: UVec3) -> (u32, u32, u32) {
+    let groups = (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+    (groups.x, groups.y, groups.z)
 }
 
 #[derive(ShaderType, Clone, Copy, Debug, Default)]
@@ -49,23 +220,37 @@ struct DispatchIndirectArgs {
     x: u32,
     y: u32,
     z: u32,
+    vertices: u32,
 }
 
-#[derive(Resource, Clone, ExtractResource)]
+#[derive(Resource, Clone, PartialEq, ExtractResource)]
 struct DualContouringResources {
+    /// Number of cells along each axis the resources were allocated for.
+    size: UVec3,
     input: Handle<Image>,
+    /// [`DensityMap`] material IDs, uploaded along with the densities.
+    materials: Handle<Image>,
     index_lookup: Handle<Image>,
     debug_tex: Handle<Image>,
     vertex_buffer: Handle<ShaderStorageBuffer>,
     index_buffer: Handle<ShaderStorageBuffer>,
+    /// Number of vertices and indices the buffers have room for, starting
+    /// from [`initial_capacity`] and grown by [`grow_buffers`] when a
+    /// dispatch asks for more.
+    vertex_capacity: u32,
+    index_capacity: u32,
     count_buffer: Handle<ShaderStorageBuffer>,
     indirect_buffer: Handle<ShaderStorageBuffer>,
+    /// Scratch space for the Surface Nets relaxation, one slot per vertex.
+    relax_buffer: Handle<ShaderStorageBuffer>,
     mesh_handle: Handle<Mesh>,
     normal: Handle<Image>,
 }
 
 #[derive(Resource)]
 struct DualContouringBindGroup {
+    /// The resources this bind group was created from.
+    resources: DualContouringResources,
     group: BindGroup,
     adaptivity_group: BindGroup,
     sdf_group: BindGroup,
@@ -91,6 +276,8 @@ impl FromWorld for DualContouringPipeline {
             }
         }
 
+        let qef_settings = *world.resource::<QefSettings>();
+
         let render_device = world.resource::<RenderDevice>();
         let bind_group_layout = render_device.create_bind_group_layout(
             "March group layout",
@@ -113,6 +300,8 @@ impl FromWorld for DualContouringPipeline {
                     TextureFormat::Rgba32Float,
                     StorageTextureAccess::ReadWrite,
                 ),
+                // material_tex
+                binding_types::texture_3d(TextureSampleType::Uint).build(7, ShaderStages::COMPUTE),
             ],
         );
 
@@ -132,30 +321,50 @@ impl FromWorld for DualContouringPipeline {
                 (
                     binding_types::texture_3d(TextureSampleType::Float { filterable: true }),
                     binding_types::sampler(SamplerBindingType::Filtering),
+                    // relaxed surface nets vertices
+                    storage_buffer::<Vec4>(false),
                 ),
             ),
         );
 
+        let uv_bind_group_layout = render_device.create_bind_group_layout(
+            "uv group layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::COMPUTE,
+                binding_types::uniform_buffer::<GpuUvSettings>(false),
+            ),
+        );
+
         const CONTOUR_SHADER_PATH: &str = "shaders/contour.wgsl";
         const SDF_SHADER_PATH: &str = "shaders/sdf.wgsl";
         const ADAPTIVITY_SHADER_PATH: &str = "shaders/adaptivity.wgsl";
+        const SURFACE_NETS_SHADER_PATH: &str = "shaders/surface_nets.wgsl";
+        const UV_SHADER_PATH: &str = "shaders/uv.wgsl";
 
         let contour_shader = world.load_asset(CONTOUR_SHADER_PATH);
         let sdf_shader = world.load_asset(SDF_SHADER_PATH);
         let adaptivity_shader = world.load_asset(ADAPTIVITY_SHADER_PATH);
+        let surface_nets_shader = world.load_asset(SURFACE_NETS_SHADER_PATH);
+        let uv_shader = world.load_asset(UV_SHADER_PATH);
 
         let pipeline_cache = world.resource::<PipelineCache>();
+        let make_variant = |shader: &Handle<_>,
+                            layouts: &[&BindGroupLayout],
+                            entrypoint: &'static str,
+                            shader_defs: Vec<ShaderDefVal>| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: None,
+                layout: layouts.iter().map(|&x| x).cloned().collect(),
+                push_constant_ranges: Vec::new(),
+                shader: shader.clone(),
+                shader_defs,
+                entry_point: Cow::from(entrypoint),
+                zero_initialize_workgroup_memory: true,
+            })
+        };
         let make_pipeline =
             |shader: &Handle<_>, layouts: &[&BindGroupLayout], entrypoint: &'static str| {
-                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-                    label: None,
-                    layout: layouts.iter().map(|&x| x).cloned().collect(),
-                    push_constant_ranges: Vec::new(),
-                    shader: shader.clone(),
-                    shader_defs: vec![],
-                    entry_point: Cow::from(entrypoint),
-                    zero_initialize_workgroup_memory: true,
-                })
+                make_variant(shader, layouts, entrypoint, vec![])
             };
 
         let sdf_pipeline = make_pipeline(
@@ -163,70 +372,217 @@ impl FromWorld for DualContouringPipeline {
             &[&bind_group_layout, &sdf_bind_group_layout],
             "compute_sdf",
         );
+        let gradient_pipeline = make_pipeline(
+            &sdf_shader,
+            &[&bind_group_layout, &sdf_bind_group_layout],
+            "compute_gradient",
+        );
 
         let vertex_pipeline =
             make_pipeline(&contour_shader, &[&bind_group_layout], "compute_vertices");
         let edge_pipeline = make_pipeline(&contour_shader, &[&bind_group_layout], "compute_edges");
         let cleanup_pipeline = make_pipeline(&contour_shader, &[&bind_group_layout], "cleanup");
+        let vertex_dispatch_pipeline =
+            make_pipeline(&contour_shader, &[&bind_group_layout], "vertex_dispatch");
+
+        let make_adaptivity_pipeline = |shader_defs: Vec<ShaderDefVal>| {
+            make_variant(
+                &adaptivity_shader,
+                &[&bind_group_layout, &adaptivity_bind_group_layout],
+                "compute_adaptivity",
+                shader_defs,
+            )
+        };
 
-        let adaptivity_pipeline =
-            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-                label: None,
-                layout: vec![
-                    bind_group_layout.clone(),
-                    adaptivity_bind_group_layout.clone(),
-                ],
-                push_constant_ranges: Vec::new(),
-                shader: adaptivity_shader.clone(),
-                shader_defs: vec![ShaderDefVal::UInt("GRID_SIZE".to_string(), SIZE_GRID)],
-                entry_point: Cow::from("compute_adaptivity"),
-                zero_initialize_workgroup_memory: true,
-            });
+        let surface_nets_pipeline = make_adaptivity_pipeline(vec!["SURFACE_NETS".into()]);
+        let (adaptivity_pipeline, manifold_adaptivity_pipeline) = queue_qef_pipelines(
+            pipeline_cache,
+            &adaptivity_shader,
+            &[&bind_group_layout, &adaptivity_bind_group_layout],
+            &qef_settings,
+        );
+
+        let manifold_vertex_pipeline = make_variant(
+            &contour_shader,
+            &[&bind_group_layout],
+            "compute_vertices",
+            vec!["MANIFOLD".into()],
+        );
+        let manifold_edge_pipeline = make_variant(
+            &contour_shader,
+            &[&bind_group_layout],
+            "compute_edges",
+            vec!["MANIFOLD".into()],
+        );
+
+        let relax_pipeline = make_pipeline(
+            &surface_nets_shader,
+            &[&bind_group_layout, &adaptivity_bind_group_layout],
+            "relax_vertices",
+        );
+        let apply_relaxation_pipeline = make_pipeline(
+            &surface_nets_shader,
+            &[&bind_group_layout, &adaptivity_bind_group_layout],
+            "apply_relaxation",
+        );
+        let uv_pipeline = make_pipeline(
+            &uv_shader,
+            &[&bind_group_layout, &uv_bind_group_layout],
+            "compute_uvs",
+        );
 
         DualContouringPipeline {
             bind_group_layout,
             sdf_pipeline,
+            gradient_pipeline,
             vertex_pipeline,
             edge_pipeline,
             adaptivity_pipeline,
+            surface_nets_pipeline,
+            relax_pipeline,
+            apply_relaxation_pipeline,
+            manifold_vertex_pipeline,
+            manifold_edge_pipeline,
+            manifold_adaptivity_pipeline,
             cleanup_pipeline,
+            vertex_dispatch_pipeline,
+            uv_pipeline,
             adaptivity_bind_group_layout,
             sdf_bind_group_layout,
+            uv_bind_group_layout,
+            adaptivity_shader,
+            qef_settings,
         }
     }
 }
 
+/// Queues the adaptivity pipelines solving QEFs, for
+/// [`Mesher::DualContouring`] and [`Mesher::ManifoldDualContouring`].
+fn queue_qef_pipelines(
+    pipeline_cache: &PipelineCache,
+    shader: &Handle<Shader>,
+    layouts: &[&BindGroupLayout],
+    settings: &QefSettings,
+) -> (CachedComputePipelineId, CachedComputePipelineId) {
+    let queue = |shader_defs: Vec<ShaderDefVal>| {
+        pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: layouts.iter().copied().cloned().collect(),
+            push_constant_ranges: Vec::new(),
+            shader: shader.clone(),
+            shader_defs,
+            entry_point: Cow::from("compute_adaptivity"),
+            zero_initialize_workgroup_memory: true,
+        })
+    };
+
+    (
+        queue(qef_shader_defs(settings)),
+        queue([qef_shader_defs(settings), vec!["MANIFOLD".into()]].concat()),
+    )
+}
+
+/// Queues the QEF pipelines again when the [`QefSettings`] they were
+/// specialized on change. [`ContouringNode`] holds off dispatching until
+/// they're compiled.
+fn specialize_qef_pipelines(
+    mut pipeline: ResMut<DualContouringPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    settings: Res<QefSettings>,
+) {
+    if pipeline.qef_settings == *settings {
+        return;
+    }
+
+    let pipeline = &mut *pipeline;
+    (
+        pipeline.adaptivity_pipeline,
+        pipeline.manifold_adaptivity_pipeline,
+    ) = queue_qef_pipelines(
+        &pipeline_cache,
+        &pipeline.adaptivity_shader,
+        &[
+            &pipeline.bind_group_layout,
+            &pipeline.adaptivity_bind_group_layout,
+        ],
+        &settings,
+    );
+    pipeline.qef_settings = *settings;
+}
+
+// naga_oil has no float shader defs, so the floats are passed as their bits
+// and bitcast back in the shader
+fn qef_shader_defs(settings: &QefSettings) -> Vec<ShaderDefVal> {
+    vec![
+        ShaderDefVal::UInt("QEF_SVD_THRESHOLD".into(), settings.svd_threshold.to_bits()),
+        ShaderDefVal::UInt(
+            "QEF_MASS_POINT_BIAS".into(),
+            settings.mass_point_bias.to_bits(),
+        ),
+        ShaderDefVal::Bool("QEF_CLAMP".into(), settings.clamp),
+    ]
+}
+
 fn create_bind_group(
     mut commands: Commands,
     pipeline: Res<DualContouringPipeline>,
     gpu_images: Res<RenderAssets<GpuImage>>,
-    mut contouring_data: ResMut<DualContouringResources>,
+    contouring_data: Res<DualContouringResources>,
+    existing: Option<Res<DualContouringBindGroup>>,
     buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
     render_device: Res<RenderDevice>,
 ) {
+    if existing.is_some_and(|existing| existing.resources == *contouring_data) {
+        return;
+    }
+
     let DualContouringResources {
+        size: _,
         input,
+        materials,
         normal,
         index_lookup,
         debug_tex,
         vertex_buffer,
         index_buffer,
+        vertex_capacity: _,
+        index_capacity: _,
         count_buffer,
         indirect_buffer,
+        relax_buffer,
         mesh_handle: _,
-    } = &mut *contouring_data;
+    } = &*contouring_data;
 
     let render_device = &*render_device;
 
-    let vertex_buffer = buffers.get(vertex_buffer).unwrap();
-    let index_buffer = buffers.get(index_buffer).unwrap();
-    let count_buffer = buffers.get(count_buffer).unwrap();
-    let indirect_buffer = buffers.get(indirect_buffer).unwrap();
-
-    let view_input = gpu_images.get(input).unwrap();
-    let view_normal = gpu_images.get(normal).unwrap();
-    let view_index = gpu_images.get(index_lookup).unwrap();
-    let view_debug = gpu_images.get(debug_tex).unwrap();
+    // freshly (re)allocated resources might not have been prepared yet, try
+    // again next frame if that's the case
+    let (
+        Some(vertex_buffer),
+        Some(index_buffer),
+        Some(count_buffer),
+        Some(indirect_buffer),
+        Some(relax_buffer),
+        Some(view_input),
+        Some(view_materials),
+        Some(view_normal),
+        Some(view_index),
+        Some(view_debug),
+    ) = (
+        buffers.get(vertex_buffer),
+        buffers.get(index_buffer),
+        buffers.get(count_buffer),
+        buffers.get(indirect_buffer),
+        buffers.get(relax_buffer),
+        gpu_images.get(input),
+        gpu_images.get(materials),
+        gpu_images.get(normal),
+        gpu_images.get(index_lookup),
+        gpu_images.get(debug_tex),
+    )
+    else {
+        return;
+    };
 
     let group = render_device.create_bind_group(
         None,
@@ -234,11 +590,12 @@ fn create_bind_group(
         &BindGroupEntries::sequential((
             &view_input.texture_view,
             &view_index.texture_view,
-            vertex_buffer.buffer.as_entire_buffer_binding(),
-            index_buffer.buffer.as_entire_buffer_binding(),
-            count_buffer.buffer.as_entire_buffer_binding(),
+            untagged(&vertex_buffer.buffer),
+            untagged(&index_buffer.buffer),
+            untagged(&count_buffer.buffer),
             indirect_buffer.buffer.as_entire_buffer_binding(),
             &view_debug.texture_view,
+            &view_materials.texture_view,
         )),
     );
 
@@ -250,48 +607,324 @@ fn create_bind_group(
     let adaptivity_group = render_device.create_bind_group(
         None,
         &pipeline.adaptivity_bind_group_layout,
-        &BindGroupEntries::sequential((&view_normal.texture_view, &view_normal.sampler)),
+        &BindGroupEntries::sequential((
+            &view_normal.texture_view,
+            &view_normal.sampler,
+            relax_buffer.buffer.as_entire_buffer_binding(),
+        )),
     );
 
     commands.insert_resource(DualContouringBindGroup {
+        resources: contouring_data.clone(),
         group,
         adaptivity_group,
         sdf_group,
     });
 }
 
+#[derive(Resource)]
+struct UvBindGroup {
+    settings: UniformBuffer<GpuUvSettings>,
+    group: BindGroup,
+}
+
+/// Creates the uv pass's bind group once, later changes to the
+/// [`UvSettings`] are written into the same buffer.
+fn prepare_uv_bind_group(
+    mut commands: Commands,
+    pipeline: Res<DualContouringPipeline>,
+    uv: Res<UvSettings>,
+    existing: Option<ResMut<UvBindGroup>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let uv = GpuUvSettings::from(&*uv);
+
+    if let Some(mut existing) = existing {
+        if *existing.settings.get() != uv {
+            existing.settings.set(uv);
+            existing
+                .settings
+                .write_buffer(&render_device, &render_queue);
+        }
+        return;
+    }
+
+    let mut settings = UniformBuffer::from(uv);
+    settings.set_label(Some("contour uv settings"));
+    settings.write_buffer(&render_device, &render_queue);
+
+    let group = render_device.create_bind_group(
+        "contour uv",
+        &pipeline.uv_bind_group_layout,
+        &BindGroupEntries::single(settings.binding().unwrap()),
+    );
+
+    commands.insert_resource(UvBindGroup { settings, group });
+}
+
+fn update_sdf_scene(
+    scene: Res<SdfScene>,
+    settings: Res<ContouringSettings>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut map: ResMut<DensityMap>,
+) {
+    if !scene.is_changed() {
+        return;
+    }
+
+    log::debug!("Regenerating {SDF_SCENE_IMPORT_PATH}");
+
+    // replacing the shader makes the pipeline cache recompile everything
+    // importing it
+    shaders.insert(
+        SDF_SCENE_SHADER_HANDLE.id(),
+        Shader::from_wgsl(scene.to_wgsl(), "sdf_scene.wgsl"),
+    );
+
+    if settings.source == DensitySource::Cpu {
+        scene.fill(&mut map);
+    }
+}
+
+fn queue_density_upload(mut map: ResMut<DensityMap>, mut uploads: ResMut<DensityUploads>) {
+    if !map.is_changed() {
+        return;
+    }
+
+    let map = map.bypass_change_detection();
+    let Some((min, max)) = map.take_dirty() else {
+        return;
+    };
+
+    let extent = max - min + 1;
+    let samples = (extent.x * extent.y * extent.z) as usize;
+    let mut data = Vec::with_capacity(samples);
+    let mut materials = Vec::with_capacity(samples);
+
+    // x-major, to match the texture layout
+    for z in min.z..=max.z {
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = UVec3 { x, y, z };
+                data.push(map[pos]);
+                materials.push(map.material(pos));
+            }
+        }
+    }
+
+    uploads.0.push(DensityUpload {
+        size: map.size(),
+        origin: min,
+        extent,
+        data,
+        materials,
+    });
+}
+
+fn clear_density_uploads(mut uploads: ResMut<DensityUploads>) {
+    if !uploads.0.is_empty() {
+        uploads.0.clear();
+    }
+}
+
+fn write_density_uploads(
+    uploads: Res<DensityUploads>,
+    mut pending: ResMut<PendingDensityUploads>,
+    resources: Res<DualContouringResources>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_queue: Res<RenderQueue>,
+) {
+    if uploads.is_changed() {
+        pending.0.extend(uploads.0.iter().cloned());
+    }
+
+    pending.0.retain(|upload| upload.size == resources.size);
+
+    let (Some(input), Some(material_tex)) = (
+        gpu_images.get(&resources.input),
+        gpu_images.get(&resources.materials),
+    ) else {
+        return;
+    };
+
+    for upload in pending.0.drain(..) {
+        let DensityUpload {
+            size: _,
+            origin,
+            extent,
+            data,
+            materials,
+        } = upload;
+
+        let write = |texture: &GpuImage, bytes: &[u8], sample_size: usize| {
+            render_queue.write_texture(
+                ImageCopyTexture {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: origin.x,
+                        y: origin.y,
+                        z: origin.z,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                bytes,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(extent.x * sample_size as u32),
+                    rows_per_image: Some(extent.y),
+                },
+                Extent3d {
+                    width: extent.x,
+                    height: extent.y,
+                    depth_or_array_layers: extent.z,
+                },
+            );
+        };
+
+        write(input, bytemuck::cast_slice(&data), mem::size_of::<f32>());
+        write(material_tex, &materials, mem::size_of::<u8>());
+    }
+}
+
 #[derive(Component, Default)]
 struct ContouringMarker;
 
+/// The buffers read back after one contouring dispatch. The readbacks
+/// complete independently, so they're matched up by the generation
+/// [`tagged`] buffers end with and the mesh is only updated from a complete
+/// set.
 #[derive(Component, Default)]
-struct BufferData {
+struct ContouringResult {
+    generation: u32,
+    /// The last generation uploaded into the mesh, or dropped because its
+    /// output overflowed.
+    applied: u32,
+    /// The last generation whose counts were checked for an overflow.
+    checked: u32,
     vtx: Option<Vec<VertexInfo>>,
     idx: Option<Vec<u32>>,
     counts: Option<MeshCounts>,
 }
 
+impl ContouringResult {
+    /// Makes room for a readback of `generation`, dropping whatever was
+    /// gathered for an older one. False if the readback is stale and
+    /// should be ignored.
+    fn gather(&mut self, generation: u32) -> bool {
+        // 0 is the generation of buffers that were never dispatched
+        if generation == 0 || generation <= self.applied || generation < self.generation {
+            return false;
+        }
+
+        if generation > self.generation {
+            *self = Self {
+                generation,
+                applied: self.applied,
+                checked: self.checked,
+                ..default()
+            };
+        }
+
+        true
+    }
+
+    fn take_complete(&mut self) -> Option<(Vec<VertexInfo>, Vec<u32>, MeshCounts)> {
+        if self.vtx.is_none() || self.idx.is_none() || self.counts.is_none() {
+            return None;
+        }
+
+        self.applied = self.generation;
+        Some((self.vtx.take()?, self.idx.take()?, self.counts.take()?))
+    }
+}
+
+/// Size of the generation [`tagged`] buffers end with.
+const GENERATION_SIZE: u64 = mem::size_of::<u32>() as u64;
+
+/// A buffer holding `data` followed by the generation of the dispatch that
+/// last wrote it, see [`ContouringNode::run`]. The shaders never see the
+/// generation, see [`untagged`].
+fn tagged(data: impl Into<ShaderStorageBuffer>) -> ShaderStorageBuffer {
+    let mut buffer = data.into();
+    if let Some(bytes) = &mut buffer.data {
+        bytes.extend_from_slice(&[0; GENERATION_SIZE as usize]);
+    }
+
+    buffer
+}
+
+/// Binds a [`tagged`] buffer without its generation, so the shaders can
+/// neither read nor overwrite it.
+fn untagged(buffer: &Buffer) -> BufferBinding<'_> {
+    BufferBinding {
+        buffer,
+        offset: 0,
+        size: NonZeroU64::new(buffer.size() - GENERATION_SIZE),
+    }
+}
+
+/// Splits a readback of a [`tagged`] buffer into its generation and data.
+fn read_tagged<T: ShaderType + ReadFrom + Default>(readback: &ReadbackComplete) -> (u32, T) {
+    let (data, generation) = readback.split_at(readback.len() - GENERATION_SIZE as usize);
+
+    let mut value = T::default();
+    encase::StorageBuffer::new(data)
+        .read(&mut value)
+        .expect("readback doesn't match its buffer's type");
+
+    (bytemuck::pod_read_unaligned(generation), value)
+}
+
 #[repr(C)]
 #[derive(ShaderType, Copy, Clone, Default, Debug)]
 struct VertexInfo {
     pos: [f32; 3],
     uv: [f32; 2],
     normal: [f32; 3],
+    tangent: [f32; 4],
+    qef_error: f32,
+    component: u32,
+    /// Packed like [`ATTRIBUTE_MATERIAL`].
+    material: u32,
+}
+
+/// `UvSettings` in `uv.wgsl`.
+#[derive(ShaderType, Clone, Copy, Debug, PartialEq)]
+struct GpuUvSettings {
+    center: Vec3,
+    mode: u32,
+    scale: f32,
 }
 
+impl From<&UvSettings> for GpuUvSettings {
+    fn from(settings: &UvSettings) -> Self {
+        Self {
+            center: settings.center,
+            mode: settings.mode as u32,
+            scale: settings.scale,
+        }
+    }
+}
+
+/// Holds the read back mesh. It's hidden, as [`DrawContouring`] draws the
+/// same mesh from the GPU buffers with its material.
 #[derive(Bundle)]
 struct ContouringMesh {
     mesh: Mesh3d,
-    material: MeshMaterial3d<StandardMaterial>,
-    buffer_data: BufferData,
+    material: MeshMaterial3d<SplatMaterial>,
+    visibility: Visibility,
+    result: ContouringResult,
     marker: ContouringMarker,
 }
 
 macro_rules! make_buffers {
-    ($buffers:expr, $([$name:ident, $val:expr, $usage:ident]),*) => {
+    ($buffers:expr, $([$name:ident, $val:expr, $($usage:ident)|+]),*) => {
         $(
             let $name = {
                 let mut buffer = ShaderStorageBuffer::from($val);
-                buffer.buffer_description.usage |= BufferUsages::$usage;
+                buffer.buffer_description.usage |= $(BufferUsages::$usage)|+;
                 buffer.buffer_description.label = Some(concat!("contour ", stringify!($name)));
                 $buffers.add(buffer)
             };
@@ -299,19 +932,170 @@ macro_rules! make_buffers {
     };
 }
 
+/// Marks the entities reading back the contouring buffers, so they can be
+/// replaced when the buffers are reallocated or removed when
+/// [`ContouringSettings::readback`] is unset.
+#[derive(Component)]
+struct ContouringReadback;
+
 fn setup(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<SplatMaterial>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    (map, layers): (Res<DensityMap>, Res<SplatLayers>),
+) {
+    shaders.insert(
+        EDGE_COMPONENTS_SHADER_HANDLE.id(),
+        Shader::from_wgsl(edge_components_wgsl(), "edge_components.wgsl"),
+    );
+
+    let mesh = Cuboid::new(1.0, 1.0, 1.0).mesh();
+    let mesh_handle = meshes.add(mesh);
+
+    commands.spawn(ContouringMesh {
+        mesh: Mesh3d(mesh_handle.clone()),
+        material: MeshMaterial3d(materials.add(layers.material(StandardMaterial::default()))),
+        visibility: Visibility::Hidden,
+        result: ContouringResult::default(),
+        marker: ContouringMarker,
+    });
+
+    let resources = create_resources(&mut images, &mut buffers, map.size(), mesh_handle);
+    commands.insert_resource(resources);
+}
+
+/// Reallocates the textures and buffers when the density map changes size.
+fn reallocate_resources(
+    mut images: ResMut<Assets<Image>>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut resources: ResMut<DualContouringResources>,
+    map: Res<DensityMap>,
+) {
+    if map.size() == resources.size {
+        return;
+    }
+
+    log::info!(
+        "Reallocating contouring resources from {} to {} cells",
+        resources.size,
+        map.size()
+    );
+
+    let mesh_handle = resources.mesh_handle.clone();
+    *resources = create_resources(&mut images, &mut buffers, map.size(), mesh_handle);
+}
+
+/// Bumps the [`RemeshGeneration`] when anything the contouring passes read
+/// has changed, or a [`RequestRemesh`] was sent.
+fn mark_remesh(
+    mut generation: ResMut<RemeshGeneration>,
+    mut requests: EventReader<RequestRemesh>,
+    uploads: Res<DensityUploads>,
+    scene: Res<SdfScene>,
+    resources: Res<DualContouringResources>,
+    (settings, qef, surface_nets, uv): (
+        Res<ContouringSettings>,
+        Res<QefSettings>,
+        Res<SurfaceNetsSettings>,
+        Res<UvSettings>,
+    ),
+) {
+    let requested = requests.read().count() > 0;
+
+    if requested
+        || !uploads.0.is_empty()
+        || scene.is_changed()
+        || resources.is_changed()
+        || settings.is_changed()
+        || qef.is_changed()
+        || surface_nets.is_changed()
+        || uv.is_changed()
+    {
+        generation.0 += 1;
+    }
+}
+
+/// Spawns the entities reading back the contouring buffers until the
+/// latest generation has been checked for an overflow and, while
+/// [`ContouringSettings::readback`] is set, made it into the mesh. They're
+/// replaced whenever the buffers are reallocated.
+fn sync_readbacks(
+    mut commands: Commands,
+    settings: Res<ContouringSettings>,
+    resources: Res<DualContouringResources>,
+    (generation, failed): (Res<RemeshGeneration>, Res<FailedGeneration>),
+    results: Query<&ContouringResult>,
+    readbacks: Query<Entity, With<ContouringReadback>>,
+    mut reading_back_mesh: Local<bool>,
 ) {
+    // nothing will be written for a generation that failed
+    let failed = failed.0.load(Ordering::Relaxed) == generation.0;
+    let wanted = !failed
+        && results.iter().any(|result| {
+            result.checked != generation.0 || (settings.readback && result.applied != generation.0)
+        });
+    // the existing readbacks point at the buffers that were replaced, or
+    // are missing the mesh
+    let stale = resources.is_changed() || settings.readback != *reading_back_mesh;
+
+    if !wanted || stale {
+        for readback in &readbacks {
+            commands.entity(readback).despawn();
+        }
+    }
+
+    if !wanted || !(readbacks.is_empty() || stale) {
+        return;
+    }
+
+    *reading_back_mesh = settings.readback;
+
+    // the counts are always needed, to catch overflows
+    commands
+        .spawn((
+            Readback::buffer(resources.count_buffer.clone()),
+            ContouringReadback,
+        ))
+        .observe(update_counts);
+
+    if !settings.readback {
+        return;
+    }
+
+    commands
+        .spawn((
+            Readback::buffer(resources.vertex_buffer.clone()),
+            ContouringReadback,
+        ))
+        .observe(update_vtx);
+    commands
+        .spawn((
+            Readback::buffer(resources.index_buffer.clone()),
+            ContouringReadback,
+        ))
+        .observe(update_idx);
+}
+
+fn create_resources(
+    images: &mut Assets<Image>,
+    buffers: &mut Assets<ShaderStorageBuffer>,
+    size: UVec3,
+    mesh_handle: Handle<Mesh>,
+) -> DualContouringResources {
+    let size_grid = size + 1;
+    let size_cells = size;
+
+    let extent = |size: UVec3| Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: size.z,
+    };
+
     let mut input_tex = Image::new_fill(
-        Extent3d {
-            width: SIZE_GRID,
-            height: SIZE_GRID,
-            depth_or_array_layers: SIZE_GRID,
-        },
+        extent(size_grid),
         TextureDimension::D3,
         &[0, 0, 0, 0],
         TextureFormat::R32Float,
@@ -322,12 +1106,19 @@ fn setup(
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
     input_tex.texture_descriptor.label = Some("contour 3d sdf input");
 
+    let mut material_tex = Image::new_fill(
+        extent(size_grid),
+        TextureDimension::D3,
+        &[0],
+        TextureFormat::R8Uint,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    material_tex.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+    material_tex.texture_descriptor.label = Some("contour 3d materials");
+
     let mut normal_tex = Image::new_fill(
-        Extent3d {
-            width: SIZE_GRID,
-            height: SIZE_GRID,
-            depth_or_array_layers: SIZE_GRID,
-        },
+        extent(size_grid),
         TextureDimension::D3,
         &[0, 0, 0, 0],
         TextureFormat::Rgba8Snorm,
@@ -336,13 +1127,11 @@ fn setup(
     normal_tex.texture_descriptor.usage =
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
     normal_tex.texture_descriptor.label = Some("contour 3d sdf normals");
+    // the vertex normals are interpolated between the grid points
+    normal_tex.sampler = ImageSampler::linear();
 
     let mut index_tex = Image::new_fill(
-        Extent3d {
-            width: SIZE_CELLS,
-            height: SIZE_CELLS,
-            depth_or_array_layers: SIZE_CELLS,
-        },
+        extent(size_cells),
         TextureDimension::D3,
         &[0, 0, 0, 0],
         TextureFormat::R32Uint,
@@ -353,11 +1142,7 @@ fn setup(
     index_tex.texture_descriptor.label = Some("contour 3d index lookup");
 
     let mut debug_tex = Image::new_fill(
-        Extent3d {
-            width: SIZE_GRID,
-            height: SIZE_GRID,
-            depth_or_array_layers: SIZE_GRID,
-        },
+        extent(size_grid),
         TextureDimension::D3,
         &[0; 16],
         TextureFormat::Rgba32Float,
@@ -368,90 +1153,199 @@ fn setup(
 
     debug_tex.texture_descriptor.label = Some("contour 3d debug_tex");
 
-    use crate::N;
+    let (vertex_capacity, index_capacity) = initial_capacity(size_cells);
+    let (vertex_buffer, index_buffer, relax_buffer) =
+        create_output_buffers(buffers, vertex_capacity, index_capacity);
 
     make_buffers!(
         buffers,
         [
-            vertex_buffer,
-            vec![VertexInfo::default(); N * N * N],
-            COPY_SRC
+            count_buffer,
+            tagged(MeshCounts {
+                instance_count: 1,
+                ..default()
+            }),
+            COPY_SRC | COPY_DST | INDIRECT
         ],
-        [index_buffer, vec![0u32; N * N * N * 3], COPY_SRC],
-        [count_buffer, MeshCounts { vtx: 0, idx: 0 }, COPY_SRC],
         [
             indirect_buffer,
-            DispatchIndirectArgs { x: 1, y: 1, z: 1 },
+            DispatchIndirectArgs {
+                x: 0,
+                y: 0,
+                z: 1,
+                vertices: 0,
+            },
             INDIRECT
         ]
     );
 
-    commands
-        .spawn(Readback::buffer(vertex_buffer.clone()))
-        .observe(update_vtx);
-    commands
-        .spawn(Readback::buffer(index_buffer.clone()))
-        .observe(update_idx);
-    commands
-        .spawn(Readback::buffer(count_buffer.clone()))
-        .observe(update_counts);
-
-    let mesh = Cuboid::new(1.0, 1.0, 1.0).mesh();
-    let mesh_handle = meshes.add(mesh);
-
-    commands.spawn(ContouringMesh {
-        mesh: Mesh3d(mesh_handle.clone()),
-        material: MeshMaterial3d(materials.add(StandardMaterial::from_color(WHITE))),
-        buffer_data: BufferData::default(),
-        marker: ContouringMarker,
-    });
-
-    commands.insert_resource(DualContouringResources {
+    DualContouringResources {
+        size,
         input: images.add(input_tex),
+        materials: images.add(material_tex),
         normal: images.add(normal_tex),
         index_lookup: images.add(index_tex),
         debug_tex: images.add(debug_tex),
         vertex_buffer,
         index_buffer,
+        vertex_capacity,
+        index_capacity,
         count_buffer,
         indirect_buffer,
+        relax_buffer,
         mesh_handle,
-    });
+    }
+}
+
+/// Room for a surface crossing a map of `size` cells a few times, as
+/// vertices and indices. Far less than a vertex per cell, which big maps
+/// couldn't allocate, dense surfaces grow the buffers instead.
+fn initial_capacity(size: UVec3) -> (u32, u32) {
+    let cells = size.x * size.y * size.z;
+    let faces = size.x * size.y + size.y * size.z + size.z * size.x;
+
+    // a vertex is shared by four quads of four vertices each, so there's
+    // about a quad of six indices per vertex
+    let vertices = (2 * faces).min(cells).max(1);
+    (vertices, vertices * 6)
+}
+
+/// The buffers sized by the number of vertices or indices.
+fn create_output_buffers(
+    buffers: &mut Assets<ShaderStorageBuffer>,
+    vertex_capacity: u32,
+    index_capacity: u32,
+) -> (
+    Handle<ShaderStorageBuffer>,
+    Handle<ShaderStorageBuffer>,
+    Handle<ShaderStorageBuffer>,
+) {
+    make_buffers!(
+        buffers,
+        [
+            vertex_buffer,
+            tagged(vec![VertexInfo::default(); vertex_capacity as usize]),
+            COPY_SRC | COPY_DST
+        ],
+        [
+            index_buffer,
+            tagged(vec![0u32; index_capacity as usize]),
+            COPY_SRC | COPY_DST | INDEX
+        ],
+        [
+            relax_buffer,
+            vec![Vec4::ZERO; vertex_capacity as usize],
+            STORAGE
+        ]
+    );
+
+    (vertex_buffer, index_buffer, relax_buffer)
+}
+
+/// Triggered when the counts read back after a dispatch say its output
+/// didn't fit into the buffers.
+#[derive(Event)]
+struct ContouringOverflow(MeshCounts);
+
+/// Replaces the buffers that overflowed with ones big enough for everything
+/// the dispatch asked for. Changing the resources contours them again.
+fn grow_buffers(
+    trigger: Trigger<ContouringOverflow>,
+    mut resources: ResMut<DualContouringResources>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    let counts = trigger.event().0;
+
+    // indices of the quads whose vertices didn't fit weren't asked for, so
+    // growing can take a few rounds
+    let grow = |capacity: u32, needed: u32, flag: u32| {
+        if counts.overflow & flag != 0 && needed > capacity {
+            // some headroom, so a growing surface doesn't overflow every time
+            needed + needed / 2
+        } else {
+            capacity
+        }
+    };
+
+    let vertex_capacity = grow(resources.vertex_capacity, counts.vtx, OVERFLOW_VERTICES);
+    let index_capacity = grow(
+        resources.index_capacity,
+        counts.idx_reserved,
+        OVERFLOW_INDICES,
+    );
+
+    // a late readback of a dispatch into the old buffers
+    if (vertex_capacity, index_capacity) == (resources.vertex_capacity, resources.index_capacity) {
+        return;
+    }
+
+    log::warn!(
+        "Contouring output overflowed, growing the buffers from {} to {} vertices and from {} to {} indices",
+        resources.vertex_capacity,
+        vertex_capacity,
+        resources.index_capacity,
+        index_capacity,
+    );
+
+    let (vertex_buffer, index_buffer, relax_buffer) =
+        create_output_buffers(&mut buffers, vertex_capacity, index_capacity);
+
+    resources.vertex_buffer = vertex_buffer;
+    resources.index_buffer = index_buffer;
+    resources.relax_buffer = relax_buffer;
+    resources.vertex_capacity = vertex_capacity;
+    resources.index_capacity = index_capacity;
 }
 
 fn update_vtx(
     trigger: Trigger<ReadbackComplete>,
     mut commands: Commands,
-    mut query: Query<&mut BufferData>,
+    mut query: Query<&mut ContouringResult>,
 ) {
-    let data: Vec<VertexInfo> = trigger.event().to_shader_type();
-    for mut buffer in &mut query {
-        buffer.vtx.get_or_insert(data.clone());
-        commands.trigger(AttemptMeshUpload);
+    let (generation, data): (_, Vec<VertexInfo>) = read_tagged(trigger.event());
+    for mut result in &mut query {
+        if result.gather(generation) {
+            result.vtx = Some(data.clone());
+            commands.trigger(AttemptMeshUpload);
+        }
     }
 }
 
 fn update_idx(
     trigger: Trigger<ReadbackComplete>,
     mut commands: Commands,
-    mut query: Query<&mut BufferData>,
+    mut query: Query<&mut ContouringResult>,
 ) {
-    let data: Vec<u32> = trigger.event().to_shader_type();
-    for mut buffer in &mut query {
-        buffer.idx.get_or_insert(data.clone());
-        commands.trigger(AttemptMeshUpload);
+    let (generation, data): (_, Vec<u32>) = read_tagged(trigger.event());
+    for mut result in &mut query {
+        if result.gather(generation) {
+            result.idx = Some(data.clone());
+            commands.trigger(AttemptMeshUpload);
+        }
     }
 }
 
 fn update_counts(
     trigger: Trigger<ReadbackComplete>,
     mut commands: Commands,
-    mut query: Query<&mut BufferData>,
+    mut query: Query<&mut ContouringResult>,
 ) {
-    let data: MeshCounts = trigger.event().to_shader_type();
-    for mut buffer in &mut query {
-        buffer.counts.get_or_insert(data);
-        commands.trigger(AttemptMeshUpload);
+    let (generation, data): (_, MeshCounts) = read_tagged(trigger.event());
+    for mut result in &mut query {
+        if !result.gather(generation) {
+            continue;
+        }
+
+        result.checked = generation;
+
+        if data.overflow != 0 {
+            // the mesh is missing whatever didn't fit, it's never uploaded
+            result.applied = generation;
+            commands.trigger(ContouringOverflow(data));
+        } else {
+            result.counts = Some(data);
+            commands.trigger(AttemptMeshUpload);
+        }
     }
 }
 
@@ -460,34 +1354,35 @@ struct AttemptMeshUpload;
 
 fn attempt_mesh_upload(
     _trigger: Trigger<AttemptMeshUpload>,
-    mut query: Query<(&Mesh3d, &mut BufferData)>,
+    mut query: Query<(&Mesh3d, &mut ContouringResult)>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    for (mesh, mut data) in &mut query {
-        let BufferData {
-            vtx: vtx @ Some(_),
-            idx: idx @ Some(_),
-            counts: counts @ Some(_),
-        } = &mut *data
-        else {
+    for (mesh, mut result) in &mut query {
+        let Some((mut vtx, mut idx, counts)) = result.take_complete() else {
             continue;
         };
 
-        let mut vtx = vtx.take().unwrap();
-        let mut idx = idx.take().unwrap();
-        let counts = counts.take().unwrap();
-
         vtx.resize(counts.vtx as usize, VertexInfo::default());
         idx.resize(counts.idx as usize, 0);
 
         let mut vtx_new = Vec::new();
+        let mut normals = Vec::new();
         let mut uv = Vec::new();
+        let mut tangents = Vec::new();
+        let mut qef_errors = Vec::new();
+        let mut materials = Vec::new();
+        let mut weights = Vec::new();
 
         for v in vtx {
             let [x, y, z] = v.pos;
 
             vtx_new.push(Vec3::new(x, y, z));
-            uv.push(Vec2::new(0.0, 0.0));
+            normals.push(Vec3::from(v.normal));
+            uv.push(Vec2::from(v.uv));
+            tangents.push(Vec4::from(v.tangent));
+            qef_errors.push(v.qef_error);
+            materials.push(v.material);
+            weights.push(splat_weights(v.material));
         }
 
         let Some(mesh) = meshes.get_mut(&mesh.0) else {
@@ -499,38 +1394,118 @@ fn attempt_mesh_upload(
         mesh.remove_attribute(Mesh::ATTRIBUTE_POSITION);
         mesh.remove_attribute(Mesh::ATTRIBUTE_UV_0);
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vtx_new);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uv);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+        mesh.insert_attribute(ATTRIBUTE_QEF_ERROR, qef_errors);
+        mesh.insert_attribute(ATTRIBUTE_MATERIAL, materials);
+        mesh.insert_attribute(ATTRIBUTE_SPLAT_WEIGHTS, weights);
         mesh.insert_indices(bevy::render::mesh::Indices::U32(idx));
-        mesh.compute_normals();
     }
 }
 
-pub struct DualContouringPlugin;
+#[derive(Default)]
+pub struct DualContouringPlugin {
+    pub source: DensitySource,
+    pub mesher: Mesher,
+    /// See [`ContouringSettings::readback`].
+    pub readback: bool,
+    pub qef: QefSettings,
+    pub surface_nets: SurfaceNetsSettings,
+    pub uv: UvSettings,
+}
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 struct ContouringLabel;
 
 impl Plugin for DualContouringPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ExtractResourcePlugin::<DualContouringResources>::default())
-            .add_systems(Startup, setup)
-            .add_observer(attempt_mesh_upload);
+        app.add_plugins((
+            ExtractResourcePlugin::<DualContouringResources>::default(),
+            ExtractResourcePlugin::<ContouringSettings>::default(),
+            ExtractResourcePlugin::<QefSettings>::default(),
+            ExtractResourcePlugin::<SurfaceNetsSettings>::default(),
+            ExtractResourcePlugin::<UvSettings>::default(),
+            ExtractResourcePlugin::<DensityUploads>::default(),
+            ExtractResourcePlugin::<RemeshGeneration>::default(),
+        ))
+        .insert_resource(ContouringSettings {
+            source: self.source,
+            mesher: self.mesher,
+            readback: self.readback,
+        })
+        .insert_resource(self.qef)
+        .insert_resource(self.surface_nets)
+        .insert_resource(self.uv)
+        .init_resource::<DensityUploads>()
+        .init_resource::<SdfScene>()
+        .init_resource::<RemeshGeneration>()
+        .init_resource::<FailedGeneration>()
+        .add_event::<RequestRemesh>()
+        .add_systems(Startup, setup)
+        .add_systems(PreUpdate, update_sdf_scene)
+        .add_systems(First, clear_density_uploads)
+        .add_systems(Update, reallocate_resources)
+        .add_systems(
+            PostUpdate,
+            (
+                queue_density_upload.run_if(|settings: Res<ContouringSettings>| {
+                    settings.source == DensitySource::Cpu
+                }),
+                mark_remesh,
+                sync_readbacks,
+            )
+                .chain(),
+        )
+        .add_observer(attempt_mesh_upload)
+        .add_observer(grow_buffers);
     }
 
     fn finish(&self, app: &mut App) {
+        // the pipelines are created with the settings the app starts with,
+        // before they're first extracted
+        let qef_settings = *app.world().resource::<QefSettings>();
+        let FailedGeneration(failed) = app.world().resource::<FailedGeneration>().clone();
+
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .insert_resource(qef_settings)
             .init_resource::<DualContouringPipeline>()
+            .init_resource::<ContouringDrawPipeline>()
+            .init_resource::<SpecializedMeshPipelines<ContouringDrawPipeline>>()
+            .add_render_command::<Opaque3d, DrawContouring>()
+            .init_resource::<PendingDensityUploads>()
+            .add_systems(ExtractSchedule, extract_contouring_draw)
+            .add_systems(Render, specialize_qef_pipelines.in_set(RenderSet::Prepare))
             .add_systems(
                 Render,
-                create_bind_group
+                prepare_uv_bind_group.in_set(RenderSet::PrepareBindGroups),
+            )
+            .add_systems(
+                Render,
+                (create_bind_group, prepare_draw_buffers)
                     .in_set(RenderSet::PrepareBindGroups)
-                    .run_if(not(resource_exists::<DualContouringBindGroup>)),
+                    .run_if(resource_exists::<DualContouringResources>),
+            )
+            .add_systems(Render, queue_contouring_draw.in_set(RenderSet::Queue))
+            .add_systems(
+                Render,
+                write_density_uploads
+                    .in_set(RenderSet::PrepareResources)
+                    .run_if(resource_exists::<DualContouringResources>)
+                    .run_if(resource_exists::<DensityUploads>),
             );
 
         let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
 
-        render_graph.add_node(ContouringLabel, ContouringNode(ContouringState::Loading));
+        render_graph.add_node(
+            ContouringLabel,
+            ContouringNode {
+                state: ContouringState::Loading,
+                dispatched: AtomicU32::new(0),
+                failed,
+            },
+        );
         render_graph.add_node_edge(ContouringLabel, bevy::render::graph::CameraDriverLabel);
     }
 }
@@ -541,7 +1516,14 @@ enum ContouringState {
     Done,
 }
 
-struct ContouringNode(ContouringState);
+struct ContouringNode {
+    state: ContouringState,
+    /// The [`RemeshGeneration`] last contoured, which is written after the
+    /// end of the [`tagged`] buffers.
+    dispatched: AtomicU32,
+    /// The [`FailedGeneration`] of the main world.
+    failed: Arc<AtomicU32>,
+}
 
 impl render_graph::Node for ContouringNode {
     fn update(&mut self, world: &mut World) {
@@ -551,33 +1533,54 @@ impl render_graph::Node for ContouringNode {
         use CachedPipelineState as CPS;
 
         // if the corresponding pipeline has loaded, transition to the next stage
-        match self.0 {
+        match self.state {
             ContouringState::Error => return,
             ContouringState::Loading => {
                 let DualContouringPipeline {
                     sdf_pipeline,
+                    gradient_pipeline,
                     vertex_pipeline,
                     edge_pipeline,
                     cleanup_pipeline,
+                    vertex_dispatch_pipeline,
                     adaptivity_pipeline,
+                    surface_nets_pipeline,
+                    relax_pipeline,
+                    apply_relaxation_pipeline,
+                    manifold_vertex_pipeline,
+                    manifold_edge_pipeline,
+                    manifold_adaptivity_pipeline,
+                    uv_pipeline,
                     adaptivity_bind_group_layout: _,
                     sdf_bind_group_layout: _,
+                    uv_bind_group_layout: _,
                     bind_group_layout: _,
+                    adaptivity_shader: _,
+                    qef_settings: _,
                 } = &*pipeline;
 
                 let mut done = true;
 
                 for pipeline in [
                     sdf_pipeline,
+                    gradient_pipeline,
                     vertex_pipeline,
                     edge_pipeline,
                     cleanup_pipeline,
+                    vertex_dispatch_pipeline,
                     adaptivity_pipeline,
+                    surface_nets_pipeline,
+                    relax_pipeline,
+                    apply_relaxation_pipeline,
+                    manifold_vertex_pipeline,
+                    manifold_edge_pipeline,
+                    manifold_adaptivity_pipeline,
+                    uv_pipeline,
                 ] {
                     match pipeline_cache.get_compute_pipeline_state(*pipeline) {
                         CPS::Ok(_) => {}
                         CPS::Err(PipelineCacheError::ProcessShaderError(err)) => {
-                            self.0 = ContouringState::Error;
+                            self.state = ContouringState::Error;
                             done = false;
                             log::error!("Error while initializing shader: {err}")
                         }
@@ -588,7 +1591,7 @@ impl render_graph::Node for ContouringNode {
                 }
 
                 if done {
-                    self.0 = ContouringState::Done;
+                    self.state = ContouringState::Done;
                 }
             }
             ContouringState::Done => {}
@@ -601,15 +1604,31 @@ impl render_graph::Node for ContouringNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        match self.0 {
+        match self.state {
             ContouringState::Loading | ContouringState::Error => return Ok(()),
             _ => {}
         }
 
-        let bind_group = &world.resource::<DualContouringBindGroup>();
+        let RemeshGeneration(generation) = *world.resource::<RemeshGeneration>();
+        if generation == self.dispatched.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let Some(bind_group) = world.get_resource::<DualContouringBindGroup>() else {
+            return Ok(());
+        };
+        let Some(uv_group) = world.get_resource::<UvBindGroup>() else {
+            return Ok(());
+        };
+        // the bind group might lag behind a reallocation by a frame, the new
+        // buffers are contoured once it catches up
+        let resources = &bind_group.resources;
+        if resources != world.resource::<DualContouringResources>() {
+            return Ok(());
+        }
+
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<DualContouringPipeline>();
-        let resources = world.resource::<DualContouringResources>();
         let buffers = world.resource::<RenderAssets<GpuShaderStorageBuffer>>();
 
         let encoder = render_context.command_encoder();
@@ -625,38 +1644,138 @@ impl render_graph::Node for ContouringNode {
             pass.dispatch_workgroups(x, y, z);
         };
 
+        let settings = world.resource::<ContouringSettings>();
+        let surface_nets = world.resource::<SurfaceNetsSettings>();
+
         let DualContouringPipeline {
             sdf_pipeline,
+            gradient_pipeline,
             vertex_pipeline,
             edge_pipeline,
             cleanup_pipeline,
+            vertex_dispatch_pipeline,
             bind_group_layout: _,
             adaptivity_pipeline,
+            surface_nets_pipeline,
+            relax_pipeline,
+            apply_relaxation_pipeline,
+            manifold_vertex_pipeline,
+            manifold_edge_pipeline,
+            manifold_adaptivity_pipeline,
+            uv_pipeline,
             adaptivity_bind_group_layout: _,
             sdf_bind_group_layout: _,
+            uv_bind_group_layout: _,
+            adaptivity_shader: _,
+            qef_settings: _,
         } = pipeline;
 
-        let per_grid_vertices = (SIZE_GRID, SIZE_GRID, SIZE_GRID);
-        let per_grid_cells = (SIZE_CELLS, SIZE_CELLS, SIZE_CELLS);
+        let pipelines = [
+            sdf_pipeline,
+            gradient_pipeline,
+            vertex_pipeline,
+            edge_pipeline,
+            cleanup_pipeline,
+            vertex_dispatch_pipeline,
+            adaptivity_pipeline,
+            surface_nets_pipeline,
+            relax_pipeline,
+            apply_relaxation_pipeline,
+            manifold_vertex_pipeline,
+            manifold_edge_pipeline,
+            manifold_adaptivity_pipeline,
+            uv_pipeline,
+        ];
+
+        // a respecialized pipeline that doesn't compile stays broken until
+        // the settings change again, which bumps the generation
+        let error =
+            pipelines
+                .iter()
+                .find_map(|&&id| match pipeline_cache.get_compute_pipeline_state(id) {
+                    CachedPipelineState::Err(err) => Some(err),
+                    _ => None,
+                });
+        if let Some(err) = error {
+            if self.failed.swap(generation, Ordering::Relaxed) != generation {
+                log::error!("Not contouring generation {generation}, a pipeline failed: {err}");
+            }
+            return Ok(());
+        }
+
+        // pipelines get recompiled whenever the sdf scene or the QEF
+        // settings change, skip dispatching until they're back
+        let ready = pipelines
+            .iter()
+            .all(|&&id| pipeline_cache.get_compute_pipeline(id).is_some());
+
+        if !ready {
+            return Ok(());
+        }
+        // the shader was fixed and reloaded
+        self.failed.store(0, Ordering::Relaxed);
+
+        let size_grid = resources.size + 1;
+        let size_cells = resources.size;
+
+        let per_grid_cells = workgroups(size_cells);
         let once = (1, 1, 1);
 
+        // written before this frame's commands run, so every readback of
+        // it carries the generation of the dispatch below
+        self.dispatched.store(generation, Ordering::Relaxed);
+        let render_queue = world.resource::<RenderQueue>();
+        for buffer in [
+            &resources.vertex_buffer,
+            &resources.index_buffer,
+            &resources.count_buffer,
+        ] {
+            let buffer = &buffers.get(buffer).unwrap().buffer;
+            render_queue.write_buffer(
+                buffer,
+                buffer.size() - GENERATION_SIZE,
+                bytemuck::bytes_of(&generation),
+            );
+        }
+
         encoder.push_debug_group("render mesh");
 
         run_pass(encoder, *cleanup_pipeline, once);
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
-            let pipeline = pipeline_cache.get_compute_pipeline(*sdf_pipeline).unwrap();
+            // uploaded densities are already in the input texture, only the
+            // gradient has to be computed
+            let density_pipeline = match settings.source {
+                DensitySource::Sdf => *sdf_pipeline,
+                DensitySource::Cpu => *gradient_pipeline,
+            };
+
+            let pipeline = pipeline_cache
+                .get_compute_pipeline(density_pipeline)
+                .unwrap();
             pass.set_bind_group(0, &bind_group.group, &[]);
             pass.set_bind_group(1, &bind_group.sdf_group, &[]);
             pass.set_pipeline(pipeline);
-            pass.dispatch_workgroups(SIZE_GRID, SIZE_GRID, SIZE_GRID);
+            let (x, y, z) = workgroups(size_grid);
+            pass.dispatch_workgroups(x, y, z);
         }
-        run_pass(encoder, *vertex_pipeline, per_grid_cells);
+        let (vertex_pipeline, adaptivity_pipeline, edge_pipeline) = match settings.mesher {
+            Mesher::DualContouring => (*vertex_pipeline, *adaptivity_pipeline, *edge_pipeline),
+            Mesher::SurfaceNets => (*vertex_pipeline, *surface_nets_pipeline, *edge_pipeline),
+            Mesher::ManifoldDualContouring => (
+                *manifold_vertex_pipeline,
+                *manifold_adaptivity_pipeline,
+                *manifold_edge_pipeline,
+            ),
+        };
+
+        run_pass(encoder, vertex_pipeline, per_grid_cells);
+        run_pass(encoder, *vertex_dispatch_pipeline, once);
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
             let adaptivity_pipeline = pipeline_cache
-                .get_compute_pipeline(*adaptivity_pipeline)
+                .get_compute_pipeline(adaptivity_pipeline)
                 .unwrap();
             pass.set_bind_group(0, &bind_group.group, &[]);
             pass.set_bind_group(1, &bind_group.adaptivity_group, &[]);
@@ -666,10 +1785,445 @@ impl render_graph::Node for ContouringNode {
                 0,
             );
         }
-        run_pass(encoder, *edge_pipeline, per_grid_cells);
+        if settings.mesher == Mesher::SurfaceNets {
+            let relax_pipeline = pipeline_cache
+                .get_compute_pipeline(*relax_pipeline)
+                .unwrap();
+            let apply_relaxation_pipeline = pipeline_cache
+                .get_compute_pipeline(*apply_relaxation_pipeline)
+                .unwrap();
+
+            // separate passes, so every vertex is relaxed from the previous
+            // iteration's neighbours
+            for _ in 0..surface_nets.relax_iterations {
+                for pipeline in [relax_pipeline, apply_relaxation_pipeline] {
+                    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+                    pass.set_bind_group(0, &bind_group.group, &[]);
+                    pass.set_bind_group(1, &bind_group.adaptivity_group, &[]);
+                    pass.set_pipeline(pipeline);
+                    let (x, y, z) = per_grid_cells;
+                    pass.dispatch_workgroups(x, y, z);
+                }
+            }
+        }
+        {
+            // after the relaxation, so the UVs are for the final positions
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            let uv_pipeline = pipeline_cache.get_compute_pipeline(*uv_pipeline).unwrap();
+            pass.set_bind_group(0, &bind_group.group, &[]);
+            pass.set_bind_group(1, &uv_group.group, &[]);
+            pass.set_pipeline(uv_pipeline);
+            pass.dispatch_workgroups_indirect(
+                &buffers.get(&resources.indirect_buffer).unwrap().buffer,
+                0,
+            );
+        }
+        run_pass(encoder, edge_pipeline, per_grid_cells);
 
         encoder.pop_debug_group();
 
         Ok(())
     }
 }
+
+/// Draws the contoured mesh from the GPU buffers with the index count
+/// `compute_edges` left in the count buffer, so nothing has to be read back
+/// to render it. Specialized like the [`SplatMaterial`] of the
+/// [`ContouringMesh`], so it's lit by the same lights and shadows as any
+/// other mesh, with the vertices pulled from their buffer in place of the
+/// mesh bindings.
+#[derive(Resource)]
+struct ContouringDrawPipeline {
+    material_pipeline: MaterialPipeline<SplatMaterial>,
+    vertices_layout: BindGroupLayout,
+    /// A single mesh at the origin, bound next to the vertices where the
+    /// fragment functions of `bevy_pbr` read the mesh flags.
+    mesh: StorageBuffer<Vec<MeshUniform>>,
+    /// The attributes the vertices have, for the shader defs the mesh
+    /// pipeline adds for them.
+    vertex_layout: MeshVertexBufferLayoutRef,
+    shader: Handle<Shader>,
+}
+
+impl FromWorld for ContouringDrawPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let vertices_layout = render_device.create_bind_group_layout(
+            "contour draw vertices layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    binding_types::storage_buffer_read_only::<MeshUniform>(false),
+                    binding_types::storage_buffer_read_only::<VertexInfo>(false),
+                ),
+            ),
+        );
+
+        let transforms = MeshTransforms {
+            world_from_local: Affine3::from(&Affine3A::IDENTITY),
+            previous_world_from_local: Affine3::from(&Affine3A::IDENTITY),
+            flags: MeshFlags::SHADOW_RECEIVER.bits(),
+        };
+        let mut mesh_buffer = StorageBuffer::from(vec![MeshUniform::new(&transforms, 0, None)]);
+        mesh_buffer.set_label(Some("contour draw mesh"));
+        mesh_buffer.write_buffer(render_device, world.resource::<RenderQueue>());
+
+        // the compute passes always write UVs and tangents
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![Vec3::ZERO]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![Vec3::ZERO]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![Vec2::ZERO]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, vec![Vec4::ZERO]);
+        mesh.insert_attribute(ATTRIBUTE_SPLAT_WEIGHTS, vec![Vec4::ZERO]);
+        let vertex_layout = mesh
+            .get_mesh_vertex_buffer_layout(&mut world.resource_mut::<MeshVertexBufferLayouts>());
+
+        const DRAW_SHADER_PATH: &str = "shaders/draw.wgsl";
+
+        ContouringDrawPipeline {
+            // added by the splat plugin, before this one
+            material_pipeline: world.resource::<MaterialPipeline<SplatMaterial>>().clone(),
+            vertices_layout,
+            mesh: mesh_buffer,
+            vertex_layout,
+            shader: world.load_asset(DRAW_SHADER_PATH),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for ContouringDrawPipeline {
+    type Key = MaterialPipelineKey<SplatMaterial>;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayoutRef,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.material_pipeline.specialize(key, layout)?;
+
+        descriptor.label = Some("contour draw pipeline".into());
+        descriptor.layout[1] = self.vertices_layout.clone();
+        // the vertices are pulled from the storage buffer by index
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers = Vec::new();
+        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+
+        Ok(descriptor)
+    }
+}
+
+/// The [`ContouringMesh`], which the draw stands in for in the render
+/// phases, and its material.
+#[derive(Resource)]
+struct ContouringDrawItem {
+    entity: (Entity, MainEntity),
+    material: AssetId<SplatMaterial>,
+}
+
+#[allow(clippy::type_complexity)]
+fn extract_contouring_draw(
+    mut commands: Commands,
+    meshes: Extract<
+        Query<(Entity, &RenderEntity, &MeshMaterial3d<SplatMaterial>), With<ContouringMarker>>,
+    >,
+) {
+    let Ok((main_entity, render_entity, material)) = meshes.get_single() else {
+        return;
+    };
+
+    commands.insert_resource(ContouringDrawItem {
+        entity: (render_entity.id(), main_entity.into()),
+        material: material.id(),
+    });
+}
+
+/// The buffers the compute passes write, as [`DrawContouring`] binds them.
+#[derive(Resource)]
+struct ContouringDrawBuffers {
+    /// The resources these were taken from, they're only taken again when
+    /// they change.
+    resources: DualContouringResources,
+    vertices: BindGroup,
+    index_buffer: Buffer,
+    count_buffer: Buffer,
+}
+
+fn prepare_draw_buffers(
+    mut commands: Commands,
+    resources: Res<DualContouringResources>,
+    existing: Option<Res<ContouringDrawBuffers>>,
+    draw_pipeline: Res<ContouringDrawPipeline>,
+    buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+) {
+    if existing.is_some_and(|existing| existing.resources == *resources) {
+        return;
+    }
+
+    let (Some(vertex_buffer), Some(index_buffer), Some(count_buffer)) = (
+        buffers.get(&resources.vertex_buffer),
+        buffers.get(&resources.index_buffer),
+        buffers.get(&resources.count_buffer),
+    ) else {
+        return;
+    };
+
+    let vertices = render_device.create_bind_group(
+        "contour draw vertices",
+        &draw_pipeline.vertices_layout,
+        &BindGroupEntries::sequential((
+            draw_pipeline.mesh.binding().unwrap(),
+            untagged(&vertex_buffer.buffer),
+        )),
+    );
+
+    commands.insert_resource(ContouringDrawBuffers {
+        resources: resources.clone(),
+        vertices,
+        index_buffer: index_buffer.buffer.clone(),
+        count_buffer: count_buffer.buffer.clone(),
+    });
+}
+
+/// Adds the contoured mesh to the opaque phase of every 3d view, with the
+/// pipeline key [`queue_material_meshes`] would give a mesh with its
+/// material.
+///
+/// [`queue_material_meshes`]: bevy::pbr::queue_material_meshes
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn queue_contouring_draw(
+    draw_functions: Res<DrawFunctions<Opaque3d>>,
+    draw_pipeline: Res<ContouringDrawPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ContouringDrawPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    (item, buffers): (
+        Option<Res<ContouringDrawItem>>,
+        Option<Res<ContouringDrawBuffers>>,
+    ),
+    materials: Res<RenderAssets<PreparedMaterial<SplatMaterial>>>,
+    mut opaque_phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &Msaa,
+        Option<&Tonemapping>,
+        Option<&DebandDither>,
+        Option<&ShadowFilteringMethod>,
+        Has<ScreenSpaceAmbientOcclusion>,
+        (
+            Has<NormalPrepass>,
+            Has<DepthPrepass>,
+            Has<MotionVectorPrepass>,
+            Has<DeferredPrepass>,
+        ),
+        Option<&Camera3d>,
+        Has<TemporalJitter>,
+        Option<&Projection>,
+        (
+            Has<RenderViewLightProbes<EnvironmentMapLight>>,
+            Has<RenderViewLightProbes<IrradianceVolume>>,
+        ),
+        Has<OrderIndependentTransparencySettings>,
+    )>,
+) {
+    let (Some(item), Some(_)) = (item, buffers) else {
+        return;
+    };
+    let Some(material) = materials.get(item.material) else {
+        return;
+    };
+    let draw_function = draw_functions.read().id::<DrawContouring>();
+
+    for (
+        view_entity,
+        view,
+        msaa,
+        tonemapping,
+        dither,
+        shadow_filter_method,
+        ssao,
+        (normal_prepass, depth_prepass, motion_vector_prepass, deferred_prepass),
+        camera_3d,
+        temporal_jitter,
+        projection,
+        (has_environment_maps, has_irradiance_volumes),
+        has_oit,
+    ) in &views
+    {
+        let Some(opaque_phase) = opaque_phases.get_mut(&view_entity) else {
+            continue;
+        };
+
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_hdr(view.hdr);
+
+        for (enabled, bit) in [
+            (normal_prepass, MeshPipelineKey::NORMAL_PREPASS),
+            (depth_prepass, MeshPipelineKey::DEPTH_PREPASS),
+            (
+                motion_vector_prepass,
+                MeshPipelineKey::MOTION_VECTOR_PREPASS,
+            ),
+            (deferred_prepass, MeshPipelineKey::DEFERRED_PREPASS),
+            (temporal_jitter, MeshPipelineKey::TEMPORAL_JITTER),
+            (has_environment_maps, MeshPipelineKey::ENVIRONMENT_MAP),
+            (has_irradiance_volumes, MeshPipelineKey::IRRADIANCE_VOLUME),
+            (has_oit, MeshPipelineKey::OIT_ENABLED),
+            (ssao, MeshPipelineKey::SCREEN_SPACE_AMBIENT_OCCLUSION),
+        ] {
+            if enabled {
+                view_key |= bit;
+            }
+        }
+
+        if let Some(projection) = projection {
+            view_key |= match projection {
+                Projection::Perspective(_) => MeshPipelineKey::VIEW_PROJECTION_PERSPECTIVE,
+                Projection::Orthographic(_) => MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC,
+            };
+        }
+
+        view_key |= match shadow_filter_method.unwrap_or(&ShadowFilteringMethod::default()) {
+            ShadowFilteringMethod::Hardware2x2 => {
+                MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2
+            }
+            ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+            ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+        };
+
+        if !view.hdr {
+            if let Some(tonemapping) = tonemapping {
+                view_key |= MeshPipelineKey::TONEMAP_IN_SHADER;
+                view_key |= tonemapping_pipeline_key(*tonemapping);
+            }
+            if let Some(DebandDither::Enabled) = dither {
+                view_key |= MeshPipelineKey::DEBAND_DITHER;
+            }
+        }
+        if let Some(camera_3d) = camera_3d {
+            view_key |= screen_space_specular_transmission_pipeline_key(
+                camera_3d.screen_space_specular_transmission_quality,
+            );
+        }
+
+        // always opaque, it's drawn in the opaque pass whatever the
+        // material's alpha mode
+        let mesh_key = view_key
+            | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList)
+            | material.properties.mesh_pipeline_key_bits
+            | MeshPipelineKey::BLEND_OPAQUE;
+
+        let pipeline = pipelines.specialize(
+            &pipeline_cache,
+            &draw_pipeline,
+            MaterialPipelineKey {
+                mesh_key,
+                bind_group_data: material.key,
+            },
+            &draw_pipeline.vertex_layout,
+        );
+        let pipeline = match pipeline {
+            Ok(id) => id,
+            Err(err) => {
+                error!("{err}");
+                continue;
+            }
+        };
+
+        opaque_phase.add(
+            Opaque3dBinKey {
+                pipeline,
+                draw_function,
+                asset_id: item.material.untyped(),
+                material_bind_group_id: material.get_bind_group_id().0,
+                lightmap_image: None,
+            },
+            item.entity,
+            BinnedRenderPhaseType::NonMesh,
+        );
+    }
+}
+
+type DrawContouring = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetContouringVerticesBindGroup<1>,
+    SetContouringMaterialBindGroup<2>,
+    DrawContouringIndirect,
+);
+
+struct SetContouringVerticesBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetContouringVerticesBindGroup<I> {
+    type Param = SRes<ContouringDrawBuffers>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: Option<()>,
+        buffers: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.set_bind_group(I, &buffers.into_inner().vertices, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// The bind group of the [`ContouringMesh`]'s material. Hidden meshes have
+/// no [`RenderMaterialInstances`](bevy::pbr::RenderMaterialInstances), so
+/// [`SetMaterialBindGroup`](bevy::pbr::SetMaterialBindGroup) can't find it.
+struct SetContouringMaterialBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetContouringMaterialBindGroup<I> {
+    type Param = (
+        SRes<ContouringDrawItem>,
+        SRes<RenderAssets<PreparedMaterial<SplatMaterial>>>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: Option<()>,
+        (item, materials): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(material) = materials.into_inner().get(item.material) else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_bind_group(I, &material.bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Draws as many indices as [`ContouringNode`] wrote, from the same
+/// buffers it just wrote them to.
+struct DrawContouringIndirect;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawContouringIndirect {
+    type Param = SRes<ContouringDrawBuffers>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: Option<()>,
+        buffers: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let buffers = buffers.into_inner();
+
+        pass.set_index_buffer(buffers.index_buffer.slice(..), 0, IndexFormat::Uint32);
+        pass.draw_indexed_indirect(&buffers.count_buffer, 0);
+        RenderCommandResult::Success
+    }
+}
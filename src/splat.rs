@@ -0,0 +1,282 @@
+//! A terrain material for contoured meshes, blending a texture array of
+//! surface materials by the per vertex [`ATTRIBUTE_SPLAT_WEIGHTS`].
+//! Contoured meshes have no usable UVs, so the layers are projected
+//! triplanarly in world space. See `splat.wgsl`.
+
+use std::f32::consts::TAU;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    image::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
+    prelude::*,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef},
+        render_resource::{
+            AsBindGroup, Extent3d, RenderPipelineDescriptor, ShaderRef, ShaderType,
+            SpecializedMeshPipelineError, TextureDimension, TextureFormat, VertexFormat,
+        },
+    },
+};
+
+use crate::mesh::unpack_material;
+
+/// How many materials a [`SplatMaterial`] blends between, the layers of
+/// [`SplatExtension::layers`]. Material IDs past the last layer use it.
+pub const SPLAT_LAYERS: usize = 4;
+
+/// How much of each of the [`SPLAT_LAYERS`] a vertex is made of, derived
+/// from its [`crate::mesh::ATTRIBUTE_MATERIAL`] by [`splat_weights`].
+pub const ATTRIBUTE_SPLAT_WEIGHTS: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_SplatWeights", 0x5b1a_73c2, VertexFormat::Float32x4);
+
+/// The layer weights of a vertex with a packed material, summing to 1.
+pub fn splat_weights(packed: u32) -> Vec4 {
+    let (a, b, blend) = unpack_material(packed);
+    let layer = |material: u8| Vec4::AXES[(material as usize).min(SPLAT_LAYERS - 1)];
+
+    layer(a) * (1.0 - blend) + layer(b) * blend
+}
+
+pub type SplatMaterial = ExtendedMaterial<StandardMaterial, SplatExtension>;
+
+/// Replaces the base color of the [`StandardMaterial`] it extends with the
+/// blended layers, tinted by the base color.
+#[derive(Asset, AsBindGroup, Reflect, Clone, Debug)]
+pub struct SplatExtension {
+    #[uniform(100)]
+    pub settings: SplatSettings,
+    /// One layer per material, with the albedo in RGB and the height the
+    /// layers are blended by in alpha. A vertically stacked image can be
+    /// made into one with [`Image::reinterpret_stacked_2d_as_array`].
+    #[texture(101, dimension = "2d_array")]
+    #[sampler(102)]
+    pub layers: Handle<Image>,
+}
+
+#[derive(ShaderType, Reflect, Clone, Copy, Debug, PartialEq)]
+pub struct SplatSettings {
+    /// Texture repeats per world unit.
+    pub scale: f32,
+    /// How sharply the triplanar projections are blended where the surface
+    /// turns, higher values give narrower seams.
+    pub sharpness: f32,
+    /// How far below the highest layer the others still show through. Low
+    /// values make the higher layer cover the others, as if the blend
+    /// followed the cracks between stones.
+    pub blend_depth: f32,
+}
+
+impl Default for SplatSettings {
+    fn default() -> Self {
+        Self {
+            scale: 0.5,
+            sharpness: 4.0,
+            blend_depth: 0.2,
+        }
+    }
+}
+
+impl MaterialExtension for SplatExtension {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/splat.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/splat.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // the prepasses keep the shaders and vertex layout of the base
+        if descriptor
+            .vertex
+            .shader_defs
+            .contains(&"PREPASS_PIPELINE".into())
+        {
+            return Ok(());
+        }
+
+        // the same locations as the base material, which the standard
+        // vertex outputs are built from
+        let mut attributes = vec![
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+        ];
+        if layout.0.contains(Mesh::ATTRIBUTE_UV_0) {
+            attributes.push(Mesh::ATTRIBUTE_UV_0.at_shader_location(2));
+        }
+        if layout.0.contains(Mesh::ATTRIBUTE_TANGENT) {
+            attributes.push(Mesh::ATTRIBUTE_TANGENT.at_shader_location(4));
+        }
+
+        // meshes without materials, like empty chunks, are all the first layer
+        if layout.0.contains(ATTRIBUTE_SPLAT_WEIGHTS) {
+            attributes.push(ATTRIBUTE_SPLAT_WEIGHTS.at_shader_location(8));
+            descriptor.vertex.shader_defs.push("SPLAT_WEIGHTS".into());
+        }
+
+        descriptor.vertex.buffers = vec![layout.0.get_layout(&attributes)?];
+        Ok(())
+    }
+}
+
+/// The layers every [`SplatMaterial`] is made with until artists provide
+/// their own.
+#[derive(Resource, Deref)]
+pub struct SplatLayers(pub Handle<Image>);
+
+impl FromWorld for SplatLayers {
+    fn from_world(world: &mut World) -> Self {
+        let image = placeholder_layers();
+        Self(world.resource_mut::<Assets<Image>>().add(image))
+    }
+}
+
+impl SplatLayers {
+    /// A material with these layers and the default settings.
+    pub fn material(&self, base: StandardMaterial) -> SplatMaterial {
+        SplatMaterial {
+            base,
+            extension: SplatExtension {
+                settings: SplatSettings::default(),
+                layers: self.0.clone(),
+            },
+        }
+    }
+}
+
+/// Flat colors with bumps of a different size on each layer, so the height
+/// blending shows.
+fn placeholder_layers() -> Image {
+    const SIZE: u32 = 64;
+    let colors = [
+        Color::srgb(0.45, 0.55, 0.35),
+        Color::srgb(0.5, 0.38, 0.25),
+        Color::srgb(0.55, 0.55, 0.55),
+        Color::srgb(0.85, 0.78, 0.55),
+    ];
+
+    let mut data = Vec::with_capacity((SIZE * SIZE) as usize * SPLAT_LAYERS * 4);
+    for (layer, color) in colors.into_iter().enumerate() {
+        let bumps = (layer + 2) as f32;
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let [u, v] = [x, y].map(|i| (i as f32 / SIZE as f32 * bumps * TAU).sin());
+                let height = 0.5 + 0.25 * (u + v);
+
+                let shade = color.to_srgba() * (0.7 + 0.3 * height);
+                let [r, g, b, _] = shade.to_u8_array();
+                data.extend_from_slice(&[r, g, b, (height * 255.0) as u8]);
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: SIZE,
+            height: SIZE,
+            depth_or_array_layers: SPLAT_LAYERS as u32,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Linear,
+        min_filter: ImageFilterMode::Linear,
+        ..default()
+    });
+
+    image
+}
+
+pub fn splat_plugin(app: &mut App) {
+    app.add_plugins(MaterialPlugin::<SplatMaterial>::default())
+        .init_resource::<SplatLayers>();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mesh::pack_material,
+        test::{ShaderComposer, TestGpu},
+    };
+
+    use super::*;
+
+    #[test]
+    fn weights_follow_the_blend() {
+        assert_eq!(splat_weights(pack_material(2, 2, 0.3)), Vec4::Z);
+        assert_eq!(splat_weights(pack_material(0, 1, 0.0)), Vec4::X);
+        assert_eq!(splat_weights(pack_material(0, 1, 1.0)), Vec4::Y);
+
+        let weights = splat_weights(pack_material(1, 200, 0.5));
+        assert!((weights.element_sum() - 1.0).abs() < 1e-6);
+        assert!(weights.y > 0.49 && weights.w > 0.49);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn gpu_weights_agree() {
+        let gpu = TestGpu::new();
+
+        let materials: Vec<u32> = (0..6u8)
+            .flat_map(|a| (0..6u8).map(move |b| (a, b)))
+            .flat_map(|(a, b)| [0.0, 0.3, 1.0].map(|t| pack_material(a, b, t)))
+            .collect();
+
+        let source = ShaderComposer::new()
+            .add_asset("shaders/splat_blend.wgsl")
+            .compose_source(
+                "
+#import \"shaders/splat_blend.wgsl\"::splat_weights
+
+@group(0) @binding(0) var<storage, read> materials: array<u32>;
+@group(0) @binding(1) var<storage, read_write> weights: array<vec4<f32>>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < arrayLength(&weights) {
+        weights[id.x] = splat_weights(materials[id.x]);
+    }
+}
+",
+                "weights_test.wgsl",
+                default(),
+            );
+
+        let input = gpu.buffer(bytemuck::cast_slice(&materials));
+        let output = gpu.buffer(&vec![0; materials.len() * size_of::<Vec4>()]);
+
+        gpu.dispatch(
+            source,
+            "main",
+            &[&[
+                (0, input.as_entire_binding()),
+                (1, output.as_entire_binding()),
+            ]],
+            UVec3::new(materials.len().div_ceil(64) as u32, 1, 1),
+        );
+
+        let weights: Vec<Vec4> = bytemuck::cast_slice(&gpu.read_buffer(&output)).to_vec();
+
+        for (&material, gpu) in materials.iter().zip(weights) {
+            let cpu = splat_weights(material);
+            assert!(
+                cpu.distance(gpu) < 1e-6,
+                "{:?}: {cpu} on the CPU, {gpu} on the GPU",
+                unpack_material(material)
+            );
+        }
+    }
+}
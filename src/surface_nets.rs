@@ -0,0 +1,173 @@
+//! Surface Nets, sharing the mesh structure of [`contour`] but placing each
+//! vertex at the average of its cell's edge crossings instead of solving a
+//! QEF. Mirrors the `SURFACE_NETS` variant of `adaptivity.wgsl` and the
+//! relaxation in `surface_nets.wgsl`.
+
+use arrayvec::ArrayVec;
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use crate::{
+    dual_contouring::{contour, inside, CellVertices},
+    mesh::ContourMesh,
+    DensityMap,
+};
+
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, Default, PartialEq)]
+pub struct SurfaceNetsSettings {
+    /// How many times every vertex is moved to the average of its
+    /// neighbours, staying inside its cell. 0 gives Naive Surface Nets,
+    /// anything more Smooth Surface Nets.
+    pub relax_iterations: u32,
+}
+
+const AXES: [UVec3; 3] = [UVec3::X, UVec3::Y, UVec3::Z];
+
+/// Surface nets `map`, with the same vertices and quads as
+/// [`dual_contour`](crate::dual_contouring::dual_contour) apart from the
+/// vertex positions.
+pub fn surface_nets(
+    map: &DensityMap,
+    gradient: impl Fn(UVec3) -> Vec3,
+    settings: &SurfaceNetsSettings,
+) -> ContourMesh {
+    let mut cells = Vec::new();
+
+    // the relaxation assumes one vertex per cell
+    let mut mesh = contour(
+        map,
+        gradient,
+        CellVertices::One,
+        UVec3::ZERO..map.size(),
+        |cell, crossings| {
+            cells.push(cell);
+
+            crossings.iter().map(|crossing| crossing.pos).sum::<Vec3>() / crossings.len() as f32
+        },
+    );
+
+    relax(map, &mut mesh.positions, &cells, settings.relax_iterations);
+
+    mesh
+}
+
+/// Moves every vertex to the average of the vertices it's linked to, the
+/// ones in the neighbouring cells it shares a crossed face with. Vertices
+/// are clamped to their own cell, so the mesh never strays more than a
+/// cell from the surface.
+fn relax(map: &DensityMap, positions: &mut [Vec3], cells: &[UVec3], iterations: u32) {
+    if iterations == 0 {
+        return;
+    }
+
+    let size = map.size();
+    let cell_index = |cell: UVec3| (cell.x + size.x * (cell.y + size.y * cell.z)) as usize;
+
+    let mut index_lookup = vec![u32::MAX; (size.x * size.y * size.z) as usize];
+    for (vtx, &cell) in cells.iter().enumerate() {
+        index_lookup[cell_index(cell)] = vtx as u32;
+    }
+
+    let links: Vec<ArrayVec<u32, 6>> = cells
+        .iter()
+        .map(|&cell| {
+            let mut links = ArrayVec::new();
+
+            for (axis, &dir) in AXES.iter().enumerate() {
+                for side in [0, 1] {
+                    let neighbour = cell.as_ivec3() + dir.as_ivec3() * (side * 2 - 1);
+                    if neighbour.cmplt(IVec3::ZERO).any() || neighbour.cmpge(size.as_ivec3()).any()
+                    {
+                        continue;
+                    }
+
+                    if face_is_crossed(map, cell + dir * side as u32, axis) {
+                        links.push(index_lookup[cell_index(neighbour.as_uvec3())]);
+                    }
+                }
+            }
+
+            links
+        })
+        .collect();
+
+    let mut relaxed = positions.to_vec();
+
+    for _ in 0..iterations {
+        for (vtx, links) in links.iter().enumerate() {
+            if links.is_empty() {
+                continue;
+            }
+
+            let average = links
+                .iter()
+                .map(|&link| positions[link as usize])
+                .sum::<Vec3>()
+                / links.len() as f32;
+
+            let min = cells[vtx].as_vec3();
+            relaxed[vtx] = average.clamp(min, min + Vec3::ONE);
+        }
+
+        positions.copy_from_slice(&relaxed);
+    }
+}
+
+/// Whether the surface passes through the face perpendicular to `axis`
+/// with its lowest corner at `origin`.
+fn face_is_crossed(map: &DensityMap, origin: UVec3, axis: usize) -> bool {
+    let (u, v) = (AXES[(axis + 1) % 3], AXES[(axis + 2) % 3]);
+    let first = inside(map[origin]);
+
+    [origin + u, origin + v, origin + u + v]
+        .into_iter()
+        .any(|corner| inside(map[corner]) != first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_contouring::cell_crossings;
+
+    fn sphere(size: u32, radius: f32) -> DensityMap {
+        let mut map = DensityMap::new(UVec3::splat(size));
+        let center = Vec3::splat(size as f32 / 2.0);
+
+        for pos in crate::all_cells(map.grid_size()) {
+            map[pos] = pos.as_vec3().distance(center) - radius;
+        }
+
+        map
+    }
+
+    #[test]
+    fn relaxed_vertices_stay_in_their_cells() {
+        let map = sphere(8, 2.7);
+        let naive = surface_nets(
+            &map,
+            |pos| map.gradient(pos),
+            &SurfaceNetsSettings::default(),
+        );
+        let smooth = surface_nets(
+            &map,
+            |pos| map.gradient(pos),
+            &SurfaceNetsSettings {
+                relax_iterations: 4,
+            },
+        );
+
+        assert_eq!(naive.indices, smooth.indices);
+        assert_ne!(naive.positions, smooth.positions);
+
+        let cells = crate::all_cells(map.size())
+            .into_iter()
+            .filter(|&cell| !cell_crossings(&map, cell, |pos| map.gradient(pos)).is_empty());
+
+        for (cell, pos) in cells.zip(&smooth.positions) {
+            let min = cell.as_vec3();
+            assert!(
+                pos.cmpge(min).all() && pos.cmple(min + Vec3::ONE).all(),
+                "{pos} left {cell}"
+            );
+        }
+    }
+}
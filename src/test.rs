@@ -0,0 +1,309 @@
+//! Helpers shared by the tests: seeded noise, and a headless GPU for the
+//! tests comparing shaders with the CPU code they mirror. Those are
+//! `#[ignore]`d, as most machines running the tests have no adapter, and
+//! fail rather than skip when run with `cargo test -- --ignored` without
+//! one.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use bevy::{math::UVec3, tasks::block_on};
+use naga_oil::compose::{
+    ComposableModuleDescriptor, Composer, NagaModuleDescriptor, ShaderDefValue, ShaderLanguage,
+    ShaderType,
+};
+use wgpu::util::DeviceExt;
+
+pub struct TestGpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    backend: wgpu::Backend,
+}
+
+impl TestGpu {
+    /// The default adapter's device. Panics on machines without one.
+    pub fn new() -> Self {
+        let instance = wgpu::Instance::default();
+        let adapter = block_on(instance.request_adapter(&default()))
+            .expect("no GPU adapter, which the ignored tests need");
+
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                // like the app's, which needs the read-write storage textures
+                required_features: adapter.features(),
+                required_limits: adapter.limits(),
+                ..default()
+            },
+            None,
+        ))
+        .expect("the adapter has no device");
+
+        Self {
+            device,
+            queue,
+            backend: adapter.get_info().backend,
+        }
+    }
+
+    /// Panics unless 3d storage textures can be written and read back. The
+    /// GL backend only binds their first layer, and loses the sign of snorm
+    /// formats on some drivers.
+    pub fn require_3d_storage_textures(&self) {
+        assert_ne!(
+            self.backend,
+            wgpu::Backend::Gl,
+            "no 3d storage textures on GL"
+        );
+    }
+
+    /// A storage buffer holding `contents`, which can be read back.
+    pub fn buffer(&self, contents: &[u8]) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            })
+    }
+
+    /// Runs `entry_point` of `source` once, with bind group `i` made of the
+    /// bindings in `groups[i]`.
+    pub fn dispatch(
+        &self,
+        source: wgpu::ShaderSource,
+        entry_point: &str,
+        groups: &[&[(u32, wgpu::BindingResource)]],
+        workgroups: UVec3,
+    ) {
+        let module = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source,
+            });
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: default(),
+                cache: None,
+            });
+
+        let bind_groups: Vec<_> = groups
+            .iter()
+            .enumerate()
+            .map(|(i, bindings)| {
+                let entries: Vec<_> = bindings
+                    .iter()
+                    .map(|(binding, resource)| wgpu::BindGroupEntry {
+                        binding: *binding,
+                        resource: resource.clone(),
+                    })
+                    .collect();
+
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &pipeline.get_bind_group_layout(i as u32),
+                    entries: &entries,
+                })
+            })
+            .collect();
+
+        let mut encoder = self.device.create_command_encoder(&default());
+        {
+            let mut pass = encoder.begin_compute_pass(&default());
+            pass.set_pipeline(&pipeline);
+            for (i, group) in bind_groups.iter().enumerate() {
+                pass.set_bind_group(i as u32, group, &[]);
+            }
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+        self.queue.submit([encoder.finish()]);
+    }
+
+    /// A 3d storage texture of `size` texels, which can be read back.
+    pub fn texture_3d(&self, size: UVec3, format: wgpu::TextureFormat) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    /// The texels of `texture`, tightly packed in x, y, z order.
+    pub fn read_texture(&self, texture: &wgpu::Texture) -> Vec<u8> {
+        let texel_size = texture.format().block_copy_size(None).unwrap();
+        let row_size = texture.width() * texel_size;
+        let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let rows = texture.height() * texture.depth_or_array_layers();
+
+        let staging = self.staging((padded_row_size * rows) as u64);
+
+        let mut encoder = self.device.create_command_encoder(&default());
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: Some(texture.height()),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        self.map(&staging)
+            .chunks(padded_row_size as usize)
+            .flat_map(|row| &row[..row_size as usize])
+            .copied()
+            .collect()
+    }
+
+    pub fn read_buffer(&self, buffer: &wgpu::Buffer) -> Vec<u8> {
+        let staging = self.staging(buffer.size());
+
+        let mut encoder = self.device.create_command_encoder(&default());
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+        self.queue.submit([encoder.finish()]);
+
+        self.map(&staging)
+    }
+
+    fn staging(&self, size: u64) -> wgpu::Buffer {
+        self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn map(&self, buffer: &wgpu::Buffer) -> Vec<u8> {
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let bytes = slice.get_mapped_range().to_vec();
+        buffer.unmap();
+        bytes
+    }
+}
+
+/// Composes `assets/<path>` the way the app's shader pipeline would, so the
+/// tests run the shaders themselves rather than copies of them.
+pub struct ShaderComposer(Composer);
+
+impl ShaderComposer {
+    pub fn new() -> Self {
+        Self(Composer::default())
+    }
+
+    /// Makes `assets/<path>` importable by its asset path. Has to be called
+    /// after the modules it imports are added.
+    pub fn add_asset(&mut self, path: &str) -> &mut Self {
+        self.add(&read_asset(path), path, Some(format!("\"{path}\"")))
+    }
+
+    /// Adds a module declaring its own `#define_import_path`, like the
+    /// generated ones.
+    pub fn add_module(&mut self, source: &str) -> &mut Self {
+        self.add(source, "generated", None)
+    }
+
+    fn add(&mut self, source: &str, file_path: &str, as_name: Option<String>) -> &mut Self {
+        let result = self.0.add_composable_module(ComposableModuleDescriptor {
+            source,
+            file_path,
+            language: ShaderLanguage::Wgsl,
+            as_name,
+            ..default()
+        });
+        if let Err(err) = result {
+            panic!("{}", err.emit_to_string(&self.0));
+        }
+        self
+    }
+
+    /// `assets/<path>` with its imports resolved. `shader_defs` are the
+    /// ones its pipeline is specialized with.
+    pub fn compose(
+        &mut self,
+        path: &str,
+        shader_defs: HashMap<String, ShaderDefValue>,
+    ) -> wgpu::ShaderSource<'static> {
+        self.compose_source(&read_asset(path), path, shader_defs)
+    }
+
+    /// Like [`ShaderComposer::compose`], for a test kernel importing the
+    /// shaders under test.
+    pub fn compose_source(
+        &mut self,
+        source: &str,
+        file_path: &str,
+        shader_defs: HashMap<String, ShaderDefValue>,
+    ) -> wgpu::ShaderSource<'static> {
+        let module = self
+            .0
+            .make_naga_module(NagaModuleDescriptor {
+                source,
+                file_path,
+                shader_type: ShaderType::Wgsl,
+                shader_defs,
+                ..default()
+            })
+            .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&self.0)));
+
+        wgpu::ShaderSource::Naga(Cow::Owned(module))
+    }
+}
+
+/// Xorshift, noisy enough to hit every ambiguous configuration and the
+/// same on every run.
+pub struct Xorshift(u32);
+
+impl Xorshift {
+    /// Seeds must be nonzero.
+    pub fn new(seed: u32) -> Self {
+        assert_ne!(seed, 0, "xorshift is stuck at zero");
+        Self(seed)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// A density in `-1..=1`, in steps of a thousandth.
+    pub fn density(&mut self) -> f32 {
+        (self.next_u32() % 2001) as f32 / 1000.0 - 1.0
+    }
+
+    /// A magnitude in `0.001..=1`, never zero so it keeps a sign.
+    pub fn magnitude(&mut self) -> f32 {
+        (self.next_u32() % 1000 + 1) as f32 / 1000.0
+    }
+}
+
+fn read_asset(path: &str) -> String {
+    let path = format!("{}/assets/{path}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read_to_string(&path).unwrap_or_else(|err| panic!("{path}: {err}"))
+}
+
+fn default<T: Default>() -> T {
+    T::default()
+}
@@ -0,0 +1,180 @@
+//! UVs and tangents for contoured meshes, which have no parametrization of
+//! their own. Mirrored in `uv.wgsl`, so keep the two in sync.
+
+use std::f32::consts::{PI, TAU};
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+/// How [`UvSettings`] maps positions to UVs. The discriminants are the
+/// `mode` values `uv.wgsl` switches on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UvMode {
+    /// A planar projection along whichever axis the normal is closest to,
+    /// so no side of the mesh gets stretched. The UVs jump where the
+    /// closest axis changes.
+    #[default]
+    Box = 0,
+    /// Wrapped around the vertical axis through [`UvSettings::center`],
+    /// `u` going around it once and `v` running down it.
+    Cylindrical = 1,
+    /// Longitude and latitude around [`UvSettings::center`], `u` going
+    /// around once and `v` from the top pole to the bottom one.
+    Spherical = 2,
+}
+
+impl UvMode {
+    pub const ALL: [UvMode; 3] = [UvMode::Box, UvMode::Cylindrical, UvMode::Spherical];
+}
+
+/// How UVs and tangents are generated, used by both the CPU meshers and
+/// `uv.wgsl`.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, PartialEq)]
+pub struct UvSettings {
+    pub mode: UvMode,
+    /// UV units per world unit, along the box projections and up the
+    /// cylinder. Going around the cylinder or sphere is always one unit.
+    pub scale: f32,
+    /// Where the projections are centered.
+    pub center: Vec3,
+}
+
+impl Default for UvSettings {
+    fn default() -> Self {
+        Self {
+            mode: UvMode::default(),
+            scale: 1.0,
+            center: Vec3::ZERO,
+        }
+    }
+}
+
+/// The UV of a vertex at `pos` with `normal`, both in world space, and its
+/// tangent in the layout of [`Mesh::ATTRIBUTE_TANGENT`].
+pub fn uv_tangent(settings: &UvSettings, pos: Vec3, normal: Vec3) -> (Vec2, Vec4) {
+    let offset = pos - settings.center;
+    let around = Vec3::new(-offset.z, 0.0, offset.x);
+
+    // the UV and the directions its u and v grow in
+    let (uv, du, dv) = match settings.mode {
+        UvMode::Box => {
+            let (du, dv) = box_axes(normal);
+            let uv = Vec2::new(offset.dot(du), offset.dot(dv)) * settings.scale;
+            (uv, du, dv)
+        }
+        UvMode::Cylindrical => {
+            let uv = Vec2::new(turn(offset), -offset.y * settings.scale);
+            (uv, around, Vec3::NEG_Y)
+        }
+        UvMode::Spherical => {
+            let latitude = offset.normalize_or_zero().y.clamp(-1.0, 1.0).acos() / PI;
+            let down = Vec3::new(
+                offset.x * offset.y,
+                -(offset.x * offset.x + offset.z * offset.z),
+                offset.z * offset.y,
+            );
+            (Vec2::new(turn(offset), latitude), around, down)
+        }
+    };
+
+    (uv, tangent(normal, du, dv))
+}
+
+/// The directions of `u` and `v` in the plane the box projection picks for
+/// `normal`. `v` points down on the sides so textures stay upright, and `u`
+/// is mirrored on the negative sides so they aren't flipped.
+fn box_axes(normal: Vec3) -> (Vec3, Vec3) {
+    let abs = normal.abs();
+    let sign = |x: f32| if x < 0.0 { -1.0 } else { 1.0 };
+
+    if abs.x >= abs.y && abs.x >= abs.z {
+        (Vec3::new(0.0, 0.0, -sign(normal.x)), Vec3::NEG_Y)
+    } else if abs.y >= abs.z {
+        (Vec3::X, Vec3::new(0.0, 0.0, sign(normal.y)))
+    } else {
+        (Vec3::new(sign(normal.z), 0.0, 0.0), Vec3::NEG_Y)
+    }
+}
+
+/// How far around the vertical axis `offset` is, from 0 to 1.
+fn turn(offset: Vec3) -> f32 {
+    offset.z.atan2(offset.x) / TAU + 0.5
+}
+
+/// `du` made perpendicular to `normal`, with the sign in `w` that makes the
+/// bitangent `normal × tangent * w` follow `dv`.
+fn tangent(normal: Vec3, du: Vec3, dv: Vec3) -> Vec4 {
+    // du is along the normal at the poles of the cylinder and sphere, any
+    // tangent will do there
+    let tangent = (du - normal * normal.dot(du))
+        .try_normalize()
+        .or_else(|| normal.try_normalize().map(|n| n.any_orthonormal_vector()))
+        .unwrap_or(Vec3::X);
+
+    let w = if normal.cross(tangent).dot(dv) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+
+    tangent.extend(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tangents_are_perpendicular_and_follow_the_uvs() {
+        let offsets = [
+            Vec3::new(2.0, 1.0, -1.5),
+            Vec3::new(-0.5, -3.0, 0.2),
+            Vec3::new(0.1, 0.4, 2.5),
+        ];
+
+        for mode in UvMode::ALL {
+            let settings = UvSettings {
+                mode,
+                scale: 2.0,
+                center: Vec3::new(0.5, -1.0, 0.25),
+            };
+
+            for offset in offsets {
+                // the surfaces the projections are meant for
+                let normal = match mode {
+                    UvMode::Box => (offset * Vec3::new(1.0, -2.0, 0.5)).normalize(),
+                    UvMode::Cylindrical => offset.with_y(0.0).normalize(),
+                    UvMode::Spherical => offset.normalize(),
+                };
+
+                let pos = settings.center + offset;
+                let (uv, tangent) = uv_tangent(&settings, pos, normal);
+
+                assert!(tangent.xyz().is_normalized());
+                assert!(tangent.xyz().dot(normal).abs() < 1e-5);
+
+                // a small step along the tangent moves u forward, and one
+                // along the bitangent moves v forward
+                let bitangent = normal.cross(tangent.xyz()) * tangent.w;
+                let step = 1e-3;
+                let (along_u, _) = uv_tangent(&settings, pos + tangent.xyz() * step, normal);
+                let (along_v, _) = uv_tangent(&settings, pos + bitangent * step, normal);
+
+                assert!(along_u.x > uv.x, "{mode:?} {offset}");
+                assert!(along_v.y > uv.y, "{mode:?} {offset}");
+            }
+        }
+    }
+
+    #[test]
+    fn box_projection_keeps_the_sides_upright() {
+        let settings = UvSettings::default();
+
+        for normal in [Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z] {
+            let (low, _) = uv_tangent(&settings, Vec3::ZERO, normal);
+            let (high, _) = uv_tangent(&settings, Vec3::Y, normal);
+
+            assert_eq!(high.x, low.x);
+            assert!(high.y < low.y);
+        }
+    }
+}